@@ -0,0 +1,25 @@
+#![no_main]
+
+use icrc7::ApproveArgs;
+use icrc7_fuzz::*;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let Some((selector, data)) = data.split_first() else {
+        return;
+    };
+
+    let Ok(args) = candid::decode_one::<ApproveArgs>(data) else {
+        return;
+    };
+
+    let mut c = collection();
+    let _ = icrc7::approve(&mut c, caller(*selector), NOW, args);
+
+    // approvals index must stay consistent
+    for (key, ids) in &c.approvals_by_account {
+        for id in ids {
+            assert_eq!(&c.approvals[id].key(), key);
+        }
+    }
+});
@@ -0,0 +1,20 @@
+#![no_main]
+
+use icrc7::MintTokenArgs;
+use icrc7_fuzz::*;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let Some((selector, data)) = data.split_first() else {
+        return;
+    };
+
+    let Ok(args) = candid::decode_one::<MintTokenArgs>(data) else {
+        return;
+    };
+
+    let mut c = collection();
+    let _ = icrc7::mint(&mut c, caller(*selector), NOW, args);
+
+    assert!(c.tokens.len() <= c.supply_cap.unwrap_or(usize::MAX));
+});
@@ -0,0 +1,28 @@
+#![no_main]
+
+use icrc7::TransferArgs;
+use icrc7_fuzz::*;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let Some((selector, data)) = data.split_first() else {
+        return;
+    };
+
+    let Ok(args) = candid::decode_one::<TransferArgs>(data) else {
+        return;
+    };
+
+    let mut c = collection();
+    let before = c.tokens.clone();
+
+    let atomic = args.is_atomic.unwrap_or(true);
+    let res = icrc7::transfer(&mut c, caller(*selector), NOW, args);
+
+    if res.is_err() && atomic {
+        // failed atomic transfer must not change ownership
+        for (id, token) in &before {
+            assert_eq!(c.tokens[id].owner, token.owner);
+        }
+    }
+});
@@ -0,0 +1,51 @@
+use ic_kit::prelude::*;
+use icrc7::state::*;
+
+pub const NOW: u64 = 10 * TX_DEDUPLICATION_WINDOW;
+
+pub fn authority() -> Principal {
+    Principal::from_slice(&[0xff])
+}
+
+/// picks caller for the fuzzed call, so both owners and strangers get exercised
+pub fn caller(selector: u8) -> Principal {
+    match selector % 4 {
+        0 => authority(),
+        1 => Principal::anonymous(),
+        n => Principal::from_slice(&[n]),
+    }
+}
+
+/// collection with a few tokens and approvals spread across accounts
+pub fn collection() -> Collection {
+    let mut c = Collection {
+        authority: Some(authority()),
+        supply_cap: Some(16),
+        ..Default::default()
+    };
+
+    for id in 0..8u8 {
+        let subaccount = if id % 2 == 0 { None } else { Some([1; 32]) };
+        c.add_token(Token {
+            id: id.into(),
+            name: format!("NFT-{}", id),
+            image: vec![],
+            owner: Account::new(caller(2 + id % 2), subaccount).to_canonical(),
+            transferable: id != 7,
+            locked_until: (id == 5).then_some(NOW + 1),
+            royalties: None,
+            reveal_hash: None,
+        });
+    }
+
+    c.add_approval(Approval {
+        from: caller(2),
+        from_subaccount: None,
+        to: caller(3),
+        token_ids: None,
+        expires_at: None,
+        memo: None,
+    });
+
+    c
+}
@@ -0,0 +1,457 @@
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+
+use ic_kit::prelude::*;
+
+use crate::maintenance::instruction_counter;
+use crate::state::*;
+use crate::update::*;
+
+pub type AirdropID = u64;
+
+/// airdrop step stops taking new recipients once the message has executed this many
+/// instructions, well below the limit of update and heartbeat messages
+pub const AIRDROP_INSTRUCTIONS: u64 = 4_000_000_000;
+
+/// metadata of the token minted by airdrop
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, CandidType)]
+pub struct AirdropMetadata {
+    pub name: String,
+    /// base64 encoded token image
+    pub image: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, CandidType)]
+pub struct AirdropEntry {
+    pub to: Account,
+    /// token owned by authority is transferred if it exists, new one is minted otherwise,
+    /// next free ID is used if not set
+    pub token_id: Option<TokenID>,
+    /// required to mint, ignored for transfers
+    pub metadata: Option<AirdropMetadata>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, CandidType)]
+pub enum AirdropStatus {
+    Pending,
+    Minted {
+        token_id: TokenID,
+    },
+    Transferred {
+        token_id: TokenID,
+        transfer_id: TransferID,
+    },
+    Failed {
+        message: String,
+    },
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, CandidType)]
+pub struct AirdropJob {
+    pub created_at: u64,
+    pub finished_at: Option<u64>,
+    /// entries still to be processed, emptied once the job is finished
+    pub entries: Vec<AirdropEntry>,
+    /// status of every entry, in the same order, kept after the job is finished
+    pub statuses: Vec<AirdropStatus>,
+    /// index of the first entry not processed yet
+    pub cursor: usize,
+}
+
+/// progress of the airdrop, returned by "airdrop_status"
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, CandidType)]
+pub struct AirdropReport {
+    pub created_at: u64,
+    pub finished_at: Option<u64>,
+    pub statuses: Vec<AirdropStatus>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize, CandidType)]
+pub struct Airdrops {
+    pub jobs: BTreeMap<AirdropID, AirdropJob>,
+    /// jobs not finished yet, so heartbeat does not have to scan finished ones
+    pub pending: BTreeSet<AirdropID>,
+    pub next_id: AirdropID,
+    /// next candidate for the ID of minted token without one
+    pub next_token_id: TokenID,
+}
+
+impl Collection {
+    /// registers airdrop job on behalf of `caller`, nothing is processed until the first step
+    pub fn create_airdrop(
+        &mut self,
+        caller: &Principal,
+        now: u64,
+        entries: Vec<AirdropEntry>,
+    ) -> Result<AirdropID, String> {
+        self.check_authority(caller)?;
+
+        if entries.is_empty() {
+            return Err("no airdrop recipients".to_owned());
+        }
+
+        self.limits.check_airdrop(entries.len())?;
+
+        let id = self.airdrops.next_id;
+        self.airdrops.next_id += 1;
+
+        self.airdrops.jobs.insert(
+            id,
+            AirdropJob {
+                created_at: now,
+                finished_at: None,
+                statuses: vec![AirdropStatus::Pending; entries.len()],
+                entries,
+                cursor: 0,
+            },
+        );
+        self.airdrops.pending.insert(id);
+
+        Ok(id)
+    }
+
+    /// processes entries of the job until `has_budget` returns false, it is asked after every
+    /// entry, so each step makes progress, returns true once the job is finished
+    pub fn airdrop_step<F>(
+        &mut self,
+        id: AirdropID,
+        now: u64,
+        mut has_budget: F,
+    ) -> Result<bool, String>
+    where
+        F: FnMut() -> bool,
+    {
+        let (start, len) = match self.airdrops.jobs.get(&id) {
+            Some(job) if job.finished_at.is_some() => return Ok(true),
+            Some(job) => (job.cursor, job.statuses.len()),
+            None => return Err(format!("airdrop {} does not exist", id)),
+        };
+
+        let mut end = start;
+        while end < len {
+            let entry = self.airdrops.jobs[&id].entries[end].clone();
+            let status = match self.airdrop_to(entry, now) {
+                Ok(status) => status,
+                Err(message) => AirdropStatus::Failed { message },
+            };
+            self.airdrops.jobs.get_mut(&id).unwrap().statuses[end] = status;
+            end += 1;
+
+            if !has_budget() {
+                break;
+            }
+        }
+
+        let job = self.airdrops.jobs.get_mut(&id).unwrap();
+        job.cursor = end;
+        if end == job.statuses.len() {
+            job.finished_at = Some(now);
+            job.entries = Vec::new();
+            self.airdrops.pending.remove(&id);
+        }
+
+        Ok(job.finished_at.is_some())
+    }
+
+    fn airdrop_to(&mut self, entry: AirdropEntry, now: u64) -> Result<AirdropStatus, String> {
+        if self.denylist.contains(&entry.to) {
+            return Err("account is denylisted".to_owned());
+        }
+
+        if let Some(ref id) = entry.token_id {
+            if self.tokens.contains_key(id) {
+                return self.airdrop_transfer(id, entry.to, now);
+            }
+        }
+
+        if self.pause.state.minting {
+            return Err("minting is paused".to_owned());
+        }
+
+        let metadata = match entry.metadata {
+            Some(metadata) => metadata,
+            None => return Err("metadata is required to mint".to_owned()),
+        };
+
+        let id = match entry.token_id {
+            Some(id) => id,
+            None => {
+                let id = self.next_free_id(&self.airdrops.next_token_id);
+                let mut next_id = id.clone();
+                next_id += 1;
+                self.airdrops.next_token_id = next_id;
+                id
+            }
+        };
+
+        let token_id = create_token(
+            self,
+            now,
+            MintTokenArgs {
+                id,
+                name: metadata.name,
+                image: metadata.image,
+                owner: entry.to,
+                transferable: None,
+                locked_until: None,
+                royalties: None,
+                reveal_hash: None,
+            },
+        )?;
+
+        Ok(AirdropStatus::Minted { token_id })
+    }
+
+    /// moves existing token from authority account to `to`
+    fn airdrop_transfer(
+        &mut self,
+        token_id: &TokenID,
+        to: Account,
+        now: u64,
+    ) -> Result<AirdropStatus, String> {
+        if self.pause.state.transfers {
+            return Err("transfers are paused".to_owned());
+        }
+
+        let from = Account::from_owner(self.authority.unwrap_or(Principal::anonymous()));
+        let token = &self.tokens[token_id];
+
+        if token.owner != from {
+            return Err(format!(
+                "token with id {} is not owned by authority",
+                token_id
+            ));
+        }
+
+        if !self.is_transferable(token) {
+            return Err(format!("token with id {} is not transferable", token_id));
+        }
+
+        if self.in_escrow(token_id) {
+            return Err(format!("token with id {} is listed for sale", token_id));
+        }
+
+        if let Some(until) = token.locked_at(now) {
+            return Err(format!(
+                "token with id {} is locked until {}",
+                token_id, until
+            ));
+        }
+
+        let to = to.to_canonical();
+        self.tokens.get_mut(token_id).unwrap().owner = to.clone();
+
+        let transfer_id = self.add_transfer(
+            Transfer {
+                from,
+                to,
+                token_ids: HashSet::from([token_id.clone()]),
+                created_at: now,
+                ..Default::default()
+            },
+            vec![token_id.clone()],
+            now,
+        );
+
+        Ok(AirdropStatus::Transferred {
+            token_id: token_id.clone(),
+            transfer_id,
+        })
+    }
+
+    /// called on every heartbeat, advances the oldest unfinished airdrop by a single step
+    pub fn airdrop_tick(&mut self, now: u64) {
+        if let Some(&id) = self.airdrops.pending.first() {
+            let _ = self.airdrop_step(id, now, within_instruction_budget);
+        }
+    }
+}
+
+/// message has not yet executed `AIRDROP_INSTRUCTIONS` instructions
+fn within_instruction_budget() -> bool {
+    instruction_counter() < AIRDROP_INSTRUCTIONS
+}
+
+/// mints or transfers tokens to many recipients, the first batch is processed right away,
+/// the rest is processed from heartbeat or by calling "resume_airdrop" with returned ID
+#[update]
+pub fn airdrop(c: &mut Collection, entries: Vec<AirdropEntry>) -> Result<AirdropID, String> {
+    let id = c.create_airdrop(&caller(), ic::time(), entries)?;
+    c.airdrop_step(id, ic::time(), within_instruction_budget)?;
+    Ok(id)
+}
+
+/// processes next batch of the airdrop, returns true once all recipients are processed
+#[update]
+pub fn resume_airdrop(c: &mut Collection, id: AirdropID) -> Result<bool, String> {
+    c.check_authority(&caller())?;
+    c.airdrop_step(id, ic::time(), within_instruction_budget)
+}
+
+#[query]
+fn airdrop_status(c: &Collection, id: AirdropID) -> Option<AirdropReport> {
+    c.airdrops.jobs.get(&id).map(|job| AirdropReport {
+        created_at: job.created_at,
+        finished_at: job.finished_at,
+        statuses: job.statuses.clone(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// budget that allows `n` entries per step
+    fn entries(n: usize) -> impl FnMut() -> bool {
+        let mut left = n;
+        move || {
+            left -= 1;
+            left > 0
+        }
+    }
+
+    fn entry(to: &Account, token_id: Option<u64>) -> AirdropEntry {
+        AirdropEntry {
+            to: to.clone(),
+            token_id: token_id.map(Into::into),
+            metadata: Some(AirdropMetadata {
+                name: "Airdrop".to_owned(),
+                image: "QUFBQQ".to_owned(),
+            }),
+        }
+    }
+
+    #[test]
+    fn test_airdrop() {
+        let authority = Principal::from_slice(&[0x1]);
+        let alice = Account::from_owner(Principal::from_slice(&[0x2]));
+        let bob = Account::from_owner(Principal::from_slice(&[0x3]));
+
+        let mut c = Collection {
+            authority: Some(authority),
+            supply_cap: Some(4),
+            ..Default::default()
+        };
+        c.add_token(Token {
+            id: 0.into(),
+            name: "Reserved".to_owned(),
+            image: vec![],
+            owner: Account::from_owner(authority),
+            transferable: true,
+            locked_until: None,
+            royalties: None,
+            reveal_hash: None,
+        });
+        c.add_token(Token {
+            id: 1.into(),
+            name: "Someone else's".to_owned(),
+            image: vec![],
+            owner: bob.clone(),
+            transferable: true,
+            locked_until: None,
+            royalties: None,
+            reveal_hash: None,
+        });
+
+        assert!(c
+            .create_airdrop(&bob.owner, 0, vec![entry(&bob, None)])
+            .is_err());
+
+        let recipients = vec![
+            entry(&alice, Some(0)),
+            entry(&alice, Some(1)),
+            entry(&bob, None),
+            AirdropEntry {
+                metadata: None,
+                ..entry(&bob, None)
+            },
+            entry(&bob, Some(10)),
+            entry(&alice, None),
+        ];
+        let id = c.create_airdrop(&authority, 10, recipients).unwrap();
+
+        assert_eq!(c.airdrop_step(id, 10, entries(4)), Ok(false));
+        assert_eq!(c.airdrops.jobs[&id].statuses[4], AirdropStatus::Pending);
+        assert_eq!(c.airdrop_step(id, 20, entries(4)), Ok(true));
+
+        let job = &c.airdrops.jobs[&id];
+        assert_eq!(job.finished_at, Some(20));
+        assert_eq!(
+            job.statuses,
+            vec![
+                AirdropStatus::Transferred {
+                    token_id: 0.into(),
+                    transfer_id: 0.into(),
+                },
+                AirdropStatus::Failed {
+                    message: "token with id 1 is not owned by authority".to_owned()
+                },
+                AirdropStatus::Minted { token_id: 2.into() },
+                AirdropStatus::Failed {
+                    message: "metadata is required to mint".to_owned()
+                },
+                AirdropStatus::Minted {
+                    token_id: 10.into()
+                },
+                AirdropStatus::Failed {
+                    message: "supply cap reached".to_owned()
+                },
+            ]
+        );
+        assert_eq!(c.tokens[&Nat::from(0)].owner, alice);
+        assert_eq!(c.tokens[&Nat::from(10)].owner, bob);
+
+        // finished job is not processed again
+        assert_eq!(c.airdrop_step(id, 30, entries(4)), Ok(true));
+        assert!(c.airdrop_step(id + 1, 30, entries(4)).is_err());
+    }
+
+    #[test]
+    fn test_airdrop_tick() {
+        let authority = Principal::from_slice(&[0x1]);
+        let alice = Account::from_owner(Principal::from_slice(&[0x2]));
+
+        let mut c = Collection {
+            authority: Some(authority),
+            ..Default::default()
+        };
+        let recipients = vec![entry(&alice, None); 3];
+        let first = c.create_airdrop(&authority, 0, recipients.clone()).unwrap();
+        let second = c.create_airdrop(&authority, 0, recipients).unwrap();
+
+        // instructions are not counted outside of the canister, so every tick finishes a job
+        c.airdrop_tick(10);
+        assert_eq!(c.airdrops.jobs[&first].finished_at, Some(10));
+        assert_eq!(c.airdrops.jobs[&second].cursor, 0);
+        assert_eq!(c.airdrops.pending, BTreeSet::from([second]));
+
+        c.airdrop_tick(20);
+        c.airdrop_tick(30);
+        assert_eq!(c.airdrops.jobs[&second].finished_at, Some(20));
+        assert!(c.airdrops.pending.is_empty());
+        assert_eq!(c.tokens.len(), 6);
+
+        // finished jobs keep only statuses
+        let job = &c.airdrops.jobs[&second];
+        assert!(job.entries.is_empty());
+        assert_eq!(job.statuses.len(), 3);
+    }
+
+    #[test]
+    fn test_airdrop_limit() {
+        let authority = Principal::from_slice(&[0x1]);
+        let alice = Account::from_owner(Principal::from_slice(&[0x2]));
+
+        let mut c = Collection {
+            authority: Some(authority),
+            ..Default::default()
+        };
+        c.limits.max_airdrop_entries = 2;
+
+        let recipients = vec![entry(&alice, None); 3];
+        assert_eq!(
+            c.create_airdrop(&authority, 0, recipients),
+            Err("airdrop is too large: 3 > 2".to_owned())
+        );
+        assert!(c.airdrops.jobs.is_empty());
+    }
+}
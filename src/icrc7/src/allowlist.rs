@@ -0,0 +1,296 @@
+//! Presale phases gated by allowlist.
+//!
+//! Allowlist is not stored in the canister, only the Merkle root of its leaves is. Leaf is
+//! `sha256(0x00 || len(principal) || principal || quota)` with length as u64 and quota as u32,
+//! both big endian. Inner node is `sha256(0x01 || min(a, b) || max(a, b))`, so proofs don't need
+//! to carry sibling positions. Last node of an odd-sized level is moved up unchanged.
+
+use std::collections::HashMap;
+
+use ic_kit::prelude::*;
+use sha2::{Digest, Sha256};
+
+use crate::state::*;
+
+/// presale phase, only principals from the allowlist can mint while it's active
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, CandidType)]
+pub struct MintPhase {
+    /// unique phase name, claimed counts are tracked by it
+    pub name: String,
+    /// 32 byte Merkle root of (principal, quota) leaves
+    pub merkle_root: Vec<u8>,
+    pub starts_at: u64,
+    pub ends_at: u64,
+    /// price for this phase, public mint price is used if not set
+    pub price: Option<Nat>,
+}
+
+/// proof that (caller, quota) leaf is included in the phase allowlist
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, CandidType)]
+pub struct AllowlistProof {
+    pub quota: u32,
+    /// sibling hashes from the leaf up to the root
+    pub proof: Vec<Vec<u8>>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize, CandidType)]
+pub struct Allowlist {
+    pub phases: Vec<MintPhase>,
+    /// tokens minted by each principal in each phase, including ones with payment in flight
+    pub claimed: HashMap<String, HashMap<Principal, u32>>,
+}
+
+impl Allowlist {
+    /// first phase running at time `now`
+    pub fn active_phase(&self, now: u64) -> Option<&MintPhase> {
+        self.phases
+            .iter()
+            .find(|phase| phase.starts_at <= now && now < phase.ends_at)
+    }
+
+    pub fn claimed_by(&self, phase: &str, principal: &Principal) -> u32 {
+        self.claimed
+            .get(phase)
+            .and_then(|claimed| claimed.get(principal))
+            .copied()
+            .unwrap_or(0)
+    }
+
+    /// checks that `principal` is allowlisted in `phase` and has not used up its quota
+    pub fn check_claim(
+        &self,
+        phase: &MintPhase,
+        principal: &Principal,
+        proof: Option<&AllowlistProof>,
+    ) -> Result<(), String> {
+        let proof = match proof {
+            Some(proof) => proof,
+            None => return Err("allowlist proof is required".to_owned()),
+        };
+
+        let leaf = allowlist_leaf(principal, proof.quota);
+        if !verify_proof(&phase.merkle_root, &leaf, &proof.proof) {
+            return Err("invalid allowlist proof".to_owned());
+        }
+
+        if self.claimed_by(&phase.name, principal) >= proof.quota {
+            return Err("allowlist quota reached".to_owned());
+        }
+
+        Ok(())
+    }
+
+    pub fn claim(&mut self, phase: &str, principal: Principal) {
+        *self
+            .claimed
+            .entry(phase.to_owned())
+            .or_default()
+            .entry(principal)
+            .or_default() += 1;
+    }
+
+    /// gives back claim after failed payment
+    pub fn unclaim(&mut self, phase: &str, principal: &Principal) {
+        if let Some(claimed) = self
+            .claimed
+            .get_mut(phase)
+            .and_then(|claimed| claimed.get_mut(principal))
+        {
+            *claimed = claimed.saturating_sub(1);
+        }
+    }
+}
+
+pub fn allowlist_leaf(principal: &Principal, quota: u32) -> Vec<u8> {
+    let mut h = Sha256::new();
+    h.update([0x00]);
+    h.update((principal.as_slice().len() as u64).to_be_bytes());
+    h.update(principal.as_slice());
+    h.update(quota.to_be_bytes());
+    h.finalize().to_vec()
+}
+
+fn hash_pair(a: &[u8], b: &[u8]) -> Vec<u8> {
+    let (lo, hi) = if a <= b { (a, b) } else { (b, a) };
+
+    let mut h = Sha256::new();
+    h.update([0x01]);
+    h.update(lo);
+    h.update(hi);
+    h.finalize().to_vec()
+}
+
+fn next_level(level: &[Vec<u8>]) -> Vec<Vec<u8>> {
+    level
+        .chunks(2)
+        .map(|pair| match pair {
+            [a, b] => hash_pair(a, b),
+            [a] => a.clone(),
+            _ => unreachable!(),
+        })
+        .collect()
+}
+
+/// root of the tree built from `leaves`, meant for off-chain tooling and tests
+pub fn merkle_root(leaves: &[Vec<u8>]) -> Vec<u8> {
+    let mut level = leaves.to_vec();
+    while level.len() > 1 {
+        level = next_level(&level);
+    }
+    level.pop().unwrap_or_default()
+}
+
+/// proof for the leaf at `index`, meant for off-chain tooling and tests
+pub fn merkle_proof(leaves: &[Vec<u8>], mut index: usize) -> Vec<Vec<u8>> {
+    let mut proof = vec![];
+    let mut level = leaves.to_vec();
+    while level.len() > 1 {
+        if let Some(sibling) = level.get(index ^ 1) {
+            proof.push(sibling.clone());
+        }
+        level = next_level(&level);
+        index /= 2;
+    }
+    proof
+}
+
+pub fn verify_proof(root: &[u8], leaf: &[u8], proof: &[Vec<u8>]) -> bool {
+    let node = proof
+        .iter()
+        .fold(leaf.to_vec(), |node, sibling| hash_pair(&node, sibling));
+    node == root
+}
+
+fn validate_phases(phases: &[MintPhase]) -> Result<(), String> {
+    for (i, phase) in phases.iter().enumerate() {
+        if phase.merkle_root.len() != 32 {
+            return Err(format!(
+                "phase {}: merkle root must be 32 bytes",
+                phase.name
+            ));
+        }
+
+        if phase.starts_at >= phase.ends_at {
+            return Err(format!("phase {}: starts after it ends", phase.name));
+        }
+
+        if phases[..i].iter().any(|other| other.name == phase.name) {
+            return Err(format!("phase {}: duplicate name", phase.name));
+        }
+    }
+
+    Ok(())
+}
+
+/// replaces presale phases, claimed counts of phases with the same name are kept
+#[update]
+pub fn set_mint_phases(c: &mut Collection, phases: Vec<MintPhase>) -> Result<(), String> {
+    c.check_authority(&caller())?;
+    validate_phases(&phases)?;
+    c.allowlist.phases = phases;
+    Ok(())
+}
+
+#[query]
+fn mint_phases(c: &Collection) -> Vec<MintPhase> {
+    c.allowlist.phases.clone()
+}
+
+#[query]
+fn allowlist_claimed(c: &Collection, phase: String, minter: Principal) -> u32 {
+    c.allowlist.claimed_by(&phase, &minter)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn leaves(n: u8) -> Vec<Vec<u8>> {
+        (1..=n)
+            .map(|i| allowlist_leaf(&Principal::from_slice(&[i]), i as u32))
+            .collect()
+    }
+
+    #[test]
+    fn test_merkle_proof() {
+        for n in 1..=7 {
+            let leaves = leaves(n);
+            let root = merkle_root(&leaves);
+
+            for (i, leaf) in leaves.iter().enumerate() {
+                let proof = merkle_proof(&leaves, i);
+                assert!(verify_proof(&root, leaf, &proof));
+            }
+        }
+
+        let leaves = leaves(5);
+        let root = merkle_root(&leaves);
+        let proof = merkle_proof(&leaves, 2);
+
+        // quota can't be raised by the minter
+        let forged = allowlist_leaf(&Principal::from_slice(&[3]), 10);
+        assert!(!verify_proof(&root, &forged, &proof));
+        assert!(!verify_proof(&root, &leaves[2], &proof[1..]));
+    }
+
+    #[test]
+    fn test_check_claim() {
+        let minter = Principal::from_slice(&[1]);
+        let leaves = leaves(3);
+        let phase = MintPhase {
+            name: "presale".to_owned(),
+            merkle_root: merkle_root(&leaves),
+            starts_at: 10,
+            ends_at: 20,
+            price: None,
+        };
+        let mut allowlist = Allowlist {
+            phases: vec![phase.clone()],
+            ..Default::default()
+        };
+
+        assert_eq!(allowlist.active_phase(9), None);
+        assert_eq!(allowlist.active_phase(10), Some(&phase));
+        assert_eq!(allowlist.active_phase(20), None);
+
+        let proof = AllowlistProof {
+            quota: 1,
+            proof: merkle_proof(&leaves, 0),
+        };
+        assert!(allowlist.check_claim(&phase, &minter, None).is_err());
+        assert!(allowlist
+            .check_claim(&phase, &Principal::from_slice(&[2]), Some(&proof))
+            .is_err());
+        assert_eq!(allowlist.check_claim(&phase, &minter, Some(&proof)), Ok(()));
+
+        allowlist.claim(&phase.name, minter);
+        assert_eq!(
+            allowlist.check_claim(&phase, &minter, Some(&proof)),
+            Err("allowlist quota reached".to_owned())
+        );
+
+        allowlist.unclaim(&phase.name, &minter);
+        assert_eq!(allowlist.claimed_by(&phase.name, &minter), 0);
+    }
+
+    #[test]
+    fn test_validate_phases() {
+        let phase = MintPhase {
+            name: "presale".to_owned(),
+            merkle_root: vec![0; 32],
+            starts_at: 10,
+            ends_at: 20,
+            price: None,
+        };
+        assert!(validate_phases(std::slice::from_ref(&phase)).is_ok());
+        assert!(validate_phases(&[phase.clone(), phase.clone()]).is_err());
+
+        let mut invalid = phase.clone();
+        invalid.merkle_root = vec![0; 31];
+        assert!(validate_phases(&[invalid]).is_err());
+
+        let mut invalid = phase;
+        invalid.ends_at = 10;
+        assert!(validate_phases(&[invalid]).is_err());
+    }
+}
@@ -0,0 +1,580 @@
+//! English and Dutch auctions.
+//!
+//! Ended auctions are settled from heartbeat. ic-kit 0.5 does not support the global timer that
+//! `ic_cdk_timers` builds on, so instead of a timer per auction every heartbeat peeks at the
+//! earliest deadline in `Marketplace::auction_deadlines`, which costs a single lookup until some
+//! auction actually ends. Settlement waits while transfers are paused.
+
+use ic_kit::prelude::*;
+
+use crate::marketplace::*;
+use crate::state::*;
+
+/// max number of auctions settled by a single heartbeat, the rest waits for the next ones
+pub const SETTLE_BUDGET: usize = 100;
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, CandidType)]
+pub enum AuctionKind {
+    /// ascending bids, highest bid at the end of the auction wins
+    English { min_increment: Nat },
+    /// price falls linearly from start price to `end_price`, first bid wins immediately
+    Dutch { end_price: Nat },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, CandidType)]
+pub struct Bid {
+    pub bidder: Account,
+    pub amount: Nat,
+    /// token recipient if bid wins
+    pub to: Account,
+    pub created_at: u64,
+}
+
+/// token put up for auction, it stays with the seller but can't be transferred until the auction
+/// is settled or cancelled
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, CandidType)]
+pub struct Auction {
+    pub seller: Account,
+    pub kind: AuctionKind,
+    pub start_price: Nat,
+    /// english auction without bid at or above reserve price returns token to the seller
+    pub reserve_price: Option<Nat>,
+    pub starts_at: u64,
+    pub ends_at: u64,
+    pub highest_bid: Option<Bid>,
+}
+
+impl Auction {
+    /// smallest amount bid at time `now` has to offer
+    pub fn min_bid(&self, now: u64) -> Nat {
+        match self.kind {
+            AuctionKind::English { ref min_increment } => match self.highest_bid {
+                Some(ref bid) => Nat(bid.amount.0.clone() + &min_increment.0),
+                None => self.start_price.clone(),
+            },
+            AuctionKind::Dutch { ref end_price } => {
+                let duration = self.ends_at - self.starts_at;
+                let elapsed = now.clamp(self.starts_at, self.ends_at) - self.starts_at;
+                let drop = (self.start_price.0.clone() - &end_price.0) * elapsed / duration;
+                Nat(self.start_price.0.clone() - drop)
+            }
+        }
+    }
+
+    pub fn is_active(&self, now: u64) -> bool {
+        self.starts_at <= now && now < self.ends_at
+    }
+}
+
+/// arguments for the "create_auction" method
+#[derive(Debug, Clone, Deserialize, Serialize, CandidType)]
+pub struct CreateAuctionArgs {
+    pub token_id: TokenID,
+    pub from_subaccount: Option<Subaccount>,
+    pub kind: AuctionKind,
+    pub start_price: Nat,
+    pub reserve_price: Option<Nat>,
+    /// auction starts immediately if not set
+    pub starts_at: Option<u64>,
+    pub ends_at: u64,
+}
+
+/// arguments for the "place_bid" method
+#[derive(Debug, Clone, Deserialize, Serialize, CandidType)]
+pub struct BidArgs {
+    pub token_id: TokenID,
+    /// bid amount, for dutch auctions it's the most bidder is willing to pay
+    pub amount: Nat,
+    /// bidder's subaccount payment is taken from
+    pub from_subaccount: Option<Subaccount>,
+    /// token recipient, bidder's account if not set
+    pub to: Option<Account>,
+}
+
+impl Collection {
+    /// puts token of `caller` up for auction
+    pub fn create_auction(
+        &mut self,
+        caller: Principal,
+        now: u64,
+        args: CreateAuctionArgs,
+    ) -> Result<(), String> {
+        if self.marketplace.ledger.is_none() {
+            return Err("payment ledger is not configured".to_owned());
+        }
+
+        if self.pause.state.transfers {
+            return Err("transfers are paused".to_owned());
+        }
+
+        if args.start_price == 0 {
+            return Err("start price must be greater than 0".to_owned());
+        }
+
+        let starts_at = args.starts_at.unwrap_or(now).max(now);
+        if args.ends_at <= starts_at {
+            return Err("auction must end after it starts".to_owned());
+        }
+
+        if let AuctionKind::Dutch { ref end_price } = args.kind {
+            if *end_price >= args.start_price {
+                return Err("end price must be lower than start price".to_owned());
+            }
+        }
+
+        let seller = Account::new(caller, args.from_subaccount).to_canonical();
+        self.check_listable(&seller, &args.token_id, now)?;
+
+        self.marketplace
+            .auction_deadlines
+            .insert((args.ends_at, args.token_id.clone()));
+        self.marketplace.auctions.insert(
+            args.token_id,
+            Auction {
+                seller,
+                kind: args.kind,
+                start_price: args.start_price,
+                reserve_price: args.reserve_price,
+                starts_at,
+                ends_at: args.ends_at,
+                highest_bid: None,
+            },
+        );
+
+        Ok(())
+    }
+
+    /// cancels auction without bids, can be called by seller or authority
+    pub fn cancel_auction(&mut self, caller: Principal, token_id: &TokenID) -> Result<(), String> {
+        let auction = match self.marketplace.auctions.get(token_id) {
+            Some(auction) => auction,
+            None => return Err(format!("token with id {} is not auctioned", token_id)),
+        };
+
+        if auction.seller.owner != caller && self.check_authority(&caller).is_err() {
+            return Err("only seller or authority can cancel auction".to_owned());
+        }
+
+        if auction.highest_bid.is_some() {
+            return Err("auction already has bids".to_owned());
+        }
+
+        self.remove_auction(token_id);
+
+        Ok(())
+    }
+
+    /// removes auction together with its deadline
+    fn remove_auction(&mut self, token_id: &TokenID) -> Option<Auction> {
+        let auction = self.marketplace.auctions.remove(token_id)?;
+        self.marketplace
+            .auction_deadlines
+            .remove(&(auction.ends_at, token_id.clone()));
+        Some(auction)
+    }
+
+    /// checks bid before payment is pulled, returns amount to charge and ledger to pull it from
+    pub fn check_bid(
+        &self,
+        bidder: &Account,
+        to: &Account,
+        now: u64,
+        args: &BidArgs,
+    ) -> Result<(Nat, Principal), BuyError> {
+        if self.pause.state.transfers {
+            return Err(BuyError::TemporarilyUnavailable);
+        }
+
+        let ledger = match self.marketplace.ledger {
+            Some(ledger) => ledger,
+            None => {
+                return Err(BuyError::GenericError {
+                    message: "payment ledger is not configured".to_owned(),
+                })
+            }
+        };
+
+        let auction = match self.marketplace.auctions.get(&args.token_id) {
+            Some(auction) if auction.is_active(now) => auction,
+            _ => return Err(BuyError::NotListed),
+        };
+
+        if auction.seller == *bidder {
+            return Err(BuyError::GenericError {
+                message: "can't bid on own token".to_owned(),
+            });
+        }
+
+        self.check_sale(&args.token_id, &auction.seller, bidder, to, now)?;
+
+        let min_bid = auction.min_bid(now);
+        if args.amount < min_bid {
+            return Err(BuyError::BidTooLow { min_bid });
+        }
+
+        // dutch auction is won at current price, even if bidder is willing to pay more
+        let amount = match auction.kind {
+            AuctionKind::English { .. } => args.amount.clone(),
+            AuctionKind::Dutch { .. } => min_bid,
+        };
+
+        Ok((amount, ledger))
+    }
+
+    /// records paid bid, returns id of the transfer if it won dutch auction,
+    /// payment is credited back to the bidder if the bid is no longer valid
+    pub fn accept_bid(
+        &mut self,
+        token_id: &TokenID,
+        bid: Bid,
+        now: u64,
+    ) -> Result<Option<TransferID>, BuyError> {
+        // auction could have been settled or outbid while payment was in flight
+        let seller = match self.marketplace.auctions.get(token_id) {
+            Some(auction) if auction.is_active(now) => auction.seller.clone(),
+            _ => {
+                self.credit_proceeds(bid.bidder, bid.amount);
+                return Err(BuyError::NotListed);
+            }
+        };
+
+        // as well as pause, denylist or token lock
+        if let Err(e) = self.check_sale(token_id, &seller, &bid.bidder, &bid.to, now) {
+            self.credit_proceeds(bid.bidder, bid.amount);
+            return Err(e);
+        }
+
+        let auction = self.marketplace.auctions.get_mut(token_id).unwrap();
+
+        let min_bid = auction.min_bid(bid.created_at);
+        if bid.amount < min_bid {
+            self.credit_proceeds(bid.bidder, bid.amount);
+            return Err(BuyError::BidTooLow { min_bid });
+        }
+
+        if let AuctionKind::Dutch { .. } = auction.kind {
+            let auction = self.remove_auction(token_id).unwrap();
+            let id = self.complete_sale(token_id, auction.seller, &bid.amount, bid.to, now);
+            return Ok(Some(id));
+        }
+
+        if let Some(outbid) = auction.highest_bid.replace(bid) {
+            self.credit_proceeds(outbid.bidder, outbid.amount);
+        }
+
+        Ok(None)
+    }
+
+    /// settles up to `budget` auctions that ended by time `now`, does nothing while transfers
+    /// are paused
+    pub fn settle_auctions(&mut self, now: u64, budget: usize) {
+        if self.pause.state.transfers {
+            return;
+        }
+
+        for _ in 0..budget {
+            let token_id = match self.marketplace.auction_deadlines.first() {
+                Some((ends_at, token_id)) if *ends_at <= now => token_id.clone(),
+                _ => return,
+            };
+
+            let auction = self.remove_auction(&token_id).unwrap();
+            let reserve_met = |bid: &Bid| {
+                auction
+                    .reserve_price
+                    .as_ref()
+                    .is_none_or(|reserve| bid.amount >= *reserve)
+            };
+
+            match auction.highest_bid {
+                Some(bid) if reserve_met(&bid) => {
+                    // denylist or token lock could have changed since the bid was placed,
+                    // the sale is then called off and the winner is refunded
+                    match self.check_sale(&token_id, &auction.seller, &bid.bidder, &bid.to, now) {
+                        Ok(()) => {
+                            self.complete_sale(&token_id, auction.seller, &bid.amount, bid.to, now);
+                        }
+                        Err(_) => self.credit_proceeds(bid.bidder, bid.amount),
+                    }
+                }
+                // token just stays with the seller
+                Some(bid) => self.credit_proceeds(bid.bidder, bid.amount),
+                None => {}
+            }
+        }
+    }
+}
+
+#[update]
+pub fn create_auction(c: &mut Collection, args: CreateAuctionArgs) -> Result<(), String> {
+    c.create_auction(caller(), ic::time(), args)
+}
+
+#[update]
+pub fn cancel_auction(c: &mut Collection, token_id: TokenID) -> Result<(), String> {
+    c.cancel_auction(caller(), &token_id)
+}
+
+/// bids on auctioned token, bid is pulled from the bidder with "icrc2_transfer_from"
+/// and credited back to proceeds of the bidder once outbid
+#[update]
+pub async fn place_bid(args: BidArgs) -> Result<Option<TransferID>, BuyError> {
+    let bidder = Account::new(caller(), args.from_subaccount).to_canonical();
+    let to = args.to.clone().unwrap_or(bidder.clone()).to_canonical();
+    let created_at = ic::time();
+
+    let (amount, ledger) = ic::with(|c: &Collection| c.check_bid(&bidder, &to, created_at, &args))?;
+
+    pull_payment(ledger, bidder.clone(), amount.clone()).await?;
+
+    let bid = Bid {
+        bidder,
+        amount,
+        to,
+        created_at,
+    };
+
+    ic::with_mut(|c: &mut Collection| c.accept_bid(&args.token_id, bid, ic::time()))
+}
+
+#[query]
+fn get_auction(c: &Collection, token_id: TokenID) -> Option<Auction> {
+    c.marketplace.auctions.get(&token_id).cloned()
+}
+
+/// smallest bid auction currently accepts
+#[query]
+fn auction_min_bid(c: &Collection, token_id: TokenID) -> Option<Nat> {
+    let now = ic::time();
+    c.marketplace
+        .auctions
+        .get(&token_id)
+        .filter(|a| a.is_active(now))
+        .map(|a| a.min_bid(now))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SELLER: u8 = 0x1;
+
+    fn account(id: u8) -> Account {
+        Account::from_owner(Principal::from_slice(&[id]))
+    }
+
+    fn auctioned_collection(kind: AuctionKind, reserve_price: Option<Nat>) -> Collection {
+        let mut c = Collection::default();
+        c.marketplace.ledger = Some(Principal::from_slice(&[0x8]));
+        c.add_token(Token {
+            id: 1.into(),
+            name: "NFT-1".to_owned(),
+            image: vec![],
+            owner: account(SELLER),
+            transferable: true,
+            locked_until: None,
+            royalties: None,
+            reveal_hash: None,
+        });
+
+        let args = CreateAuctionArgs {
+            token_id: 1.into(),
+            from_subaccount: None,
+            kind,
+            start_price: 1000.into(),
+            reserve_price,
+            starts_at: None,
+            ends_at: 100,
+        };
+        c.create_auction(account(SELLER).owner, 0, args).unwrap();
+
+        c
+    }
+
+    fn bid_args(amount: u64) -> BidArgs {
+        BidArgs {
+            token_id: 1.into(),
+            amount: amount.into(),
+            from_subaccount: None,
+            to: None,
+        }
+    }
+
+    fn bid(
+        c: &mut Collection,
+        bidder: u8,
+        amount: u64,
+        now: u64,
+    ) -> Result<Option<TransferID>, BuyError> {
+        let (amount, _) =
+            c.check_bid(&account(bidder), &account(bidder), now, &bid_args(amount))?;
+        let bid = Bid {
+            bidder: account(bidder),
+            amount,
+            to: account(bidder),
+            created_at: now,
+        };
+        c.accept_bid(&1.into(), bid, now)
+    }
+
+    #[test]
+    fn test_english_auction() {
+        let kind = AuctionKind::English {
+            min_increment: 100.into(),
+        };
+        let mut c = auctioned_collection(kind, None);
+
+        assert_eq!(c.tokens[&Nat::from(1)].owner, account(SELLER));
+        assert!(c.in_escrow(&1.into()));
+        assert_eq!(
+            bid(&mut c, 2, 900, 10),
+            Err(BuyError::BidTooLow {
+                min_bid: 1000.into()
+            })
+        );
+
+        assert_eq!(bid(&mut c, 2, 1000, 10), Ok(None));
+        assert_eq!(
+            bid(&mut c, 3, 1050, 20),
+            Err(BuyError::BidTooLow {
+                min_bid: 1100.into()
+            })
+        );
+        assert_eq!(bid(&mut c, 3, 1100, 20), Ok(None));
+
+        // outbid bidder is refunded
+        assert_eq!(c.marketplace.proceeds[&account(2)], Nat::from(1000));
+
+        c.settle_auctions(99, SETTLE_BUDGET);
+        assert_eq!(c.marketplace.auctions.len(), 1);
+
+        c.settle_auctions(100, SETTLE_BUDGET);
+        assert!(c.marketplace.auctions.is_empty());
+        assert_eq!(c.tokens[&Nat::from(1)].owner, account(3));
+        assert_eq!(c.marketplace.proceeds[&account(SELLER)], Nat::from(1100));
+    }
+
+    #[test]
+    fn test_reserve_price() {
+        let kind = AuctionKind::English {
+            min_increment: 1.into(),
+        };
+        let mut c = auctioned_collection(kind, Some(2000.into()));
+
+        assert_eq!(bid(&mut c, 2, 1500, 10), Ok(None));
+        c.settle_auctions(100, SETTLE_BUDGET);
+
+        assert_eq!(c.tokens[&Nat::from(1)].owner, account(SELLER));
+        assert!(!c.in_escrow(&1.into()));
+        assert_eq!(c.marketplace.proceeds[&account(2)], Nat::from(1500));
+        assert!(!c.marketplace.proceeds.contains_key(&account(SELLER)));
+    }
+
+    #[test]
+    fn test_dutch_auction() {
+        let kind = AuctionKind::Dutch {
+            end_price: 500.into(),
+        };
+        let mut c = auctioned_collection(kind, None);
+
+        let auction = &c.marketplace.auctions[&Nat::from(1)];
+        assert_eq!(auction.min_bid(0), Nat::from(1000));
+        assert_eq!(auction.min_bid(50), Nat::from(750));
+        assert_eq!(auction.min_bid(200), Nat::from(500));
+
+        // bidder pays current price, not the amount offered
+        assert!(bid(&mut c, 2, 1000, 50).unwrap().is_some());
+        assert!(c.marketplace.auctions.is_empty());
+        assert_eq!(c.tokens[&Nat::from(1)].owner, account(2));
+        assert_eq!(c.marketplace.proceeds[&account(SELLER)], Nat::from(750));
+    }
+
+    #[test]
+    fn test_sale_checks() {
+        let kind = AuctionKind::English {
+            min_increment: 1.into(),
+        };
+        let mut c = auctioned_collection(kind, None);
+        assert_eq!(bid(&mut c, 2, 1000, 10), Ok(None));
+
+        // bid paid while transfers got paused is refunded
+        let (amount, _) = c
+            .check_bid(&account(3), &account(3), 20, &bid_args(1100))
+            .unwrap();
+        c.pause.state.transfers = true;
+        let late = Bid {
+            bidder: account(3),
+            amount,
+            to: account(3),
+            created_at: 20,
+        };
+        assert_eq!(
+            c.accept_bid(&1.into(), late, 20),
+            Err(BuyError::TemporarilyUnavailable)
+        );
+        assert_eq!(c.marketplace.proceeds[&account(3)], Nat::from(1100));
+        c.pause.state.transfers = false;
+
+        // token locked before the end calls the sale off
+        c.tokens.get_mut(&Nat::from(1)).unwrap().locked_until = Some(1000);
+        c.settle_auctions(100, SETTLE_BUDGET);
+
+        assert!(c.marketplace.auctions.is_empty());
+        assert_eq!(c.tokens[&Nat::from(1)].owner, account(SELLER));
+        assert_eq!(c.marketplace.proceeds[&account(2)], Nat::from(1000));
+        assert!(!c.marketplace.proceeds.contains_key(&account(SELLER)));
+    }
+
+    #[test]
+    fn test_settle_auctions() {
+        let mut c = Collection::default();
+        c.marketplace.ledger = Some(Principal::from_slice(&[0x8]));
+
+        for (id, ends_at) in [(1, 300), (2, 100), (3, 200)] {
+            c.add_token(Token {
+                id: id.into(),
+                name: format!("NFT-{}", id),
+                image: vec![],
+                owner: account(SELLER),
+                transferable: true,
+                locked_until: None,
+                royalties: None,
+                reveal_hash: None,
+            });
+
+            let args = CreateAuctionArgs {
+                token_id: id.into(),
+                from_subaccount: None,
+                kind: AuctionKind::English {
+                    min_increment: 1.into(),
+                },
+                start_price: 1000.into(),
+                reserve_price: None,
+                starts_at: None,
+                ends_at,
+            };
+            c.create_auction(account(SELLER).owner, 0, args).unwrap();
+        }
+
+        // cancelled auction leaves the deadline index as well
+        c.cancel_auction(account(SELLER).owner, &3.into()).unwrap();
+        assert_eq!(c.marketplace.auction_deadlines.len(), 2);
+
+        c.pause.state.transfers = true;
+        c.settle_auctions(1000, SETTLE_BUDGET);
+        assert_eq!(c.marketplace.auctions.len(), 2);
+
+        // earliest auction is settled first
+        c.pause.state.transfers = false;
+        c.settle_auctions(1000, 1);
+        assert!(c.marketplace.auctions.contains_key(&Nat::from(1)));
+        assert!(!c.marketplace.auctions.contains_key(&Nat::from(2)));
+
+        c.settle_auctions(299, SETTLE_BUDGET);
+        assert_eq!(c.marketplace.auctions.len(), 1);
+
+        c.settle_auctions(300, SETTLE_BUDGET);
+        assert!(c.marketplace.auctions.is_empty());
+        assert!(c.marketplace.auction_deadlines.is_empty());
+    }
+}
@@ -0,0 +1,82 @@
+use std::collections::HashSet;
+
+use ic_kit::prelude::*;
+
+use crate::state::*;
+
+/// entity that can be put on the denylist
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, CandidType)]
+pub enum DenylistTarget {
+    /// all accounts of the principal
+    Principal(Principal),
+    /// single account
+    Account(Account),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, CandidType)]
+pub enum DenylistAction {
+    Add,
+    Remove,
+}
+
+/// audit log entry, recorded on every denylist change
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, CandidType)]
+pub struct DenylistEntry {
+    pub action: DenylistAction,
+    pub target: DenylistTarget,
+    /// principal that made the change
+    pub caller: Principal,
+    pub timestamp: u64,
+}
+
+/// accounts and principals that can't send, receive or spend tokens
+#[derive(Debug, Clone, Default, Deserialize, Serialize, CandidType)]
+pub struct Denylist {
+    pub principals: HashSet<Principal>,
+    pub accounts: HashSet<Account>,
+    pub log: Vec<DenylistEntry>,
+}
+
+impl Denylist {
+    /// checks if account is denied either directly or through its owner
+    pub fn contains(&self, account: &Account) -> bool {
+        self.principals.contains(&account.owner) || self.accounts.contains(&account.to_canonical())
+    }
+}
+
+impl Collection {
+    /// adds or removes `target` from the denylist on behalf of `caller` at time `now`
+    pub fn update_denylist(
+        &mut self,
+        caller: &Principal,
+        now: u64,
+        action: DenylistAction,
+        target: DenylistTarget,
+    ) -> Result<(), String> {
+        self.check_authority(caller)?;
+
+        let list = &mut self.denylist;
+        let changed = match (action, &target) {
+            (DenylistAction::Add, DenylistTarget::Principal(p)) => list.principals.insert(*p),
+            (DenylistAction::Add, DenylistTarget::Account(a)) => {
+                list.accounts.insert(a.to_canonical())
+            }
+            (DenylistAction::Remove, DenylistTarget::Principal(p)) => list.principals.remove(p),
+            (DenylistAction::Remove, DenylistTarget::Account(a)) => {
+                list.accounts.remove(&a.to_canonical())
+            }
+        };
+
+        // no-op changes are not worth an audit entry
+        if changed {
+            list.log.push(DenylistEntry {
+                action,
+                target,
+                caller: *caller,
+                timestamp: now,
+            });
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,280 @@
+use std::collections::HashMap;
+
+use ic_kit::prelude::*;
+
+use crate::state::*;
+use crate::transactions::{Transaction, TxID};
+
+/// max number of records returned by a single "token_history" call
+pub const MAX_HISTORY_PAGE: usize = 1000;
+
+/// single ownership change of a token
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, CandidType)]
+pub struct OwnershipRecord {
+    /// position in token history, starting from 0
+    pub index: u64,
+    /// none once token is burned
+    pub owner: Option<Account>,
+    /// canister time of the transaction
+    pub timestamp: u64,
+    /// ID of the mint, transfer or burn in the transaction log, as returned by "get_transactions"
+    pub tx_id: Nat,
+}
+
+/// point in collection history, used by "owner_of_at"
+#[derive(Debug, Clone, Deserialize, Serialize, CandidType)]
+pub enum HistoryPoint {
+    /// right after the transaction with this ID
+    Block(Nat),
+    /// at this time, in nanoseconds
+    Timestamp(u64),
+}
+
+/// ownership records of every token ever minted, unlike transfers they are never garbage collected
+#[derive(Debug, Clone, Default, Deserialize, Serialize, CandidType)]
+pub struct OwnershipHistory {
+    pub tokens: HashMap<TokenID, Vec<OwnershipRecord>>,
+}
+
+impl Collection {
+    /// appends ownership changes made by the transaction to token histories, called for every
+    /// transaction added to the log
+    pub fn record_history(&mut self, tx_id: TxID, tx: &Transaction) {
+        let mut changes = vec![];
+        if let Some(ref mint) = tx.mint {
+            changes.push((mint.token_id.clone(), Some(mint.to.clone())));
+        }
+        if let Some(ref burn) = tx.burn {
+            changes.push((burn.token_id.clone(), None));
+        }
+        if let Some(ref transfer) = tx.transfer {
+            for id in &transfer.token_ids {
+                changes.push((id.clone(), Some(transfer.to.clone())));
+            }
+        }
+
+        for (id, owner) in changes {
+            let records = self.history.tokens.entry(id).or_default();
+            records.push(OwnershipRecord {
+                index: records.len() as u64,
+                owner,
+                timestamp: tx.timestamp,
+                tx_id: tx_id.into(),
+            });
+        }
+    }
+
+    /// owner of the token at the given point, none if it was not minted yet or was burned
+    pub fn owner_of_at(&self, id: &TokenID, at: &HistoryPoint) -> Option<Account> {
+        self.history
+            .tokens
+            .get(id)?
+            .iter()
+            .rev()
+            .find(|r| match at {
+                HistoryPoint::Block(block) => r.tx_id <= *block,
+                HistoryPoint::Timestamp(time) => r.timestamp <= *time,
+            })?
+            .owner
+            .clone()
+    }
+
+    /// history of the token oldest first, starting after record with index `prev`
+    pub fn token_history(
+        &self,
+        id: &TokenID,
+        prev: Option<u64>,
+        take: Option<u32>,
+    ) -> Vec<OwnershipRecord> {
+        let take = take.map_or(MAX_HISTORY_PAGE, |take| {
+            (take as usize).min(MAX_HISTORY_PAGE)
+        });
+        let start = prev.map_or(0, |prev| prev.saturating_add(1) as usize);
+
+        self.history
+            .tokens
+            .get(id)
+            .map(|records| records.iter().skip(start).take(take).cloned().collect())
+            .unwrap_or_default()
+    }
+}
+
+/// owner of the token right after the given transaction or at the given time
+#[query]
+fn owner_of_at(c: &Collection, token_id: TokenID, at: HistoryPoint) -> Option<Account> {
+    c.owner_of_at(&token_id, &at)
+}
+
+/// page of ownership records of the token, `prev` is index of the last record of the previous page
+#[query]
+fn token_history(
+    c: &Collection,
+    token_id: TokenID,
+    prev: Option<u64>,
+    take: Option<u32>,
+) -> Vec<OwnershipRecord> {
+    c.token_history(&token_id, prev, take)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::update::*;
+
+    #[test]
+    fn test_ownership_history() {
+        let authority = Principal::from_slice(&[0x1]);
+        let alice = Account::from_owner(Principal::from_slice(&[0x2]));
+        let bob = Account::from_owner(Principal::from_slice(&[0x3]));
+
+        let mut c = Collection {
+            authority: Some(authority),
+            revocable: true,
+            ..Default::default()
+        };
+
+        let mint_args = |id: u64, transferable: bool| MintTokenArgs {
+            id: id.into(),
+            name: format!("NFT-{}", id),
+            image: "QUFBQQ".to_owned(),
+            owner: alice.clone(),
+            transferable: Some(transferable),
+            locked_until: None,
+            royalties: None,
+            reveal_hash: None,
+        };
+        mint(&mut c, authority, 10, mint_args(1, true)).unwrap();
+        mint(&mut c, authority, 10, mint_args(2, false)).unwrap();
+
+        let transfer_args = |to: &Account| TransferArgs {
+            from: None,
+            to: to.clone(),
+            token_ids: [Nat::from(1)].into(),
+            memo: None,
+            created_at_time: None,
+            is_atomic: None,
+        };
+        let first = transfer(&mut c, alice.owner, 20, transfer_args(&bob)).unwrap();
+        assert_eq!(first, Nat::from(0));
+
+        // created_at_time set by the client does not affect history
+        let args = TransferArgs {
+            created_at_time: Some(25),
+            ..transfer_args(&alice)
+        };
+        transfer(&mut c, bob.owner, 30, args).unwrap();
+
+        // token 2 is not transferable, so only token 1 moves
+        let args = TransferArgs {
+            token_ids: [Nat::from(1), Nat::from(2)].into(),
+            is_atomic: Some(false),
+            ..transfer_args(&bob)
+        };
+        transfer(&mut c, alice.owner, 35, args).unwrap();
+
+        burn(&mut c, authority, 40, 2.into()).unwrap();
+
+        // transactions: 0, 1 mints, 2, 3, 4 transfers, 5 burn
+        let id = Nat::from(1);
+        let at = |point| c.owner_of_at(&id, &point);
+        assert_eq!(at(HistoryPoint::Timestamp(9)), None);
+        assert_eq!(at(HistoryPoint::Timestamp(10)), Some(alice.clone()));
+        assert_eq!(at(HistoryPoint::Timestamp(29)), Some(bob.clone()));
+        assert_eq!(at(HistoryPoint::Timestamp(30)), Some(alice.clone()));
+        assert_eq!(at(HistoryPoint::Block(1.into())), Some(alice.clone()));
+        assert_eq!(at(HistoryPoint::Block(2.into())), Some(bob.clone()));
+        assert_eq!(at(HistoryPoint::Block(3.into())), Some(alice.clone()));
+        assert_eq!(at(HistoryPoint::Block(4.into())), Some(bob.clone()));
+
+        let id = Nat::from(2);
+        assert_eq!(
+            c.owner_of_at(&id, &HistoryPoint::Timestamp(39)),
+            Some(alice.clone())
+        );
+        assert_eq!(c.owner_of_at(&id, &HistoryPoint::Timestamp(40)), None);
+        assert_eq!(
+            c.owner_of_at(&id, &HistoryPoint::Block(4.into())),
+            Some(alice.clone())
+        );
+        assert_eq!(c.owner_of_at(&id, &HistoryPoint::Block(5.into())), None);
+
+        let history = c.token_history(&Nat::from(1), None, Some(2));
+        assert_eq!(
+            history,
+            vec![
+                OwnershipRecord {
+                    index: 0,
+                    owner: Some(alice.clone()),
+                    timestamp: 10,
+                    tx_id: 0.into(),
+                },
+                OwnershipRecord {
+                    index: 1,
+                    owner: Some(bob.clone()),
+                    timestamp: 20,
+                    tx_id: 2.into(),
+                },
+            ]
+        );
+
+        let history = c.token_history(&Nat::from(1), Some(1), None);
+        assert_eq!(
+            history.iter().map(|r| r.owner.clone()).collect::<Vec<_>>(),
+            vec![Some(alice), Some(bob)]
+        );
+        assert!(c.token_history(&Nat::from(1), Some(3), None).is_empty());
+        assert!(c.token_history(&Nat::from(3), None, None).is_empty());
+    }
+
+    #[test]
+    fn test_failed_transfer_history() {
+        let authority = Principal::from_slice(&[0x1]);
+        let alice = Account::from_owner(Principal::from_slice(&[0x2]));
+        let bob = Account::from_owner(Principal::from_slice(&[0x3]));
+
+        let mut c = Collection {
+            authority: Some(authority),
+            ..Default::default()
+        };
+
+        for (id, owner) in [(1, &alice), (2, &bob)] {
+            let args = MintTokenArgs {
+                id: id.into(),
+                name: format!("NFT-{}", id),
+                image: "QUFBQQ".to_owned(),
+                owner: owner.clone(),
+                transferable: None,
+                locked_until: None,
+                royalties: None,
+                reveal_hash: None,
+            };
+            mint(&mut c, authority, 10, args).unwrap();
+        }
+
+        // alice can't move token 2, which bob already holds
+        let args = TransferArgs {
+            from: None,
+            to: bob.clone(),
+            token_ids: [Nat::from(1), Nat::from(2)].into(),
+            memo: None,
+            created_at_time: None,
+            is_atomic: Some(false),
+        };
+        transfer(&mut c, alice.owner, 20, args).unwrap();
+
+        assert_eq!(c.token_history(&Nat::from(1), None, None).len(), 2);
+        assert_eq!(
+            c.token_history(&Nat::from(2), None, None),
+            vec![OwnershipRecord {
+                index: 0,
+                owner: Some(bob.clone()),
+                timestamp: 10,
+                tx_id: 1.into(),
+            }]
+        );
+        assert_eq!(
+            c.owner_of_at(&Nat::from(1), &HistoryPoint::Block(2.into())),
+            Some(bob)
+        );
+    }
+}
@@ -0,0 +1,63 @@
+use ic_kit::prelude::*;
+
+use crate::state::*;
+
+/// arguments of ICRC-1 "icrc1_transfer" ledger method
+#[derive(Debug, Clone, Deserialize, Serialize, CandidType)]
+pub struct Icrc1TransferArgs {
+    pub from_subaccount: Option<Subaccount>,
+    pub to: Account,
+    pub amount: Nat,
+    pub fee: Option<Nat>,
+    pub memo: Option<Vec<u8>>,
+    pub created_at_time: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, CandidType)]
+pub enum Icrc1TransferError {
+    BadFee { expected_fee: Nat },
+    BadBurn { min_burn_amount: Nat },
+    InsufficientFunds { balance: Nat },
+    TooOld,
+    CreatedInFuture { ledger_time: u64 },
+    Duplicate { duplicate_of: Nat },
+    TemporarilyUnavailable,
+    GenericError { error_code: Nat, message: String },
+}
+
+/// arguments of ICRC-2 "icrc2_transfer_from" ledger method
+#[derive(Debug, Clone, Deserialize, Serialize, CandidType)]
+pub struct TransferFromArgs {
+    pub spender_subaccount: Option<Subaccount>,
+    pub from: Account,
+    pub to: Account,
+    pub amount: Nat,
+    pub fee: Option<Nat>,
+    pub memo: Option<Vec<u8>>,
+    pub created_at_time: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, CandidType)]
+pub enum TransferFromError {
+    BadFee { expected_fee: Nat },
+    BadBurn { min_burn_amount: Nat },
+    InsufficientFunds { balance: Nat },
+    InsufficientAllowance { allowance: Nat },
+    TooOld,
+    CreatedInFuture { ledger_time: u64 },
+    Duplicate { duplicate_of: Nat },
+    TemporarilyUnavailable,
+    GenericError { error_code: Nat, message: String },
+}
+
+/// moves tokens with allowance given to the canister, fails with a message if ledger can't be called
+pub async fn transfer_from(
+    ledger: Principal,
+    args: TransferFromArgs,
+) -> Result<Result<Nat, TransferFromError>, String> {
+    CallBuilder::new(ledger, "icrc2_transfer_from")
+        .with_arg(args)
+        .perform_one::<Result<Nat, TransferFromError>>()
+        .await
+        .map_err(|e| format!("ledger call failed: {:?}", e))
+}
@@ -1,6 +1,50 @@
 pub mod state;
 use crate::state::*;
 
+pub mod limits;
+use crate::limits::*;
+
+pub mod maintenance;
+use crate::maintenance::*;
+
+pub mod pause;
+use crate::pause::*;
+
+pub mod denylist;
+use crate::denylist::*;
+
+pub mod ledger;
+
+pub mod public_mint;
+
+pub mod allowlist;
+
+pub mod voucher;
+
+pub mod reveal;
+
+pub mod random;
+
+pub mod airdrop;
+
+pub mod snapshot;
+
+pub mod history;
+
+pub mod transactions;
+
+pub mod upgrade;
+use crate::upgrade::*;
+
+#[cfg(feature = "marketplace")]
+pub mod marketplace;
+
+#[cfg(feature = "marketplace")]
+pub mod auction;
+
+pub mod royalties;
+use crate::royalties::*;
+
 pub mod update;
 pub use crate::update::*;
 
@@ -23,12 +67,29 @@ pub struct InitArgs {
     pub royalties: u16,
     /// royalties recipient
     pub royalty_recipient: Account,
+    /// weighted royalty recipients, replace `royalty_recipient` when calculating payouts
+    pub royalty_recipients: Option<Vec<RoyaltyRecipient>>,
     /// base64 encoded collection image
     pub image: Option<String>,
     /// supply cap on tokens in this collection
     pub supply_cap: Option<usize>,
     /// authority that is able to mint new tokens in this collection
     pub authority: Principal,
+    /// input size limits, defaults are used if not set
+    pub limits: Option<Limits>,
+    /// interval between garbage collection runs in nanoseconds
+    pub gc_interval: Option<u64>,
+    /// instructions garbage collection may spend per heartbeat
+    pub gc_budget: Option<u64>,
+    /// tokens in this collection can not be transferred once minted
+    pub soulbound: Option<bool>,
+    /// authority is able to burn non-transferable tokens
+    pub revocable: Option<bool>,
+    /// transaction deduplication window in nanoseconds, 24 hours if not set
+    pub tx_window: Option<u64>,
+    /// permitted drift between transaction creation time and canister time in nanoseconds,
+    /// 2 minutes if not set
+    pub permitted_drift: Option<u64>,
 }
 
 #[init]
@@ -37,21 +98,50 @@ fn init(c: &mut Collection, args: InitArgs) {
         panic!("royalties must be between 0 and 10000");
     }
 
+    if let Some(ref recipients) = args.royalty_recipients {
+        let royalties = Royalties {
+            basis_points: args.royalties,
+            recipients: recipients.clone(),
+        };
+        if let Err(e) = royalties.validate() {
+            panic!("{}", e);
+        }
+    }
+
     if args.supply_cap.is_some() && args.supply_cap.unwrap() == 0 {
         panic!("supply cap must be greater than 0");
     }
 
+    let limits = args.limits.unwrap_or_default();
+    if limits.max_batch_size == 0 {
+        panic!("max batch size must be greater than 0");
+    }
+
+    let mut maintenance = Maintenance::default();
+    maintenance.interval = args.gc_interval.unwrap_or(maintenance.interval);
+    maintenance.budget = args.gc_budget.unwrap_or(maintenance.budget);
+    if maintenance.budget == 0 {
+        panic!("gc budget must be greater than 0");
+    }
+
     *c = Collection {
         name: args.name,
         symbol: args.symbol.to_uppercase(),
         royalties: args.royalties,
         royalty_recipient: args.royalty_recipient,
+        royalty_recipients: args.royalty_recipients.unwrap_or_default(),
         description: args.description,
         image: args
             .image
             .map(|s| b64.decode(s).expect("decode base64 image")),
         supply_cap: args.supply_cap,
         authority: Some(args.authority),
+        soulbound: args.soulbound.unwrap_or_default(),
+        revocable: args.revocable.unwrap_or_default(),
+        limits,
+        maintenance,
+        tx_window: args.tx_window,
+        permitted_drift: args.permitted_drift,
 
         ..Default::default()
     };
@@ -59,22 +149,40 @@ fn init(c: &mut Collection, args: InitArgs) {
 
 #[pre_upgrade]
 fn pre_upgrade(c: &mut Collection) {
-    let now = ic::time();
-    c.gc(now); // compact the collection before saving
+    // expired entries are left to incremental gc, collecting them all here could exceed
+    // the instruction limit of the upgrade
+
+    // approval indexes are rebuilt by post_upgrade, so they are not saved
+    c.approvals_by_account.clear();
+    c.approvals_count.clear();
+
+    let saved = StableCollection::V1(std::mem::take(c));
 
     #[allow(deprecated)]
-    ic_kit::stable::stable_store((c,)).expect("save state");
+    ic_kit::stable::stable_store((saved,)).expect("save state");
 }
 
 #[post_upgrade]
 fn post_upgrade(c: &mut Collection) {
     #[allow(deprecated)]
-    let (saved_collection,): (Collection,) =
-        ic_kit::stable::stable_restore().expect("restore state");
+    let saved_collection = restore_collection(
+        ic_kit::stable::stable_restore,
+        ic_kit::stable::stable_restore,
+    )
+    .expect("restore state");
 
     *c = saved_collection;
 }
 
+#[heartbeat]
+fn heartbeat(c: &mut Collection) {
+    c.maintenance_tick(ic::time());
+    c.airdrop_tick(ic::time());
+
+    #[cfg(feature = "marketplace")]
+    c.settle_auctions(ic::time(), auction::SETTLE_BUDGET);
+}
+
 #[query]
 fn icrc7_name(collection: &Collection) -> String {
     collection.name.to_owned()
@@ -105,11 +213,79 @@ fn icrc7_royalty_recipient(collection: &Collection) -> Account {
     collection.royalty_recipient.clone()
 }
 
+/// royalties owed for selling token at `sale_price`, none if token does not exist
+#[query]
+fn royalty_info(
+    collection: &Collection,
+    token_id: TokenID,
+    sale_price: Nat,
+) -> Option<Vec<RoyaltyPayment>> {
+    collection
+        .tokens
+        .get(&token_id)
+        .map(|t| collection.royalties_of(t).split(&sale_price))
+}
+
 #[query]
 fn icrc7_supply_cap(collection: &Collection) -> Option<Nat> {
     collection.supply_cap.map(Into::into)
 }
 
+#[query]
+fn icrc7_max_memo_size(collection: &Collection) -> Nat {
+    collection.limits.max_memo_bytes.into()
+}
+
+#[query]
+fn icrc7_max_batch_size(collection: &Collection) -> Nat {
+    collection.limits.max_batch_size.into()
+}
+
+#[query]
+fn icrc7_max_image_size(collection: &Collection) -> Nat {
+    collection.limits.max_image_bytes.into()
+}
+
+#[query]
+fn icrc7_max_name_length(collection: &Collection) -> Nat {
+    collection.limits.max_name_length.into()
+}
+
+#[query]
+fn icrc7_max_approvals_per_owner(collection: &Collection) -> Nat {
+    collection.limits.max_approvals_per_owner.into()
+}
+
+#[query]
+fn gc_stats(collection: &Collection) -> Option<GcRun> {
+    collection.maintenance.last_run.clone()
+}
+
+#[query]
+fn icrc7_tx_window(collection: &Collection) -> Nat {
+    collection.tx_window().into()
+}
+
+#[query]
+fn icrc7_permitted_drift(collection: &Collection) -> Nat {
+    collection.permitted_drift().into()
+}
+
+#[query]
+fn pause_state(collection: &Collection) -> PauseState {
+    collection.pause.state.clone()
+}
+
+#[query]
+fn is_denied(collection: &Collection, account: Account) -> bool {
+    collection.denylist.contains(&account)
+}
+
+#[query]
+fn denylist_log(collection: &Collection) -> Vec<DenylistEntry> {
+    collection.denylist.log.clone()
+}
+
 #[query]
 fn icrc7_total_supply(collection: &Collection) -> Nat {
     collection.tokens.len().into()
@@ -121,6 +297,8 @@ fn icrc7_metadata(collection: &Collection, id: TokenID) -> Option<TokenMetadata>
         icrc7_id: t.id.clone(),
         icrc7_name: t.name.clone(),
         icrc7_image: t.image.clone(),
+        icrc7_transferable: collection.is_transferable(t),
+        icrc7_revealed: t.reveal_hash.is_none(),
     })
 }
 
@@ -149,6 +327,28 @@ fn icrc7_tokens_of(collection: &Collection, owner: Account) -> Vec<TokenID> {
         .collect()
 }
 
+#[derive(Debug, PartialEq, Eq, Deserialize, Serialize, CandidType)]
+pub struct LockedToken {
+    pub id: TokenID,
+    pub locked_until: u64,
+}
+
+#[query]
+fn icrc7_locked_tokens_of(collection: &Collection, owner: Account) -> Vec<LockedToken> {
+    let now = ic::time();
+    collection
+        .tokens
+        .values()
+        .filter(|t| t.owner == owner.to_canonical())
+        .filter_map(|t| {
+            t.locked_at(now).map(|locked_until| LockedToken {
+                id: t.id.clone(),
+                locked_until,
+            })
+        })
+        .collect()
+}
+
 #[derive(Debug, Deserialize, Serialize, CandidType, PartialEq)]
 pub struct CollectionMetadata {
     pub icrc7_name: String,
@@ -166,6 +366,9 @@ pub struct TokenMetadata {
     pub icrc7_id: TokenID,
     pub icrc7_name: String,
     pub icrc7_image: Vec<u8>,
+    pub icrc7_transferable: bool,
+    /// false while token shows placeholder metadata
+    pub icrc7_revealed: bool,
 }
 
 #[query]
@@ -0,0 +1,67 @@
+use ic_kit::prelude::*;
+
+/// bounds on user supplied input, so a single call can't bloat state or exhaust instruction limit
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, CandidType)]
+pub struct Limits {
+    /// max memo length in bytes
+    pub max_memo_bytes: usize,
+    /// max number of token ids in a single transfer or approval
+    pub max_batch_size: usize,
+    /// max size of decoded token image in bytes
+    pub max_image_bytes: usize,
+    /// max token name length in bytes
+    pub max_name_length: usize,
+    /// max number of approvals a single account can hold (including expired ones until gc)
+    pub max_approvals_per_owner: usize,
+    /// max number of recipients of a single airdrop
+    pub max_airdrop_entries: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_memo_bytes: 32,
+            max_batch_size: 100,
+            max_image_bytes: 256 * 1024,
+            max_name_length: 128,
+            max_approvals_per_owner: 100,
+            max_airdrop_entries: 10_000,
+        }
+    }
+}
+
+impl Limits {
+    pub fn check_memo(&self, memo: &Option<Vec<u8>>) -> Result<(), String> {
+        let len = memo.as_ref().map_or(0, Vec::len);
+        check("memo", len, self.max_memo_bytes)
+    }
+
+    pub fn check_batch(&self, len: usize) -> Result<(), String> {
+        check("batch", len, self.max_batch_size)
+    }
+
+    pub fn check_name(&self, name: &str) -> Result<(), String> {
+        check("name", name.len(), self.max_name_length)
+    }
+
+    pub fn check_image(&self, len: usize) -> Result<(), String> {
+        check("image", len, self.max_image_bytes)
+    }
+
+    pub fn check_airdrop(&self, len: usize) -> Result<(), String> {
+        check("airdrop", len, self.max_airdrop_entries)
+    }
+
+    /// checks that account holding `count` approvals can add one more
+    pub fn check_approvals(&self, count: usize) -> Result<(), String> {
+        check("approvals", count + 1, self.max_approvals_per_owner)
+    }
+}
+
+fn check(what: &str, len: usize, max: usize) -> Result<(), String> {
+    if len > max {
+        return Err(format!("{} is too large: {} > {}", what, len, max));
+    }
+
+    Ok(())
+}
@@ -0,0 +1,337 @@
+//! Periodic garbage collection of expired transfers and approvals.
+//!
+//! Runs from heartbeat rather than `ic_cdk_timers::set_timer_interval`: ic-kit 0.5 has no way to
+//! export the global timer entry point those timers rely on. A heartbeat between runs only
+//! compares `last_run` with `interval`, and a run stops taking new entries once the heartbeat has
+//! spent `budget` instructions on it, so the cost per message stays bounded either way.
+
+use ic_kit::prelude::*;
+
+use crate::state::*;
+
+/// state of periodic garbage collection, which runs incrementally from heartbeat
+#[derive(Debug, Clone, Deserialize, Serialize, CandidType)]
+pub struct Maintenance {
+    /// minimal interval between starts of gc runs in nanoseconds
+    pub interval: u64,
+    /// instructions a single tick may spend, the entry being examined when it runs out is
+    /// still finished
+    pub budget: u64,
+    /// last completed gc run
+    pub last_run: Option<GcRun>,
+    /// gc run that is still in progress
+    pub current_run: Option<GcRun>,
+    /// approval id next tick resumes scanning from
+    pub approval_cursor: ApprovalID,
+}
+
+impl Default for Maintenance {
+    fn default() -> Self {
+        Maintenance {
+            interval: 60 * 60 * 1_000_000_000, // 1 hour
+            budget: 1_000_000_000,
+            last_run: None,
+            current_run: None,
+            approval_cursor: ApprovalID::from(0),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize, CandidType)]
+pub struct GcRun {
+    pub started_at: u64,
+    pub finished_at: Option<u64>,
+    /// number of ticks it took to finish the run
+    pub ticks: u64,
+    pub transfers_removed: u64,
+    pub approvals_removed: u64,
+}
+
+/// arguments for the "set_gc_settings" method, unset values are left unchanged
+#[derive(Debug, Clone, Deserialize, Serialize, CandidType)]
+pub struct GcSettings {
+    /// minimal interval between starts of gc runs in nanoseconds
+    pub interval: Option<u64>,
+    /// instructions a single heartbeat may spend on gc
+    pub budget: Option<u64>,
+}
+
+/// result of a single incremental gc step
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct GcStep {
+    pub transfers_removed: u64,
+    pub approvals_removed: u64,
+    /// true if the whole collection was covered and next step starts over
+    pub done: bool,
+}
+
+impl Collection {
+    /// examines transfers and approvals until `has_budget` returns false, purging expired ones,
+    /// it is asked after every entry, so each step makes progress
+    pub fn gc_step<F>(&mut self, now: u64, mut has_budget: F) -> GcStep
+    where
+        F: FnMut() -> bool,
+    {
+        let mut step = GcStep::default();
+
+        // transfers are ordered by timestamp, so expired ones are always in front
+        let cutoff = now.saturating_sub(self.tx_window());
+        loop {
+            match self.transfers.first_key_value() {
+                Some(((created_at, _), _)) if *created_at < cutoff => {
+                    let ((_, id), transfer) = self.transfers.pop_first().unwrap();
+                    self.unindex_transfer(&id, &transfer);
+                    step.transfers_removed += 1;
+
+                    if !has_budget() {
+                        return step;
+                    }
+                }
+                _ => break,
+            }
+        }
+
+        loop {
+            let next = self
+                .approvals
+                .range(self.maintenance.approval_cursor.clone()..)
+                .next()
+                .map(|(id, a)| (id.clone(), a.is_expired(now)));
+
+            let (id, expired) = match next {
+                Some(next) => next,
+                None => break,
+            };
+
+            if expired {
+                self.remove_approval(&id);
+                step.approvals_removed += 1;
+            }
+            let mut next = id;
+            next += 1;
+            self.maintenance.approval_cursor = next;
+
+            if !has_budget() {
+                return step;
+            }
+        }
+
+        step.done = true;
+        self.maintenance.approval_cursor = ApprovalID::from(0);
+
+        step
+    }
+
+    /// changes gc settings on behalf of `caller`
+    pub fn set_gc_settings(&mut self, caller: &Principal, args: GcSettings) -> Result<(), String> {
+        self.check_authority(caller)?;
+
+        if args.budget == Some(0) {
+            return Err("gc budget must be greater than 0".to_owned());
+        }
+
+        let m = &mut self.maintenance;
+        m.interval = args.interval.unwrap_or(m.interval);
+        m.budget = args.budget.unwrap_or(m.budget);
+
+        Ok(())
+    }
+
+    /// called on every heartbeat, starts new gc run once interval has passed
+    /// and advances the current one by a single step
+    pub fn maintenance_tick(&mut self, now: u64) {
+        let m = &mut self.maintenance;
+        if m.current_run.is_none() {
+            let due = match m.last_run {
+                Some(ref run) => now >= run.started_at.saturating_add(m.interval),
+                None => true,
+            };
+            if !due {
+                return;
+            }
+
+            m.current_run = Some(GcRun {
+                started_at: now,
+                ..Default::default()
+            });
+        }
+
+        let start = instruction_counter();
+        let budget = self.maintenance.budget;
+        let step = self.gc_step(now, || instruction_counter() - start < budget);
+
+        let m = &mut self.maintenance;
+        let run = m.current_run.as_mut().unwrap();
+        run.ticks += 1;
+        run.transfers_removed += step.transfers_removed;
+        run.approvals_removed += step.approvals_removed;
+
+        if step.done {
+            run.finished_at = Some(now);
+            m.last_run = m.current_run.take();
+        }
+    }
+}
+
+/// instructions executed by the current message so far
+#[cfg(target_arch = "wasm32")]
+pub fn instruction_counter() -> u64 {
+    #[link(wasm_import_module = "ic0")]
+    extern "C" {
+        fn performance_counter(counter_type: u32) -> u64;
+    }
+
+    // counter type 0 counts instructions of the current message
+    unsafe { performance_counter(0) }
+}
+
+/// ic-kit-runtime does not count instructions, so native builds are never out of budget
+#[cfg(not(target_arch = "wasm32"))]
+pub fn instruction_counter() -> u64 {
+    0
+}
+
+/// changes interval between gc runs and instructions spent on gc per heartbeat
+#[update]
+pub fn set_gc_settings(c: &mut Collection, args: GcSettings) -> Result<(), String> {
+    c.set_gc_settings(&caller(), args)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// budget that allows `n` entries per step
+    fn entries(n: usize) -> impl FnMut() -> bool {
+        let mut left = n;
+        move || {
+            left -= 1;
+            left > 0
+        }
+    }
+
+    fn expired_approval(expires_at: u64) -> Approval {
+        Approval {
+            from: Principal::anonymous(),
+            from_subaccount: None,
+            to: Principal::anonymous(),
+            token_ids: None,
+            expires_at: Some(expires_at),
+            memo: None,
+        }
+    }
+
+    #[test]
+    fn test_gc_step() {
+        let mut c = Collection::default();
+        let now = 10 * TX_DEDUPLICATION_WINDOW;
+
+        for i in 0..5 {
+            c.add_transfer(
+                Transfer {
+                    created_at: now - TX_DEDUPLICATION_WINDOW - 5 + i,
+                    ..Default::default()
+                },
+                vec![],
+                now,
+            );
+        }
+        for i in 0..5 {
+            c.add_approval(expired_approval(now - 1 + i * 2));
+        }
+
+        // 5 transfers, 1 approval examined
+        assert_eq!(
+            c.gc_step(now, entries(6)),
+            GcStep {
+                transfers_removed: 5,
+                approvals_removed: 1,
+                done: false,
+            }
+        );
+
+        // remaining 4 approvals, only first one of them is expired
+        assert_eq!(
+            c.gc_step(now + 2, entries(10)),
+            GcStep {
+                transfers_removed: 0,
+                approvals_removed: 1,
+                done: true,
+            }
+        );
+
+        assert!(c.transfers.is_empty());
+        assert_eq!(c.approvals.len(), 3);
+        assert_eq!(c.approvals_by_account.values().flatten().count(), 3);
+        assert_eq!(
+            c.approvals_count[&Account::from_owner(Principal::anonymous())],
+            3
+        );
+        assert_eq!(c.maintenance.approval_cursor, 0);
+    }
+
+    #[test]
+    fn test_maintenance_tick() {
+        let authority = Principal::from_slice(&[0x1]);
+        let mut c = Collection {
+            authority: Some(authority),
+            ..Default::default()
+        };
+
+        let settings = GcSettings {
+            interval: Some(100),
+            budget: None,
+        };
+        assert!(c
+            .set_gc_settings(&Principal::anonymous(), settings.clone())
+            .is_err());
+        c.set_gc_settings(&authority, settings).unwrap();
+        assert_eq!(c.maintenance.interval, 100);
+
+        let now = 10 * TX_DEDUPLICATION_WINDOW;
+        for i in 0..3 {
+            c.add_approval(expired_approval(now - 1 - i));
+        }
+
+        // instructions are not counted outside of the canister, so a single tick finishes the run
+        c.maintenance_tick(now);
+        assert_eq!(
+            c.maintenance.last_run,
+            Some(GcRun {
+                started_at: now,
+                finished_at: Some(now),
+                ticks: 1,
+                transfers_removed: 0,
+                approvals_removed: 3,
+            })
+        );
+        assert!(c.approvals.is_empty());
+
+        // interval has not passed yet
+        c.maintenance_tick(now + 50);
+        assert_eq!(c.maintenance.last_run.as_ref().unwrap().started_at, now);
+
+        c.maintenance_tick(now + 100);
+        assert_eq!(
+            c.maintenance.last_run.as_ref().unwrap().started_at,
+            now + 100
+        );
+    }
+
+    #[test]
+    fn test_gc_step_progress() {
+        let mut c = Collection::default();
+        let now = 10 * TX_DEDUPLICATION_WINDOW;
+        for i in 0..3 {
+            c.add_approval(expired_approval(now - 1 - i));
+        }
+
+        // every step examines at least one entry, even without budget
+        let mut steps = 0;
+        while !c.gc_step(now, || false).done {
+            steps += 1;
+        }
+        assert_eq!(steps, 3);
+        assert!(c.approvals.is_empty());
+    }
+}
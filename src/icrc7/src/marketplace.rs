@@ -0,0 +1,702 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use ic_kit::prelude::*;
+
+use crate::auction::Auction;
+use crate::ledger::*;
+use crate::state::*;
+
+/// token put up for sale, it stays with the seller but can't be transferred until sold or delisted
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, CandidType)]
+pub struct Listing {
+    pub seller: Account,
+    pub price: Nat,
+    pub created_at: u64,
+    /// payment for this listing is in flight
+    pub pending: bool,
+}
+
+/// fixed-price sales paid with tokens of ICRC-2 ledger
+#[derive(Debug, Clone, Default, Deserialize, Serialize, CandidType)]
+pub struct Marketplace {
+    pub ledger: Option<Principal>,
+    pub listings: HashMap<TokenID, Listing>,
+    pub auctions: HashMap<TokenID, Auction>,
+    /// auctions ordered by end time, so settlement only looks at the ones that ended
+    pub auction_deadlines: BTreeSet<(u64, TokenID)>,
+    /// ledger tokens held by the canister on behalf of sellers and royalty recipients
+    pub proceeds: HashMap<Account, Nat>,
+}
+
+/// arguments for the "list_token" method
+#[derive(Debug, Clone, Deserialize, Serialize, CandidType)]
+pub struct ListArgs {
+    pub token_id: TokenID,
+    /// price in ledger tokens
+    pub price: Nat,
+    pub from_subaccount: Option<Subaccount>,
+}
+
+/// arguments for the "buy_token" method
+#[derive(Debug, Clone, Deserialize, Serialize, CandidType)]
+pub struct BuyArgs {
+    pub token_id: TokenID,
+    /// price buyer agrees to pay, purchase fails if listing price differs
+    pub price: Nat,
+    /// buyer's subaccount payment is taken from
+    pub from_subaccount: Option<Subaccount>,
+    /// token recipient, buyer's account if not set
+    pub to: Option<Account>,
+}
+
+/// arguments for the "withdraw_proceeds" method
+#[derive(Debug, Clone, Deserialize, Serialize, CandidType)]
+pub struct WithdrawArgs {
+    pub from_subaccount: Option<Subaccount>,
+    /// ledger account to send proceeds to, caller's account if not set
+    pub to: Option<Account>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, CandidType)]
+pub enum BuyError {
+    NotListed,
+    PriceMismatch { price: Nat },
+    BidTooLow { min_bid: Nat },
+    TemporarilyUnavailable,
+    PaymentFailed(TransferFromError),
+    GenericError { message: String },
+}
+
+impl Collection {
+    /// puts token of `caller` up for sale
+    pub fn list_token(
+        &mut self,
+        caller: Principal,
+        now: u64,
+        args: ListArgs,
+    ) -> Result<(), String> {
+        if self.marketplace.ledger.is_none() {
+            return Err("payment ledger is not configured".to_owned());
+        }
+
+        if self.pause.state.transfers {
+            return Err("transfers are paused".to_owned());
+        }
+
+        if args.price == 0 {
+            return Err("price must be greater than 0".to_owned());
+        }
+
+        let seller = Account::new(caller, args.from_subaccount).to_canonical();
+        self.check_listable(&seller, &args.token_id, now)?;
+
+        self.marketplace.listings.insert(
+            args.token_id,
+            Listing {
+                seller,
+                price: args.price,
+                created_at: now,
+                pending: false,
+            },
+        );
+
+        Ok(())
+    }
+
+    /// checks that `seller` can put token up for sale or auction, token stays with the seller
+    /// but can't be transferred while it is listed or auctioned
+    pub(crate) fn check_listable(
+        &self,
+        seller: &Account,
+        token_id: &TokenID,
+        now: u64,
+    ) -> Result<(), String> {
+        if self.denylist.contains(seller) {
+            return Err("account is denylisted".to_owned());
+        }
+
+        let token = match self.tokens.get(token_id) {
+            Some(token) => token,
+            None => return Err(format!("token with id {} does not exist", token_id)),
+        };
+
+        if token.owner != *seller {
+            return Err(format!("token with id {} is not owned by caller", token_id));
+        }
+
+        if self.in_escrow(token_id) {
+            return Err(format!("token with id {} is already listed", token_id));
+        }
+
+        if !self.is_transferable(token) {
+            return Err(format!("token with id {} is not transferable", token_id));
+        }
+
+        if let Some(until) = token.locked_at(now) {
+            return Err(format!(
+                "token with id {} is locked until {}",
+                token_id, until
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// token is listed or auctioned, so only the marketplace can move it
+    pub fn in_escrow(&self, token_id: &TokenID) -> bool {
+        self.marketplace.listings.contains_key(token_id)
+            || self.marketplace.auctions.contains_key(token_id)
+    }
+
+    /// takes token off sale, can be called by seller or authority
+    pub fn delist_token(&mut self, caller: Principal, token_id: &TokenID) -> Result<(), String> {
+        let listing = match self.marketplace.listings.get(token_id) {
+            Some(listing) => listing,
+            None => return Err(format!("token with id {} is not listed", token_id)),
+        };
+
+        if listing.seller.owner != caller && self.check_authority(&caller).is_err() {
+            return Err("only seller or authority can delist token".to_owned());
+        }
+
+        if listing.pending {
+            return Err("token is being sold".to_owned());
+        }
+
+        self.marketplace.listings.remove(token_id);
+
+        Ok(())
+    }
+
+    /// marks listing as pending, so it can't be bought or delisted until payment is done,
+    /// returns price and ledger to pull it from
+    pub fn reserve_listing(
+        &mut self,
+        buyer: &Account,
+        to: &Account,
+        now: u64,
+        args: &BuyArgs,
+    ) -> Result<(Nat, Principal), BuyError> {
+        if self.pause.state.transfers {
+            return Err(BuyError::TemporarilyUnavailable);
+        }
+
+        let ledger = match self.marketplace.ledger {
+            Some(ledger) => ledger,
+            None => {
+                return Err(BuyError::GenericError {
+                    message: "payment ledger is not configured".to_owned(),
+                })
+            }
+        };
+
+        let listing = match self.marketplace.listings.get(&args.token_id) {
+            Some(listing) => listing,
+            None => return Err(BuyError::NotListed),
+        };
+
+        if listing.pending {
+            return Err(BuyError::TemporarilyUnavailable);
+        }
+
+        if listing.price != args.price {
+            return Err(BuyError::PriceMismatch {
+                price: listing.price.clone(),
+            });
+        }
+
+        if listing.seller == *buyer {
+            return Err(BuyError::GenericError {
+                message: "can't buy own token".to_owned(),
+            });
+        }
+
+        self.check_sale(&args.token_id, &listing.seller, buyer, to, now)?;
+
+        let listing = self.marketplace.listings.get_mut(&args.token_id).unwrap();
+        listing.pending = true;
+
+        Ok((listing.price.clone(), ledger))
+    }
+
+    /// checks that token of `seller` can be sold to `buyer` and handed to `to` at time `now`,
+    /// done before payment is pulled and again once it arrives, as state may change in between
+    pub(crate) fn check_sale(
+        &self,
+        token_id: &TokenID,
+        seller: &Account,
+        buyer: &Account,
+        to: &Account,
+        now: u64,
+    ) -> Result<(), BuyError> {
+        if self.pause.state.transfers {
+            return Err(BuyError::TemporarilyUnavailable);
+        }
+
+        if [buyer, to, seller]
+            .iter()
+            .any(|acc| self.denylist.contains(acc))
+        {
+            return Err(BuyError::GenericError {
+                message: "account is denylisted".to_owned(),
+            });
+        }
+
+        if let Some(until) = self.tokens.get(token_id).and_then(|t| t.locked_at(now)) {
+            return Err(BuyError::GenericError {
+                message: format!("token is locked until {}", until),
+            });
+        }
+
+        Ok(())
+    }
+
+    /// makes listing available again after failed payment
+    pub fn release_listing(&mut self, token_id: &TokenID) {
+        if let Some(listing) = self.marketplace.listings.get_mut(token_id) {
+            listing.pending = false;
+        }
+    }
+
+    /// completes paid sale of listed token, if the sale is no longer allowed payment is credited
+    /// back to proceeds of `buyer` and listing is released
+    pub fn settle_sale(
+        &mut self,
+        token_id: &TokenID,
+        buyer: Account,
+        to: Account,
+        now: u64,
+    ) -> Result<TransferID, BuyError> {
+        let listing = self
+            .marketplace
+            .listings
+            .get(token_id)
+            .cloned()
+            .expect("settled listing must exist");
+
+        if let Err(e) = self.check_sale(token_id, &listing.seller, &buyer, &to, now) {
+            self.credit_proceeds(buyer, listing.price);
+            self.release_listing(token_id);
+            return Err(e);
+        }
+
+        self.marketplace.listings.remove(token_id);
+        Ok(self.complete_sale(token_id, listing.seller, &listing.price, to, now))
+    }
+
+    /// hands listed token to `to` and credits seller and royalty recipients with `price`
+    pub(crate) fn complete_sale(
+        &mut self,
+        token_id: &TokenID,
+        seller: Account,
+        price: &Nat,
+        to: Account,
+        now: u64,
+    ) -> TransferID {
+        let mut royalties_paid = Nat::from(0);
+        if let Some(token) = self.tokens.get(token_id) {
+            for payment in self.royalties_of(token).split(price) {
+                royalties_paid = Nat(royalties_paid.0 + &payment.amount.0);
+                self.credit_proceeds(payment.recipient, payment.amount);
+            }
+        }
+
+        let seller_amount = Nat(price.0.clone() - royalties_paid.0);
+        self.credit_proceeds(seller.clone(), seller_amount);
+
+        if let Some(token) = self.tokens.get_mut(token_id) {
+            token.owner = to.clone();
+        }
+
+        self.add_transfer(
+            Transfer {
+                from: seller,
+                to,
+                token_ids: HashSet::from([token_id.clone()]),
+                created_at: now,
+                ..Default::default()
+            },
+            vec![token_id.clone()],
+            now,
+        )
+    }
+
+    /// changes payment ledger, only while the canister holds no funds of the current one,
+    /// otherwise they would be paid out in a different token
+    pub fn set_payment_ledger(
+        &mut self,
+        caller: &Principal,
+        ledger: Principal,
+    ) -> Result<(), String> {
+        self.check_authority(caller)?;
+
+        let m = &self.marketplace;
+        if !m.proceeds.is_empty() {
+            return Err("proceeds must be withdrawn before changing ledger".to_owned());
+        }
+
+        if m.listings.values().any(|l| l.pending)
+            || m.auctions.values().any(|a| a.highest_bid.is_some())
+        {
+            return Err("payments are in progress".to_owned());
+        }
+
+        self.marketplace.ledger = Some(ledger);
+        Ok(())
+    }
+
+    pub fn credit_proceeds(&mut self, account: Account, amount: Nat) {
+        if amount == 0 {
+            return;
+        }
+
+        let balance = self
+            .marketplace
+            .proceeds
+            .entry(account.to_canonical())
+            .or_insert_with(|| Nat::from(0));
+        balance.0 += amount.0;
+    }
+}
+
+/// account that holds payments
+pub(crate) fn escrow_account() -> Account {
+    Account::from_owner(ic::id())
+}
+
+#[update]
+pub fn set_payment_ledger(c: &mut Collection, ledger: Principal) -> Result<(), String> {
+    c.set_payment_ledger(&caller(), ledger)
+}
+
+#[update]
+pub fn list_token(c: &mut Collection, args: ListArgs) -> Result<(), String> {
+    c.list_token(caller(), ic::time(), args)
+}
+
+#[update]
+pub fn delist_token(c: &mut Collection, token_id: TokenID) -> Result<(), String> {
+    c.delist_token(caller(), &token_id)
+}
+
+/// buys listed token, payment is pulled from the buyer with "icrc2_transfer_from",
+/// so buyer has to approve canister to spend the price plus ledger fee beforehand
+#[update]
+pub async fn buy_token(args: BuyArgs) -> Result<TransferID, BuyError> {
+    let buyer = Account::new(caller(), args.from_subaccount).to_canonical();
+    let to = args.to.clone().unwrap_or(buyer.clone()).to_canonical();
+
+    let (price, ledger) =
+        ic::with_mut(|c: &mut Collection| c.reserve_listing(&buyer, &to, ic::time(), &args))?;
+
+    // state is only changed once payment succeeds, so the sale is either settled or refunded
+    match pull_payment(ledger, buyer.clone(), price).await {
+        Ok(()) => {
+            ic::with_mut(|c: &mut Collection| c.settle_sale(&args.token_id, buyer, to, ic::time()))
+        }
+        Err(e) => {
+            ic::with_mut(|c: &mut Collection| c.release_listing(&args.token_id));
+            Err(e)
+        }
+    }
+}
+
+/// moves `amount` from `from` to the escrow account, using allowance given to the canister
+pub(crate) async fn pull_payment(
+    ledger: Principal,
+    from: Account,
+    amount: Nat,
+) -> Result<(), BuyError> {
+    let payment = TransferFromArgs {
+        spender_subaccount: None,
+        from,
+        to: escrow_account(),
+        amount,
+        fee: None,
+        memo: None,
+        created_at_time: None,
+    };
+
+    transfer_from(ledger, payment)
+        .await
+        .map_err(|message| BuyError::GenericError { message })?
+        .map(|_| ())
+        .map_err(BuyError::PaymentFailed)
+}
+
+/// sends proceeds of caller's account to the ledger, ledger fee is deducted from the amount
+/// proceeds are restored on failure, unless the ledger call was rejected after it may have
+/// made the transfer
+#[update]
+pub async fn withdraw_proceeds(args: WithdrawArgs) -> Result<Nat, String> {
+    let account = Account::new(caller(), args.from_subaccount).to_canonical();
+    let to = args.to.unwrap_or(account.clone());
+
+    let ledger = ic::with(|c: &Collection| c.marketplace.ledger)
+        .ok_or("payment ledger is not configured")?;
+
+    // take proceeds before the call, so they can't be withdrawn twice
+    let amount = ic::with_mut(|c: &mut Collection| c.marketplace.proceeds.remove(&account))
+        .ok_or("nothing to withdraw")?;
+
+    match withdraw(ledger, to, amount.clone(), ic::time()).await {
+        Ok(block) => Ok(block),
+        Err(e) => {
+            if e.refund {
+                ic::with_mut(|c: &mut Collection| c.credit_proceeds(account, amount));
+            }
+            Err(e.message)
+        }
+    }
+}
+
+/// failed withdrawal, proceeds are only restored if the ledger surely did not transfer them
+struct WithdrawError {
+    message: String,
+    refund: bool,
+}
+
+impl WithdrawError {
+    fn refund(message: String) -> Self {
+        WithdrawError {
+            message,
+            refund: true,
+        }
+    }
+}
+
+async fn withdraw(
+    ledger: Principal,
+    to: Account,
+    amount: Nat,
+    now: u64,
+) -> Result<Nat, WithdrawError> {
+    let fee = CallBuilder::new(ledger, "icrc1_fee")
+        .perform_one::<Nat>()
+        .await
+        .map_err(|e| WithdrawError::refund(format!("ledger call failed: {:?}", e)))?;
+
+    if amount <= fee {
+        return Err(WithdrawError::refund(format!(
+            "proceeds do not cover ledger fee of {}",
+            fee
+        )));
+    }
+
+    // ledger deduplicates transfers with the same creation time
+    let args = Icrc1TransferArgs {
+        from_subaccount: None,
+        to,
+        amount: Nat(amount.0 - &fee.0),
+        fee: Some(fee),
+        memo: None,
+        created_at_time: Some(now),
+    };
+
+    let result = CallBuilder::new(ledger, "icrc1_transfer")
+        .with_arg(args)
+        .perform_one::<Result<Nat, Icrc1TransferError>>()
+        .await
+        // reject may come after the ledger executed the transfer
+        .map_err(|e| WithdrawError {
+            message: format!("ledger call failed, outcome unknown: {:?}", e),
+            refund: false,
+        })?;
+
+    match result {
+        Ok(block) => Ok(block),
+        // same transfer was already made
+        Err(Icrc1TransferError::Duplicate { duplicate_of }) => Ok(duplicate_of),
+        Err(e) => Err(WithdrawError::refund(format!("transfer failed: {:?}", e))),
+    }
+}
+
+#[query]
+fn payment_ledger(c: &Collection) -> Option<Principal> {
+    c.marketplace.ledger
+}
+
+#[query]
+fn get_listing(c: &Collection, token_id: TokenID) -> Option<Listing> {
+    c.marketplace.listings.get(&token_id).cloned()
+}
+
+#[query]
+fn proceeds_of(c: &Collection, account: Account) -> Nat {
+    c.marketplace
+        .proceeds
+        .get(&account.to_canonical())
+        .cloned()
+        .unwrap_or_else(|| Nat::from(0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::royalties::RoyaltyRecipient;
+
+    fn listed_collection(seller: &Account) -> Collection {
+        let mut c = Collection {
+            royalties: 1000,
+            royalty_recipients: vec![RoyaltyRecipient {
+                account: Account::from_owner(Principal::from_slice(&[0x9])),
+                weight: 1,
+            }],
+            ..Default::default()
+        };
+        c.marketplace.ledger = Some(Principal::from_slice(&[0x8]));
+        c.add_token(Token {
+            id: 1.into(),
+            name: "NFT-1".to_owned(),
+            image: vec![],
+            owner: seller.clone(),
+            transferable: true,
+            locked_until: None,
+            royalties: None,
+            reveal_hash: None,
+        });
+
+        let args = ListArgs {
+            token_id: 1.into(),
+            price: 1000.into(),
+            from_subaccount: None,
+        };
+        c.list_token(seller.owner, 0, args).unwrap();
+
+        c
+    }
+
+    #[test]
+    fn test_sale() {
+        let seller = Account::from_owner(Principal::from_slice(&[0x1]));
+        let buyer = Account::from_owner(Principal::from_slice(&[0x2]));
+        let mut c = listed_collection(&seller);
+
+        // seller keeps the token, but can't list it twice
+        assert_eq!(c.tokens[&Nat::from(1)].owner, seller);
+        assert!(c.in_escrow(&1.into()));
+        let args = ListArgs {
+            token_id: 1.into(),
+            price: 500.into(),
+            from_subaccount: None,
+        };
+        assert!(c.list_token(seller.owner, 0, args).is_err());
+
+        let args = BuyArgs {
+            token_id: 1.into(),
+            price: 1000.into(),
+            from_subaccount: None,
+            to: None,
+        };
+        c.reserve_listing(&buyer, &buyer, 0, &args).unwrap();
+
+        // listing can't be bought or delisted while payment is in flight
+        assert_eq!(
+            c.reserve_listing(&buyer, &buyer, 0, &args),
+            Err(BuyError::TemporarilyUnavailable)
+        );
+        assert!(c.delist_token(seller.owner, &1.into()).is_err());
+
+        c.settle_sale(&1.into(), buyer.clone(), buyer.clone(), 0)
+            .unwrap();
+
+        assert_eq!(c.tokens[&Nat::from(1)].owner, buyer);
+        assert!(c.marketplace.listings.is_empty());
+        assert_eq!(c.marketplace.proceeds[&seller], Nat::from(900));
+        assert_eq!(
+            c.marketplace.proceeds[&Account::from_owner(Principal::from_slice(&[0x9]))],
+            Nat::from(100)
+        );
+        assert_eq!(c.transfers.len(), 1);
+        assert_eq!(
+            c.owner_of_at(&1.into(), &crate::history::HistoryPoint::Block(0.into())),
+            Some(buyer)
+        );
+    }
+
+    #[test]
+    fn test_sale_refund() {
+        let seller = Account::from_owner(Principal::from_slice(&[0x1]));
+        let buyer = Account::from_owner(Principal::from_slice(&[0x2]));
+        let mut c = listed_collection(&seller);
+
+        let args = BuyArgs {
+            token_id: 1.into(),
+            price: 1000.into(),
+            from_subaccount: None,
+            to: None,
+        };
+
+        // transfers were paused while payment was in flight
+        c.reserve_listing(&buyer, &buyer, 0, &args).unwrap();
+        c.pause.state.transfers = true;
+        assert_eq!(
+            c.settle_sale(&1.into(), buyer.clone(), buyer.clone(), 0),
+            Err(BuyError::TemporarilyUnavailable)
+        );
+        c.pause.state.transfers = false;
+
+        // token was locked while payment was in flight
+        c.reserve_listing(&buyer, &buyer, 0, &args).unwrap();
+        c.tokens.get_mut(&Nat::from(1)).unwrap().locked_until = Some(100);
+        assert!(c
+            .settle_sale(&1.into(), buyer.clone(), buyer.clone(), 0)
+            .is_err());
+
+        assert_eq!(c.tokens[&Nat::from(1)].owner, seller);
+        assert!(!c.marketplace.listings[&Nat::from(1)].pending);
+        assert_eq!(c.marketplace.proceeds[&buyer], Nat::from(2000));
+        assert!(!c.marketplace.proceeds.contains_key(&seller));
+        assert!(c.transfers.is_empty());
+    }
+
+    #[test]
+    fn test_set_payment_ledger() {
+        let seller = Account::from_owner(Principal::from_slice(&[0x1]));
+        let buyer = Account::from_owner(Principal::from_slice(&[0x2]));
+        let mut c = listed_collection(&seller);
+        let authority = Principal::from_slice(&[0x3]);
+        c.authority = Some(authority);
+        let ledger = Principal::from_slice(&[0x4]);
+
+        let args = BuyArgs {
+            token_id: 1.into(),
+            price: 1000.into(),
+            from_subaccount: None,
+            to: None,
+        };
+        c.reserve_listing(&buyer, &buyer, 0, &args).unwrap();
+        assert!(c.set_payment_ledger(&seller.owner, ledger).is_err());
+        assert!(c.set_payment_ledger(&authority, ledger).is_err());
+
+        c.settle_sale(&1.into(), buyer.clone(), buyer, 0).unwrap();
+        assert!(c.set_payment_ledger(&authority, ledger).is_err());
+
+        c.marketplace.proceeds.clear();
+        c.set_payment_ledger(&authority, ledger).unwrap();
+        assert_eq!(c.marketplace.ledger, Some(ledger));
+    }
+
+    #[test]
+    fn test_delist() {
+        let seller = Account::from_owner(Principal::from_slice(&[0x1]));
+        let mut c = listed_collection(&seller);
+
+        let buyer = Account::from_owner(Principal::from_slice(&[0x2]));
+        let args = BuyArgs {
+            token_id: 1.into(),
+            price: 1000.into(),
+            from_subaccount: None,
+            to: None,
+        };
+        c.reserve_listing(&buyer, &buyer, 0, &args).unwrap();
+        c.release_listing(&1.into());
+
+        assert!(c.delist_token(buyer.owner, &1.into()).is_err());
+        c.delist_token(seller.owner, &1.into()).unwrap();
+
+        assert_eq!(c.tokens[&Nat::from(1)].owner, seller);
+        assert!(c.marketplace.listings.is_empty());
+        assert!(!c.in_escrow(&1.into()));
+    }
+}
@@ -0,0 +1,49 @@
+use std::collections::HashSet;
+
+use ic_kit::prelude::*;
+
+use crate::state::*;
+
+/// operations that are currently paused
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize, CandidType)]
+pub struct PauseState {
+    pub transfers: bool,
+    pub approvals: bool,
+    pub minting: bool,
+}
+
+impl PauseState {
+    /// checks if `new` state only pauses more operations and never resumes any
+    pub fn only_pauses(&self, new: &PauseState) -> bool {
+        (self.transfers <= new.transfers)
+            && (self.approvals <= new.approvals)
+            && (self.minting <= new.minting)
+    }
+}
+
+/// emergency stop, authority can pause and resume operations, pausers can only pause them
+#[derive(Debug, Clone, Default, Deserialize, Serialize, CandidType)]
+pub struct Pause {
+    pub state: PauseState,
+    pub pausers: HashSet<Principal>,
+}
+
+impl Collection {
+    /// updates pause state on behalf of `caller`
+    pub fn set_pause_state(&mut self, caller: &Principal, state: PauseState) -> Result<(), String> {
+        let is_authority = self.check_authority(caller).is_ok();
+        if !is_authority && !self.pause.pausers.contains(caller) {
+            return Err(format!(
+                "caller is neither authority nor pauser: {}",
+                caller
+            ));
+        }
+
+        if !is_authority && !self.pause.state.only_pauses(&state) {
+            return Err("only authority can resume operations".to_owned());
+        }
+
+        self.pause.state = state;
+        Ok(())
+    }
+}
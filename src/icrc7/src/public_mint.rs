@@ -0,0 +1,378 @@
+use std::collections::HashMap;
+
+use ic_kit::prelude::*;
+
+use crate::allowlist::*;
+use crate::ledger::*;
+use crate::random::*;
+use crate::state::*;
+use crate::transactions::{MintTx, Transaction};
+
+/// terms of public mint, anyone can mint a token by paying `price`
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, CandidType)]
+pub struct PublicMintConfig {
+    /// ICRC-2 ledger payment is taken with
+    pub ledger: Principal,
+    /// price of a single token in ledger tokens, mint is free if 0
+    pub price: Nat,
+    /// account payments are sent to
+    pub treasury: Account,
+    pub starts_at: Option<u64>,
+    pub ends_at: Option<u64>,
+    /// maximum number of tokens single principal can mint, tokens minted during presale phases
+    /// count towards it too
+    pub max_per_wallet: Option<u32>,
+    /// minted tokens get random IDs below supply cap instead of sequential ones
+    pub random_ids: bool,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize, CandidType)]
+pub struct PublicMint {
+    /// public mint is disabled if not set
+    pub config: Option<PublicMintConfig>,
+    /// tokens minted by each principal, including ones with payment in flight
+    pub minted: HashMap<Principal, u32>,
+    /// mints with payment in flight, counted against supply cap
+    pub pending: usize,
+    /// next candidate for the ID of publicly minted token
+    pub next_id: TokenID,
+}
+
+/// arguments for the "public_mint" method
+#[derive(Debug, Clone, Deserialize, Serialize, CandidType)]
+pub struct PublicMintArgs {
+    /// subaccount payment is taken from
+    pub from_subaccount: Option<Subaccount>,
+    /// new token owner, caller's account if not set
+    pub to: Option<Account>,
+    /// required while presale phase is running
+    pub proof: Option<AllowlistProof>,
+}
+
+/// payment terms of the reserved mint slot
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Reservation {
+    pub ledger: Principal,
+    pub price: Nat,
+    pub treasury: Account,
+    /// presale phase the slot was reserved in
+    pub phase: Option<String>,
+    pub random_ids: bool,
+}
+
+impl Collection {
+    /// reserves a mint slot for `caller` before payment, allowlist `proof` is required while
+    /// presale phase is running
+    pub fn reserve_public_mint(
+        &mut self,
+        caller: Principal,
+        now: u64,
+        payer: &Account,
+        to: &Account,
+        proof: Option<&AllowlistProof>,
+    ) -> Result<Reservation, String> {
+        let config = match self.public_mint.config {
+            Some(ref config) => config.clone(),
+            None => return Err("public mint is not enabled".to_owned()),
+        };
+
+        if self.pause.state.minting {
+            return Err("minting is paused".to_owned());
+        }
+
+        if caller == Principal::anonymous() {
+            return Err("anonymous calls are not supported".to_owned());
+        }
+
+        let phase = self.allowlist.active_phase(now).cloned();
+        if let Some(ref phase) = phase {
+            self.allowlist.check_claim(phase, &caller, proof)?;
+        } else {
+            if config.starts_at.is_some_and(|starts_at| now < starts_at) {
+                return Err("public mint has not started yet".to_owned());
+            }
+
+            if config.ends_at.is_some_and(|ends_at| now >= ends_at) {
+                return Err("public mint has ended".to_owned());
+            }
+
+            let minted = self.public_mint.minted.get(&caller).copied().unwrap_or(0);
+            if config.max_per_wallet.is_some_and(|max| minted >= max) {
+                return Err("mint limit per wallet reached".to_owned());
+            }
+        }
+
+        if self.denylist.contains(payer) || self.denylist.contains(to) {
+            return Err("account is denylisted".to_owned());
+        }
+
+        let supply = self.tokens.len() + self.public_mint.pending;
+        if supply >= self.supply_cap.unwrap_or(usize::MAX) {
+            return Err("supply cap reached".to_owned());
+        }
+
+        *self.public_mint.minted.entry(caller).or_default() += 1;
+        self.public_mint.pending += 1;
+        if let Some(ref phase) = phase {
+            self.allowlist.claim(&phase.name, caller);
+        }
+
+        Ok(Reservation {
+            ledger: config.ledger,
+            price: phase
+                .as_ref()
+                .and_then(|phase| phase.price.clone())
+                .unwrap_or(config.price),
+            treasury: config.treasury,
+            phase: phase.map(|phase| phase.name),
+            random_ids: config.random_ids,
+        })
+    }
+
+    /// gives back slot reserved by `caller` after failed payment
+    pub fn release_public_mint(&mut self, caller: Principal, reservation: &Reservation) {
+        if let Some(minted) = self.public_mint.minted.get_mut(&caller) {
+            *minted = minted.saturating_sub(1);
+        }
+        if let Some(ref phase) = reservation.phase {
+            self.allowlist.unclaim(phase, &caller);
+        }
+        self.public_mint.pending = self.public_mint.pending.saturating_sub(1);
+    }
+
+    /// mints paid token to `to` under randomly drawn `id` or the next free one if not set
+    pub fn complete_public_mint(&mut self, to: Account, id: Option<TokenID>, now: u64) -> TokenID {
+        self.public_mint.pending = self.public_mint.pending.saturating_sub(1);
+
+        let id = match id {
+            Some(id) => id,
+            None => self.next_public_mint_id(),
+        };
+
+        let token = Token {
+            id: id.clone(),
+            name: self.public_mint_name(&id),
+            image: self.image.clone().unwrap_or_default(),
+            owner: to.to_canonical(),
+            transferable: true,
+            locked_until: None,
+            royalties: None,
+            reveal_hash: None,
+        };
+        self.add_token(token);
+        self.record_transaction(Transaction::mint(
+            MintTx {
+                to: to.to_canonical(),
+                token_id: id.clone(),
+            },
+            now,
+        ));
+
+        id
+    }
+
+    /// "<collection name> #<id>", collection name is shortened to fit the name length limit
+    fn public_mint_name(&self, id: &TokenID) -> String {
+        let suffix = format!(" #{}", id);
+        let max = self.limits.max_name_length;
+
+        let mut name = truncate(&self.name, max.saturating_sub(suffix.len())).to_owned();
+        name.push_str(&suffix);
+        truncate(&name, max).to_owned()
+    }
+
+    fn next_public_mint_id(&mut self) -> TokenID {
+        // IDs could have been taken by authority
+        let id = self.next_free_id(&self.public_mint.next_id);
+        let mut next_id = id.clone();
+        next_id += 1;
+        self.public_mint.next_id = next_id;
+
+        id
+    }
+}
+
+/// longest prefix of `s` that is at most `max` bytes long and ends at a char boundary
+fn truncate(s: &str, max: usize) -> &str {
+    let mut end = max.min(s.len());
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    &s[..end]
+}
+
+#[update]
+pub fn set_public_mint(c: &mut Collection, config: Option<PublicMintConfig>) -> Result<(), String> {
+    c.check_authority(&caller())?;
+    if config.as_ref().is_some_and(|config| config.random_ids) {
+        c.init_random_pool()?;
+    }
+    c.public_mint.config = config;
+    Ok(())
+}
+
+/// mints new token for anyone paying the price set by authority, payment is pulled with
+/// "icrc2_transfer_from", so caller has to approve canister to spend price plus ledger fee
+#[update]
+pub async fn public_mint(args: PublicMintArgs) -> Result<TokenID, String> {
+    let caller = caller();
+    let payer = Account::new(caller, args.from_subaccount).to_canonical();
+    let to = args.to.unwrap_or(payer.clone()).to_canonical();
+
+    let reservation = ic::with_mut(|c: &mut Collection| {
+        c.reserve_public_mint(caller, ic::time(), &payer, &to, args.proof.as_ref())
+    })?;
+
+    // randomness is requested before payment, so failing to get it doesn't leave paid mint behind
+    let seed = if reservation.random_ids {
+        match random_seed().await {
+            Ok(seed) => Some(seed),
+            Err(e) => {
+                ic::with_mut(|c: &mut Collection| c.release_public_mint(caller, &reservation));
+                return Err(e);
+            }
+        }
+    } else {
+        None
+    };
+
+    if reservation.price != 0 {
+        let payment = TransferFromArgs {
+            spender_subaccount: None,
+            from: payer,
+            to: reservation.treasury.clone(),
+            amount: reservation.price.clone(),
+            fee: None,
+            memo: None,
+            created_at_time: None,
+        };
+
+        let result = match transfer_from(reservation.ledger, payment).await {
+            Ok(Ok(_)) => Ok(()),
+            Ok(Err(e)) => Err(format!("payment failed: {:?}", e)),
+            Err(e) => Err(e),
+        };
+
+        if let Err(e) = result {
+            ic::with_mut(|c: &mut Collection| c.release_public_mint(caller, &reservation));
+            return Err(e);
+        }
+    }
+
+    Ok(ic::with_mut(|c: &mut Collection| {
+        let id = seed.and_then(|seed| c.draw_random_id(&seed, caller, ic::time()));
+        c.complete_public_mint(to, id, ic::time())
+    }))
+}
+
+#[query]
+fn public_mint_config(c: &Collection) -> Option<PublicMintConfig> {
+    c.public_mint.config.clone()
+}
+
+#[query]
+fn public_mints_of(c: &Collection, minter: Principal) -> u32 {
+    c.public_mint.minted.get(&minter).copied().unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reserve_public_mint() {
+        let minter = Principal::from_slice(&[0x1]);
+        let acc = Account::from_owner(minter);
+
+        let mut c = Collection {
+            name: "Test".to_owned(),
+            supply_cap: Some(3),
+            ..Default::default()
+        };
+        c.public_mint.config = Some(PublicMintConfig {
+            ledger: Principal::from_slice(&[0x8]),
+            price: 100.into(),
+            treasury: Account::from_owner(Principal::from_slice(&[0x9])),
+            starts_at: Some(10),
+            ends_at: Some(20),
+            max_per_wallet: Some(2),
+            random_ids: false,
+        });
+        c.add_token(Token {
+            id: 0.into(),
+            name: "Authority mint".to_owned(),
+            image: vec![],
+            owner: acc.clone(),
+            transferable: true,
+            locked_until: None,
+            royalties: None,
+            reveal_hash: None,
+        });
+
+        assert!(c.reserve_public_mint(minter, 5, &acc, &acc, None).is_err());
+        assert!(c.reserve_public_mint(minter, 20, &acc, &acc, None).is_err());
+
+        let reservation = c.reserve_public_mint(minter, 10, &acc, &acc, None).unwrap();
+        assert_eq!(reservation.price, Nat::from(100));
+        assert_eq!(reservation.phase, None);
+        c.reserve_public_mint(minter, 10, &acc, &acc, None).unwrap();
+        assert!(c.reserve_public_mint(minter, 10, &acc, &acc, None).is_err());
+
+        // failed payment frees the slot
+        c.release_public_mint(minter, &reservation);
+        assert_eq!(c.public_mint.minted[&minter], 1);
+
+        // taken IDs are skipped
+        assert_eq!(c.complete_public_mint(acc.clone(), None, 0), Nat::from(1));
+        assert_eq!(c.tokens[&Nat::from(1)].name, "Test #1");
+
+        // allowlisted principal can mint during presale before public mint starts
+        let leaves = vec![allowlist_leaf(&minter, 1)];
+        c.allowlist.phases = vec![MintPhase {
+            name: "presale".to_owned(),
+            merkle_root: merkle_root(&leaves),
+            starts_at: 0,
+            ends_at: 10,
+            price: Some(50.into()),
+        }];
+        let proof = AllowlistProof {
+            quota: 1,
+            proof: merkle_proof(&leaves, 0),
+        };
+        assert!(c.reserve_public_mint(minter, 5, &acc, &acc, None).is_err());
+        let reservation = c
+            .reserve_public_mint(minter, 5, &acc, &acc, Some(&proof))
+            .unwrap();
+        assert_eq!(reservation.price, Nat::from(50));
+        assert_eq!(reservation.phase, Some("presale".to_owned()));
+        c.release_public_mint(minter, &reservation);
+        assert_eq!(c.allowlist.claimed_by("presale", &minter), 0);
+
+        // pending mints count against supply cap
+        let other = Principal::from_slice(&[0x2]);
+        let other_acc = Account::from_owner(other);
+        c.reserve_public_mint(other, 10, &other_acc, &other_acc, None)
+            .unwrap();
+        assert_eq!(
+            c.reserve_public_mint(other, 10, &other_acc, &other_acc, None),
+            Err("supply cap reached".to_owned())
+        );
+    }
+
+    #[test]
+    fn test_public_mint_name() {
+        let mut c = Collection {
+            name: "Très long".to_owned(),
+            ..Default::default()
+        };
+        assert_eq!(c.public_mint_name(&7.into()), "Très long #7");
+
+        // "è" takes two bytes and can't be split
+        c.limits.max_name_length = 6;
+        assert_eq!(c.public_mint_name(&7.into()), "Tr #7");
+        assert!(c.limits.check_name(&c.public_mint_name(&7.into())).is_ok());
+
+        c.limits.max_name_length = 2;
+        assert_eq!(c.public_mint_name(&7.into()), " #");
+    }
+}
@@ -0,0 +1,193 @@
+//! Random assignment of token IDs.
+//!
+//! IDs `0..supply_cap` form a pool, every draw removes one ID from it, so no ID can be drawn twice.
+//! Draw with seed `s` picks index `u64::from_be_bytes(sha256(s || round)[..8]) % remaining` of the
+//! pool, where `round` is a big endian u32 that starts at 0 and is incremented when the picked ID
+//! has already been minted by authority. Picked index is replaced with the last remaining ID.
+
+use std::collections::HashMap;
+
+use ic_kit::prelude::*;
+use sha2::{Digest, Sha256};
+
+use crate::state::*;
+
+/// IDs that were not drawn yet, kept as lazily shuffled array of `0..size`
+#[derive(Debug, Clone, Default, Deserialize, Serialize, CandidType)]
+pub struct RandomPool {
+    pub size: u64,
+    pub remaining: u64,
+    /// array positions holding other ID than their own index
+    pub swaps: HashMap<u64, u64>,
+}
+
+impl RandomPool {
+    pub fn new(size: u64) -> Self {
+        Self {
+            size,
+            remaining: size,
+            swaps: HashMap::new(),
+        }
+    }
+
+    /// removes and returns ID at position `index` of the remaining ones
+    fn take(&mut self, index: u64) -> u64 {
+        let last = self.remaining - 1;
+        let id = self.swaps.remove(&index).unwrap_or(index);
+        if index != last {
+            let last_id = self.swaps.remove(&last).unwrap_or(last);
+            self.swaps.insert(index, last_id);
+        }
+        self.remaining -= 1;
+        id
+    }
+}
+
+/// audit log entry, recorded on every random draw
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, CandidType)]
+pub struct RandomDraw {
+    pub id: TokenID,
+    /// randomness returned by "raw_rand"
+    pub seed: Vec<u8>,
+    pub minter: Principal,
+    pub timestamp: u64,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize, CandidType)]
+pub struct RandomIds {
+    /// created when random assignment is enabled for the first time
+    pub pool: Option<RandomPool>,
+    pub draws: Vec<RandomDraw>,
+}
+
+fn seed_index(seed: &[u8], round: u32, remaining: u64) -> u64 {
+    let mut h = Sha256::new();
+    h.update(seed);
+    h.update(round.to_be_bytes());
+    let digest = h.finalize();
+
+    let mut bytes = [0; 8];
+    bytes.copy_from_slice(&digest[..8]);
+    u64::from_be_bytes(bytes) % remaining
+}
+
+impl Collection {
+    /// creates pool of IDs for random assignment, it covers `0..supply_cap`
+    pub fn init_random_pool(&mut self) -> Result<(), String> {
+        if self.random.pool.is_some() {
+            return Ok(());
+        }
+
+        match self.supply_cap {
+            Some(cap) => {
+                self.random.pool = Some(RandomPool::new(cap as u64));
+                Ok(())
+            }
+            None => Err("random ID assignment requires supply cap".to_owned()),
+        }
+    }
+
+    /// draws unminted ID from the pool using `seed` and records the draw
+    pub fn draw_random_id(&mut self, seed: &[u8], minter: Principal, now: u64) -> Option<TokenID> {
+        let pool = self.random.pool.as_mut()?;
+
+        let mut round = 0;
+        while pool.remaining > 0 {
+            let id = TokenID::from(pool.take(seed_index(seed, round, pool.remaining)));
+
+            // authority could have minted this ID, it's out of the pool now as well
+            if !self.tokens.contains_key(&id) {
+                self.random.draws.push(RandomDraw {
+                    id: id.clone(),
+                    seed: seed.to_vec(),
+                    minter,
+                    timestamp: now,
+                });
+                return Some(id);
+            }
+
+            round += 1;
+        }
+
+        None
+    }
+}
+
+/// 32 random bytes from the management canister
+#[cfg(target_arch = "wasm32")]
+pub async fn random_seed() -> Result<Vec<u8>, String> {
+    CallBuilder::new(Principal::management_canister(), "raw_rand")
+        .perform_one::<Vec<u8>>()
+        .await
+        .map_err(|e| format!("failed to get randomness: {:?}", e))
+}
+
+/// ic-kit-runtime has no management canister, so tests get seed derived from current time
+#[cfg(not(target_arch = "wasm32"))]
+pub async fn random_seed() -> Result<Vec<u8>, String> {
+    Ok(Sha256::digest(ic::time().to_be_bytes()).to_vec())
+}
+
+#[query]
+fn random_draws(c: &Collection) -> Vec<RandomDraw> {
+    c.random.draws.clone()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+
+    #[test]
+    fn test_draw_random_id() {
+        let minter = Principal::from_slice(&[0x1]);
+        let mut c = Collection {
+            supply_cap: Some(10),
+            ..Default::default()
+        };
+
+        assert_eq!(c.draw_random_id(&[0], minter, 0), None);
+        c.init_random_pool().unwrap();
+
+        c.add_token(Token {
+            id: 3.into(),
+            name: "Authority mint".to_owned(),
+            image: vec![],
+            owner: Account::from_owner(minter),
+            transferable: true,
+            locked_until: None,
+            royalties: None,
+            reveal_hash: None,
+        });
+
+        // same seed still draws different IDs
+        let drawn: HashSet<_> = (0..9)
+            .map(|_| c.draw_random_id(&[7; 32], minter, 0).unwrap())
+            .collect();
+        assert_eq!(drawn.len(), 9);
+        assert!(!drawn.contains(&Nat::from(3)));
+        let pool: HashSet<_> = (0..10u64).map(Nat::from).collect();
+        assert!(drawn.is_subset(&pool));
+
+        assert_eq!(c.draw_random_id(&[7; 32], minter, 0), None);
+        assert_eq!(c.random.draws.len(), 9);
+    }
+
+    #[test]
+    fn test_pool_take() {
+        let mut pool = RandomPool::new(4);
+        assert_eq!(pool.take(1), 1);
+        assert_eq!(pool.take(1), 3);
+        assert_eq!(pool.take(0), 0);
+        assert_eq!(pool.take(0), 2);
+        assert_eq!(pool.remaining, 0);
+        assert!(pool.swaps.is_empty());
+    }
+
+    #[test]
+    fn test_init_random_pool() {
+        let mut c = Collection::default();
+        assert!(c.init_random_pool().is_err());
+    }
+}
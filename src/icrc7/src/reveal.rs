@@ -0,0 +1,142 @@
+use ic_kit::prelude::*;
+use sha2::{Digest, Sha256};
+
+use crate::state::*;
+use crate::update::*;
+
+/// sha256 of token metadata, `image` is base64 encoded as passed to mint methods
+pub fn metadata_hash(name: &str, image: &str) -> Vec<u8> {
+    let mut h = Sha256::new();
+    h.update((name.len() as u64).to_be_bytes());
+    h.update(name);
+    h.update((image.len() as u64).to_be_bytes());
+    h.update(image);
+    h.finalize().to_vec()
+}
+
+/// final metadata of the token minted with placeholder
+#[derive(Debug, Clone, Deserialize, Serialize, CandidType)]
+pub struct RevealArgs {
+    pub id: TokenID,
+    pub name: String,
+    /// base64 encoded token image
+    pub image: String,
+}
+
+impl Collection {
+    /// swaps placeholders for the final metadata, nothing is revealed if any of the tokens fails
+    /// verification against its committed hash
+    pub fn reveal_tokens(
+        &mut self,
+        caller: &Principal,
+        reveals: Vec<RevealArgs>,
+    ) -> Result<(), String> {
+        self.check_authority(caller)?;
+        self.limits.check_batch(reveals.len())?;
+
+        let mut revealed = Vec::with_capacity(reveals.len());
+        for reveal in reveals {
+            let token = match self.tokens.get(&reveal.id) {
+                Some(token) => token,
+                None => return Err(format!("token {} does not exist", reveal.id)),
+            };
+
+            let hash = match token.reveal_hash {
+                Some(ref hash) => hash,
+                None => return Err(format!("token {} is already revealed", reveal.id)),
+            };
+
+            if metadata_hash(&reveal.name, &reveal.image) != *hash {
+                return Err(format!(
+                    "token {}: metadata does not match committed hash",
+                    reveal.id
+                ));
+            }
+
+            self.limits.check_name(&reveal.name)?;
+            let image = decode_image(&self.limits, &reveal.image)?;
+
+            revealed.push((reveal.id, reveal.name, image));
+        }
+
+        for (id, name, image) in revealed {
+            if let Some(token) = self.tokens.get_mut(&id) {
+                token.name = name;
+                token.image = image;
+                token.reveal_hash = None;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[update]
+pub fn reveal_tokens(c: &mut Collection, reveals: Vec<RevealArgs>) -> Result<(), String> {
+    c.reveal_tokens(&caller(), reveals)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reveal_tokens() {
+        let authority = Principal::from_slice(&[0x1]);
+        let owner = Account::from_owner(Principal::from_slice(&[0x2]));
+
+        let mut c = Collection {
+            authority: Some(authority),
+            ..Default::default()
+        };
+        for id in 1..=2 {
+            let resp = mint(
+                &mut c,
+                authority,
+                0,
+                MintTokenArgs {
+                    id: id.into(),
+                    name: "Mystery box".to_owned(),
+                    image: "QUFBQQ".to_owned(),
+                    owner: owner.clone(),
+                    transferable: None,
+                    locked_until: None,
+                    royalties: None,
+                    reveal_hash: Some(metadata_hash(&format!("NFT-{}", id), "QkJCQg")),
+                },
+            );
+            assert_eq!(resp, Ok(id.into()));
+        }
+
+        let reveal = |id: u64, name: &str| RevealArgs {
+            id: id.into(),
+            name: name.to_owned(),
+            image: "QkJCQg".to_owned(),
+        };
+
+        assert!(c
+            .reveal_tokens(&owner.owner, vec![reveal(1, "NFT-1")])
+            .is_err());
+
+        // whole batch is rejected if any metadata doesn't match
+        assert_eq!(
+            c.reveal_tokens(&authority, vec![reveal(1, "NFT-1"), reveal(2, "NFT-3")]),
+            Err("token 2: metadata does not match committed hash".to_owned())
+        );
+        assert_eq!(c.tokens[&Nat::from(1)].name, "Mystery box");
+
+        assert_eq!(
+            c.reveal_tokens(&authority, vec![reveal(1, "NFT-1"), reveal(2, "NFT-2")]),
+            Ok(())
+        );
+        let token = &c.tokens[&Nat::from(2)];
+        assert_eq!(token.name, "NFT-2");
+        assert_eq!(token.image, b"BBBB".to_vec());
+        assert!(token.reveal_hash.is_none());
+
+        assert_eq!(
+            c.reveal_tokens(&authority, vec![reveal(1, "NFT-1")]),
+            Err("token 1 is already revealed".to_owned())
+        );
+    }
+}
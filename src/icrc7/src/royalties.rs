@@ -0,0 +1,166 @@
+use ic_kit::prelude::*;
+
+use crate::state::*;
+
+/// account receiving royalties and its share relative to other recipients
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, CandidType)]
+pub struct RoyaltyRecipient {
+    pub account: Account,
+    pub weight: u32,
+}
+
+/// royalties paid on secondary sales, either collection-wide or per token
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, CandidType)]
+pub struct Royalties {
+    /// royalties in basis points
+    pub basis_points: u16,
+    pub recipients: Vec<RoyaltyRecipient>,
+}
+
+/// amount owed to a single recipient for a sale
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, CandidType)]
+pub struct RoyaltyPayment {
+    pub recipient: Account,
+    pub amount: Nat,
+}
+
+impl Royalties {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.basis_points > 10000 {
+            return Err("royalties must be between 0 and 10000".to_owned());
+        }
+
+        if self.recipients.is_empty() {
+            return Err("royalties must have at least one recipient".to_owned());
+        }
+
+        if self.recipients.iter().any(|r| r.weight == 0) {
+            return Err("royalty recipient weight must be greater than 0".to_owned());
+        }
+
+        if self
+            .recipients
+            .iter()
+            .try_fold(0u32, |acc, r| acc.checked_add(r.weight))
+            .is_none()
+        {
+            return Err("total weight of royalty recipients is too large".to_owned());
+        }
+
+        Ok(())
+    }
+
+    /// splits royalties for `sale_price` between recipients according to their weights,
+    /// remainder left after rounding goes to the first recipient
+    pub fn split(&self, sale_price: &Nat) -> Vec<RoyaltyPayment> {
+        let total = sale_price.0.clone() * self.basis_points / 10000u32;
+        let total_weight: u32 = self.recipients.iter().map(|r| r.weight).sum();
+
+        let mut payments = self
+            .recipients
+            .iter()
+            .map(|r| RoyaltyPayment {
+                recipient: r.account.clone(),
+                amount: Nat(total.clone() * r.weight / total_weight),
+            })
+            .collect::<Vec<_>>();
+
+        let paid = payments
+            .iter()
+            .fold(Nat::from(0), |acc, p| acc + p.amount.clone());
+        if let Some(first) = payments.first_mut() {
+            first.amount = Nat(first.amount.0.clone() + (total - paid.0));
+        }
+
+        payments
+    }
+}
+
+impl Collection {
+    /// royalties applied to the token, its own if set and collection-wide otherwise
+    pub fn royalties_of(&self, token: &Token) -> Royalties {
+        if let Some(ref royalties) = token.royalties {
+            return royalties.clone();
+        }
+
+        let recipients = if self.royalty_recipients.is_empty() {
+            vec![RoyaltyRecipient {
+                account: self.royalty_recipient.clone(),
+                weight: 1,
+            }]
+        } else {
+            self.royalty_recipients.clone()
+        };
+
+        Royalties {
+            basis_points: self.royalties,
+            recipients,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn recipient(id: u8, weight: u32) -> RoyaltyRecipient {
+        RoyaltyRecipient {
+            account: Account::from_owner(Principal::from_slice(&[id])),
+            weight,
+        }
+    }
+
+    #[test]
+    fn test_split() {
+        let royalties = Royalties {
+            basis_points: 1000,
+            recipients: vec![recipient(1, 1), recipient(2, 1), recipient(3, 1)],
+        };
+
+        let payments = royalties.split(&Nat::from(1000));
+        let amounts = payments
+            .iter()
+            .map(|p| p.amount.clone())
+            .collect::<Vec<_>>();
+
+        // 100 can't be split evenly, remainder goes to the first recipient
+        assert_eq!(amounts, vec![Nat::from(34), Nat::from(33), Nat::from(33)]);
+    }
+
+    #[test]
+    fn test_split_weighted() {
+        let royalties = Royalties {
+            basis_points: 250,
+            recipients: vec![recipient(1, 3), recipient(2, 1)],
+        };
+
+        let payments = royalties.split(&Nat::from(10000));
+        let amounts = payments
+            .iter()
+            .map(|p| p.amount.clone())
+            .collect::<Vec<_>>();
+
+        assert_eq!(amounts, vec![Nat::from(188), Nat::from(62)]);
+    }
+
+    #[test]
+    fn test_validate() {
+        let mut royalties = Royalties {
+            basis_points: 10001,
+            recipients: vec![recipient(1, 1)],
+        };
+        assert!(royalties.validate().is_err());
+
+        royalties.basis_points = 500;
+        assert!(royalties.validate().is_ok());
+
+        royalties.recipients.push(recipient(2, 0));
+        assert!(royalties.validate().is_err());
+
+        royalties.recipients = vec![recipient(1, u32::MAX), recipient(2, 1)];
+        assert!(royalties.validate().is_err());
+
+        royalties.recipients.clear();
+        assert!(royalties.validate().is_err());
+    }
+}
@@ -0,0 +1,219 @@
+use std::collections::BTreeMap;
+use std::ops::Bound;
+
+use ic_kit::prelude::*;
+
+use crate::state::*;
+
+pub type SnapshotID = u64;
+
+/// max number of holders returned by a single "snapshot_holders" call
+pub const MAX_SNAPSHOT_PAGE: usize = 1000;
+
+/// frozen copy of token ownership
+#[derive(Debug, Clone, Deserialize, Serialize, CandidType)]
+pub struct Snapshot {
+    pub taken_at: u64,
+    /// ID the next transaction would get, as in "get_transactions", snapshot includes all
+    /// transactions before it
+    pub log_index: Nat,
+    pub total_supply: u64,
+    /// number of tokens held by every canonical account
+    pub holders: BTreeMap<Account, u64>,
+}
+
+/// snapshot summary, returned by "snapshot_info"
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, CandidType)]
+pub struct SnapshotInfo {
+    pub id: SnapshotID,
+    pub taken_at: u64,
+    pub log_index: Nat,
+    pub total_supply: u64,
+    pub holders: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, CandidType)]
+pub struct Holder {
+    pub account: Account,
+    pub tokens: u64,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize, CandidType)]
+pub struct Snapshots {
+    pub snapshots: BTreeMap<SnapshotID, Snapshot>,
+    pub next_id: SnapshotID,
+}
+
+impl Collection {
+    /// copies current ownership of all tokens on behalf of `caller`
+    pub fn take_snapshot(&mut self, caller: &Principal, now: u64) -> Result<SnapshotID, String> {
+        self.check_authority(caller)?;
+
+        let mut holders = BTreeMap::new();
+        for token in self.tokens.values() {
+            *holders.entry(token.owner.to_canonical()).or_default() += 1;
+        }
+
+        let id = self.snapshots.next_id;
+        self.snapshots.next_id += 1;
+
+        self.snapshots.snapshots.insert(
+            id,
+            Snapshot {
+                taken_at: now,
+                log_index: self.transactions.log.len().into(),
+                total_supply: self.tokens.len() as u64,
+                holders,
+            },
+        );
+
+        Ok(id)
+    }
+
+    /// holders of the snapshot ordered by account, starting after `prev`
+    pub fn snapshot_holders(
+        &self,
+        id: SnapshotID,
+        prev: Option<Account>,
+        take: Option<u32>,
+    ) -> Option<Vec<Holder>> {
+        let snapshot = self.snapshots.snapshots.get(&id)?;
+        let take = take.map_or(MAX_SNAPSHOT_PAGE, |take| {
+            (take as usize).min(MAX_SNAPSHOT_PAGE)
+        });
+
+        let start = match prev {
+            Some(prev) => Bound::Excluded(prev.to_canonical()),
+            None => Bound::Unbounded,
+        };
+
+        let holders = snapshot
+            .holders
+            .range((start, Bound::Unbounded))
+            .take(take)
+            .map(|(account, tokens)| Holder {
+                account: account.clone(),
+                tokens: *tokens,
+            })
+            .collect();
+
+        Some(holders)
+    }
+}
+
+/// freezes current ownership, so holders can be listed later no matter what happens to tokens
+#[update]
+pub fn take_snapshot(c: &mut Collection) -> Result<SnapshotID, String> {
+    c.take_snapshot(&caller(), ic::time())
+}
+
+/// removes snapshot that is no longer needed
+#[update]
+pub fn delete_snapshot(c: &mut Collection, id: SnapshotID) -> Result<(), String> {
+    c.check_authority(&caller())?;
+    match c.snapshots.snapshots.remove(&id) {
+        Some(_) => Ok(()),
+        None => Err(format!("snapshot {} does not exist", id)),
+    }
+}
+
+#[query]
+fn snapshot_info(c: &Collection, id: SnapshotID) -> Option<SnapshotInfo> {
+    c.snapshots.snapshots.get(&id).map(|s| SnapshotInfo {
+        id,
+        taken_at: s.taken_at,
+        log_index: s.log_index.clone(),
+        total_supply: s.total_supply,
+        holders: s.holders.len() as u64,
+    })
+}
+
+/// page of snapshot holders with token counts, `prev` is the last account of the previous page
+#[query]
+fn snapshot_holders(
+    c: &Collection,
+    id: SnapshotID,
+    prev: Option<Account>,
+    take: Option<u32>,
+) -> Option<Vec<Holder>> {
+    c.snapshot_holders(id, prev, take)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transactions::{MintTx, Transaction};
+
+    fn token(id: u64, owner: &Account) -> Token {
+        Token {
+            id: id.into(),
+            name: format!("NFT-{}", id),
+            image: vec![],
+            owner: owner.clone(),
+            transferable: true,
+            locked_until: None,
+            royalties: None,
+            reveal_hash: None,
+        }
+    }
+
+    #[test]
+    fn test_snapshot() {
+        let authority = Principal::from_slice(&[0x1]);
+        let holders: Vec<Account> = (2..7)
+            .map(|i| Account::from_owner(Principal::from_slice(&[i])))
+            .collect();
+
+        let mut c = Collection {
+            authority: Some(authority),
+            ..Default::default()
+        };
+        for (i, holder) in holders.iter().enumerate() {
+            c.add_token(token(i as u64 * 2, holder));
+            c.add_token(token(i as u64 * 2 + 1, holder));
+        }
+        c.add_token(token(10, &holders[0]));
+        c.record_transaction(Transaction::mint(
+            MintTx {
+                to: holders[0].clone(),
+                token_id: 10.into(),
+            },
+            5,
+        ));
+
+        assert!(c.take_snapshot(&holders[0].owner, 10).is_err());
+        let id = c.take_snapshot(&authority, 10).unwrap();
+
+        // snapshot lines up with the transaction log, not with transfers
+        assert_eq!(c.snapshots.snapshots[&id].log_index, Nat::from(1));
+
+        // later changes don't affect the snapshot
+        c.tokens.get_mut(&Nat::from(10)).unwrap().owner = holders[1].clone();
+
+        let page = c.snapshot_holders(id, None, Some(2)).unwrap();
+        assert_eq!(
+            page,
+            vec![
+                Holder {
+                    account: holders[0].clone(),
+                    tokens: 3,
+                },
+                Holder {
+                    account: holders[1].clone(),
+                    tokens: 2,
+                },
+            ]
+        );
+
+        let prev = page.last().map(|h| h.account.clone());
+        let page = c.snapshot_holders(id, prev, Some(10)).unwrap();
+        assert_eq!(
+            page.iter().map(|h| h.account.clone()).collect::<Vec<_>>(),
+            holders[2..].to_vec()
+        );
+
+        let prev = page.last().map(|h| h.account.clone());
+        assert_eq!(c.snapshot_holders(id, prev, None), Some(vec![]));
+        assert_eq!(c.snapshot_holders(id + 1, None, None), None);
+    }
+}
@@ -2,13 +2,33 @@ use std::collections::{BTreeMap, HashMap, HashSet};
 
 use ic_kit::prelude::*;
 
+use crate::airdrop::Airdrops;
+use crate::allowlist::Allowlist;
+use crate::denylist::Denylist;
+use crate::history::OwnershipHistory;
+use crate::limits::Limits;
+use crate::maintenance::Maintenance;
+#[cfg(feature = "marketplace")]
+use crate::marketplace::Marketplace;
+use crate::pause::Pause;
+use crate::public_mint::PublicMint;
+use crate::random::RandomIds;
+use crate::royalties::{Royalties, RoyaltyRecipient};
+use crate::snapshot::Snapshots;
+use crate::transactions::{Transaction, Transactions, TransferTx};
+use crate::voucher::Vouchers;
+
 pub type TokenID = Nat;
 pub type ApprovalID = Nat;
 pub type TransferID = Nat;
+/// sha256 of transfer arguments, used for deduplication
+pub type TransferHash = [u8; 32];
 
 pub type Subaccount = [u8; 32];
 
-#[derive(Debug, PartialEq, Eq, Clone, Deserialize, Serialize, CandidType)]
+#[derive(
+    Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Deserialize, Serialize, CandidType,
+)]
 pub struct Account {
     pub owner: Principal,
     pub subaccount: Option<Subaccount>,
@@ -54,16 +74,43 @@ pub struct Collection {
     pub symbol: String,
     pub royalties: u16,
     pub royalty_recipient: Account,
+    /// royalties are split between these recipients, `royalty_recipient` gets all if empty
+    pub royalty_recipients: Vec<RoyaltyRecipient>,
     pub description: Option<String>,
     pub image: Option<Vec<u8>>,
     pub supply_cap: Option<usize>,
     pub authority: Option<Principal>,
+    /// none of the tokens can be transferred once minted
+    pub soulbound: bool,
+    /// authority is able to burn non-transferable tokens
+    pub revocable: bool,
+    // deduplication window and permitted time drift in nanoseconds, defaults are used if not set
+    pub tx_window: Option<u64>,
+    pub permitted_drift: Option<u64>,
+    pub limits: Limits,
+    pub maintenance: Maintenance,
+    pub pause: Pause,
+    pub denylist: Denylist,
+    pub public_mint: PublicMint,
+    pub allowlist: Allowlist,
+    pub vouchers: Vouchers,
+    pub random: RandomIds,
+    pub airdrops: Airdrops,
+    pub snapshots: Snapshots,
+    pub history: OwnershipHistory,
+    pub transactions: Transactions,
+    #[cfg(feature = "marketplace")]
+    pub marketplace: Marketplace,
 
     pub tokens: HashMap<TokenID, Token>,
 
     pub approval_id_seq: ApprovalID,
-    pub approvals: HashMap<ApprovalID, Approval>,
-    pub approvals_by_principal: HashMap<Principal, Vec<ApprovalID>>,
+    // ordered by id, so incremental gc can resume scanning where it stopped
+    pub approvals: BTreeMap<ApprovalID, Approval>,
+    // approvals indexed by (canonical approving account, spender)
+    pub approvals_by_account: HashMap<(Account, Principal), Vec<ApprovalID>>,
+    // number of approvals held by each canonical account, used to enforce limits
+    pub approvals_count: HashMap<Account, usize>,
 
     pub transfer_id_seq: TransferID,
 
@@ -71,6 +118,8 @@ pub struct Collection {
     // key is (transfer_timestamp, transfer_id), so we can have multiple transfers at the same nanosecond
     // this is inspried by Redis streams ids
     pub transfers: BTreeMap<(u64, TransferID), Transfer>,
+    // transfers with `created_at_time` set, indexed by hash of their arguments
+    pub transfers_by_hash: HashMap<TransferHash, (u64, TransferID)>,
 }
 
 #[derive(Debug, Clone, Default, Deserialize, Serialize, CandidType)]
@@ -80,6 +129,12 @@ pub struct Transfer {
     pub token_ids: HashSet<TokenID>,
     pub memo: Option<Vec<u8>>,
     pub created_at: u64,
+    /// approvals that authorized this transfer, empty if owner transferred tokens themselves
+    pub approval_ids: HashSet<ApprovalID>,
+    /// delegate that made the transfer using `approval_ids`
+    pub spender: Option<Principal>,
+    /// hash of transfer arguments, only set if caller provided `created_at_time`
+    pub hash: Option<TransferHash>,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize, CandidType)]
@@ -92,31 +147,118 @@ pub struct Approval {
     pub memo: Option<Vec<u8>>,
 }
 
+impl Approval {
+    /// canonical account tokens are approved from
+    pub fn from_account(&self) -> Account {
+        Account::new(self.from, self.from_subaccount).to_canonical()
+    }
+
+    /// key of this approval in `Collection::approvals_by_account`
+    pub fn key(&self) -> (Account, Principal) {
+        (self.from_account(), self.to)
+    }
+
+    pub fn is_expired(&self, now: u64) -> bool {
+        matches!(self.expires_at, Some(expires_at) if expires_at < now)
+    }
+
+    /// checks if approval allows to transfer `token_id` at time `now`
+    pub fn allows(&self, token_id: &TokenID, now: u64) -> bool {
+        if self.is_expired(now) {
+            return false;
+        }
+
+        match self.token_ids {
+            Some(ref ids) => ids.contains(token_id),
+            None => true,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize, CandidType)]
 pub struct Token {
     pub id: TokenID,
     pub name: String,
     pub image: Vec<u8>,
     pub owner: Account,
+    /// false for soulbound tokens, tokens restored from the first release are transferable
+    pub transferable: bool,
+    /// token can't be transferred until this time
+    pub locked_until: Option<u64>,
+    /// overrides collection-wide royalties
+    pub royalties: Option<Royalties>,
+    /// committed hash of the final metadata, name and image are placeholders until reveal
+    pub reveal_hash: Option<Vec<u8>>,
 }
 
-// 24h in nanoseconds
-const TX_DEDUPLICATION_WINDOW: u64 = 24 * 60 * 60 * 1_000_000_000;
+impl Token {
+    /// end of the lock if token is still locked at time `now`
+    pub fn locked_at(&self, now: u64) -> Option<u64> {
+        self.locked_until.filter(|until| *until > now)
+    }
+}
+
+// default deduplication window, 24h in nanoseconds
+pub const TX_DEDUPLICATION_WINDOW: u64 = 24 * 60 * 60 * 1_000_000_000;
+
+// default permitted time drift, 2 minutes in nanoseconds
+pub const PERMITTED_TIME_DRIFT: u64 = 2 * 60 * 1_000_000_000;
 
 impl Collection {
+    pub fn tx_window(&self) -> u64 {
+        self.tx_window.unwrap_or(TX_DEDUPLICATION_WINDOW)
+    }
+
+    pub fn permitted_drift(&self) -> u64 {
+        self.permitted_drift.unwrap_or(PERMITTED_TIME_DRIFT)
+    }
+
+    /// checks that `caller` is collection authority
+    pub fn check_authority(&self, caller: &Principal) -> Result<(), String> {
+        match self.authority {
+            None => Err("authority is not set".to_owned()),
+            Some(ref authority) if authority != caller => Err(format!(
+                "caller is not authority: {} != {}",
+                caller, authority,
+            )),
+            Some(_) => Ok(()),
+        }
+    }
+
     pub fn add_token(&mut self, token: Token) {
         self.tokens.insert(token.id.clone(), token);
     }
 
+    /// first ID starting from `from` that is not taken by any token
+    pub fn next_free_id(&self, from: &TokenID) -> TokenID {
+        let mut id = from.clone();
+        while self.tokens.contains_key(&id) {
+            id += 1;
+        }
+        id
+    }
+
+    /// token can be moved by its owner or delegates
+    pub fn is_transferable(&self, token: &Token) -> bool {
+        !self.soulbound && token.transferable
+    }
+
+    /// tokens can't be listed without the marketplace
+    #[cfg(not(feature = "marketplace"))]
+    pub fn in_escrow(&self, _token_id: &TokenID) -> bool {
+        false
+    }
+
     pub fn add_approval(&mut self, approval: Approval) -> ApprovalID {
         let id = self.approval_id_seq.clone();
         self.approval_id_seq += 1;
 
-        let from = approval.from;
+        let key = approval.key();
+        *self.approvals_count.entry(key.0.clone()).or_default() += 1;
 
         self.approvals.insert(id.clone(), approval);
-        self.approvals_by_principal
-            .entry(from)
+        self.approvals_by_account
+            .entry(key)
             .or_default()
             .push(id.clone());
 
@@ -124,108 +266,150 @@ impl Collection {
     }
 
     /// search valid approvals that will allow `delegate` to transfer `token_id` from `from_acc`
+    /// all approvals of the account to the delegate are considered, first matching one is returned
     /// returns none if no approval match
     pub fn find_approval_for_delegate(
         &self,
         from_acc: &Account,
         delegate: &Principal,
         token_id: &TokenID,
+        now: u64,
     ) -> Option<ApprovalID> {
-        let approvals = self.approvals_by_principal.get(&from_acc.owner)?;
-
-        for approval_id in approvals {
-            let approval = self.approvals.get(approval_id)?;
-
-            if approval.to != *delegate {
-                // not the right delegate
-                return None;
-            }
+        let key = (from_acc.to_canonical(), *delegate);
+
+        self.approvals_by_account
+            .get(&key)?
+            .iter()
+            .find(|id| {
+                self.approvals
+                    .get(id)
+                    .is_some_and(|a| a.allows(token_id, now))
+            })
+            .cloned()
+    }
 
-            if approval.token_ids.is_some()
-                && !approval.token_ids.as_ref().unwrap().contains(token_id)
-            {
-                // approval is for another token(s)
-                return None;
-            }
+    /// removes approval together with its index entries
+    pub fn remove_approval(&mut self, id: &ApprovalID) -> Option<Approval> {
+        let approval = self.approvals.remove(id)?;
+        let key = approval.key();
 
-            if approval.expires_at.is_some() && approval.expires_at.unwrap() < ic::time() {
-                // approval has expired
-                return None;
+        if let Some(ids) = self.approvals_by_account.get_mut(&key) {
+            ids.retain(|i| i != id);
+            if ids.is_empty() {
+                self.approvals_by_account.remove(&key);
             }
+        }
 
-            if approval.from_subaccount.is_some()
-                && from_acc.subaccount.unwrap_or_default() != approval.from_subaccount.unwrap()
-            {
-                // approval is for another subaccount
-                return None;
+        if let Some(count) = self.approvals_count.get_mut(&key.0) {
+            *count -= 1;
+            if *count == 0 {
+                self.approvals_count.remove(&key.0);
             }
-
-            return Some(approval_id.clone());
         }
 
-        None
+        Some(approval)
     }
 
-    pub fn add_transfer(&mut self, transfer: Transfer) -> TransferID {
+    /// records transfer of tokens that were already moved, `moved` lists tokens that actually
+    /// changed owner, which may be fewer than `token_ids` in non-atomic batches, `now` is the
+    /// canister time, while `created_at` of the transfer is only used for deduplication
+    pub fn add_transfer(
+        &mut self,
+        transfer: Transfer,
+        mut moved: Vec<TokenID>,
+        now: u64,
+    ) -> TransferID {
         let created_at = transfer.created_at;
         let id = self.transfer_id_seq.clone();
         self.transfer_id_seq += 1;
 
+        let to = transfer.to.to_canonical();
+        moved.sort();
+
+        // nothing moved, so there is nothing to show in the log
+        if !moved.is_empty() {
+            self.record_transaction(Transaction::transfer(
+                TransferTx {
+                    from: transfer.from.clone(),
+                    to,
+                    token_ids: moved,
+                    memo: transfer.memo.clone(),
+                    spender: transfer.spender,
+                    transfer_id: id.clone(),
+                },
+                now,
+            ));
+        }
+
+        if let Some(hash) = transfer.hash {
+            self.transfers_by_hash
+                .insert(hash, (created_at, id.clone()));
+        }
+
         self.transfers.insert((created_at, id.clone()), transfer);
 
         id
     }
 
-    pub fn find_duplicate_transfer(&self, t: &Transfer) -> Option<TransferID> {
-        // search all transactions that happened in this nanosecond
-        let range = (t.created_at, Nat::from(0))..(t.created_at + 1, Nat::from(0));
+    /// returns id of transfer with the same arguments hash made within deduplication window
+    pub fn find_duplicate_transfer(&self, hash: &TransferHash, now: u64) -> Option<TransferID> {
+        let (created_at, id) = self.transfers_by_hash.get(hash)?;
 
-        for ((created_at, id), transfer) in self.transfers.range(range) {
-            if *created_at != t.created_at {
-                break;
-            }
+        // transfer could be outside of window, but not yet removed by gc
+        if *created_at < now.saturating_sub(self.tx_window()) {
+            return None;
+        }
 
-            // transfers are only equal if all fields are equal
-            if transfer.from == t.from
-                && transfer.to == t.to
-                && transfer.token_ids == t.token_ids
-                && transfer.memo == t.memo
+        Some(id.clone())
+    }
+
+    /// removes transfer from deduplication index, if index points to it
+    pub(crate) fn unindex_transfer(&mut self, id: &TransferID, transfer: &Transfer) {
+        if let Some(hash) = transfer.hash {
+            if self
+                .transfers_by_hash
+                .get(&hash)
+                .is_some_and(|(_, indexed)| indexed == id)
             {
-                return Some(id.clone());
+                self.transfers_by_hash.remove(&hash);
             }
         }
-
-        None
     }
 
     // purge old transactions and approvals
     pub fn gc(&mut self, now: u64) {
-        // purge transactions older than TX_DEDUPLICATION_WINDOW
-        let split_key = &(now - TX_DEDUPLICATION_WINDOW, Nat::from(0));
+        // purge transactions older than deduplication window
+        let split_key = &(now.saturating_sub(self.tx_window()), Nat::from(0));
         // we want to keep everything after split_key
-        let after = self.transfers.split_off(&split_key);
-        self.transfers = after;
+        let after = self.transfers.split_off(split_key);
+        let before = std::mem::replace(&mut self.transfers, after);
+
+        for ((_, id), transfer) in &before {
+            self.unindex_transfer(id, transfer);
+        }
+        self.transfers_by_hash.shrink_to_fit();
 
         // purge expired approvals
-        self.approvals.retain(|_k, a| {
-            if a.expires_at.is_some() && a.expires_at.unwrap() < now {
-                return false;
-            }
-            true
-        });
-        self.approvals.shrink_to_fit();
+        self.approvals.retain(|_k, a| !a.is_expired(now));
 
-        self.approvals_by_principal.retain(|_k, v| {
-            v.retain(|id| self.approvals.get(id).is_some());
+        self.approvals_by_account.retain(|_k, v| {
+            v.retain(|id| self.approvals.contains_key(id));
             !v.is_empty()
         });
-        self.approvals_by_principal.shrink_to_fit();
+        self.approvals_by_account.shrink_to_fit();
+
+        self.approvals_count.clear();
+        for a in self.approvals.values() {
+            *self.approvals_count.entry(a.from_account()).or_default() += 1;
+        }
+        self.approvals_count.shrink_to_fit();
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use proptest::prelude::*;
 
     #[test]
     fn test_gc() {
@@ -248,9 +432,9 @@ mod tests {
             ..Default::default()
         };
 
-        c.add_transfer(t1.clone());
-        c.add_transfer(t2.clone());
-        c.add_transfer(t3.clone());
+        c.add_transfer(t1.clone(), vec![], now);
+        c.add_transfer(t2.clone(), vec![], now);
+        c.add_transfer(t3.clone(), vec![], now);
 
         assert_eq!(c.transfers.len(), 3);
 
@@ -261,6 +445,38 @@ mod tests {
         assert!(c.transfers.contains_key(&(t3.created_at, 2.into())));
     }
 
+    #[test]
+    fn test_gc_configured_window() {
+        let mut c = Collection {
+            tx_window: Some(10),
+            ..Default::default()
+        };
+
+        let now = 1000;
+
+        c.add_transfer(
+            Transfer {
+                created_at: now - 11,
+                ..Default::default()
+            },
+            vec![],
+            now,
+        );
+        c.add_transfer(
+            Transfer {
+                created_at: now - 9,
+                ..Default::default()
+            },
+            vec![],
+            now,
+        );
+
+        c.gc(now);
+
+        assert_eq!(c.transfers.len(), 1);
+        assert!(c.transfers.contains_key(&(now - 9, 1.into())));
+    }
+
     #[test]
     fn test_gc_approvals() {
         let mut c = Collection::default();
@@ -299,13 +515,78 @@ mod tests {
         c.add_approval(a3.clone());
 
         assert_eq!(c.approvals.len(), 3);
-        assert_eq!(c.approvals_by_principal[&Principal::anonymous()].len(), 3);
+        let key = a1.key();
+        assert_eq!(c.approvals_by_account[&key].len(), 3);
 
         c.gc(now);
 
         assert_eq!(c.approvals.len(), 2);
-        assert_eq!(c.approvals_by_principal[&Principal::anonymous()].len(), 2);
+        assert_eq!(c.approvals_by_account[&key].len(), 2);
         assert!(c.approvals.contains_key(&1.into()));
         assert!(c.approvals.contains_key(&2.into()));
     }
+
+    // small value ranges so generated approvals overlap a lot
+    fn arb_approval() -> impl Strategy<Value = Approval> {
+        (
+            0u8..3,
+            prop::option::of(0u8..3),
+            0u8..3,
+            prop::option::of(prop::collection::hash_set(0u32..5, 0..3)),
+            prop::option::of(0u64..10),
+        )
+            .prop_map(|(from, subaccount, to, token_ids, expires_at)| Approval {
+                from: Principal::from_slice(&[from]),
+                from_subaccount: subaccount.map(|s| [s; 32]),
+                to: Principal::from_slice(&[to]),
+                token_ids: token_ids.map(|ids| ids.into_iter().map(Nat::from).collect()),
+                expires_at,
+                memo: None,
+            })
+    }
+
+    proptest! {
+        #[test]
+        fn test_find_approval_for_delegate(
+            approvals in prop::collection::vec(arb_approval(), 0..50),
+            owner in 0u8..3,
+            subaccount in prop::option::of(0u8..3),
+            delegate in 0u8..3,
+            token in 0u32..5,
+            now in 0u64..10,
+        ) {
+            let mut c = Collection::default();
+            for a in approvals {
+                c.add_approval(a);
+            }
+
+            let from = Account::new(Principal::from_slice(&[owner]), subaccount.map(|s| [s; 32]));
+            let delegate = Principal::from_slice(&[delegate]);
+            let token_id = Nat::from(token);
+
+            // reference: linear scan over all approvals
+            let matches = |a: &Approval| {
+                a.from == from.owner
+                    && a.to == delegate
+                    && a.from_subaccount.unwrap_or_default()
+                        == from.subaccount.unwrap_or_default()
+                    && a.token_ids.as_ref().is_none_or(|ids| ids.contains(&token_id))
+                    && a.expires_at.is_none_or(|e| e >= now)
+            };
+            let expected = c.approvals.values().any(matches);
+
+            let found = c.find_approval_for_delegate(&from, &delegate, &token_id, now);
+            match found {
+                Some(ref id) => prop_assert!(matches(&c.approvals[id])),
+                None => prop_assert!(!expected),
+            }
+
+            // gc only removes expired approvals, so lookup result must not change
+            c.gc(now);
+            prop_assert_eq!(
+                c.find_approval_for_delegate(&from, &delegate, &token_id, now),
+                found
+            );
+        }
+    }
 }
@@ -0,0 +1,503 @@
+use std::collections::HashMap;
+
+use ic_kit::prelude::*;
+
+use crate::state::*;
+
+pub type TxID = u64;
+
+/// max number of transactions returned by a single "get_account_transactions" call
+pub const MAX_TRANSACTIONS_PAGE: usize = 1000;
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, CandidType)]
+pub struct MintTx {
+    pub to: Account,
+    pub token_id: TokenID,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, CandidType)]
+pub struct BurnTx {
+    pub from: Account,
+    pub token_id: TokenID,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, CandidType)]
+pub struct TransferTx {
+    pub from: Account,
+    pub to: Account,
+    /// tokens that actually moved, sorted
+    pub token_ids: Vec<TokenID>,
+    pub memo: Option<Vec<u8>>,
+    /// delegate that moved tokens using its approvals
+    pub spender: Option<Principal>,
+    pub transfer_id: TransferID,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, CandidType)]
+pub struct ApproveTx {
+    pub from: Account,
+    pub spender: Principal,
+    /// all tokens of the account if not set
+    pub token_ids: Option<Vec<TokenID>>,
+    pub expires_at: Option<u64>,
+    pub memo: Option<Vec<u8>>,
+    pub approval_id: ApprovalID,
+}
+
+/// transaction in the shape used by ICRC-1 index canister, exactly one of the operations is set
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, CandidType)]
+pub struct Transaction {
+    /// "mint", "burn", "transfer" or "approve"
+    pub kind: String,
+    pub mint: Option<MintTx>,
+    pub burn: Option<BurnTx>,
+    pub transfer: Option<TransferTx>,
+    pub approve: Option<ApproveTx>,
+    pub timestamp: u64,
+}
+
+impl Transaction {
+    fn new(kind: &str, timestamp: u64) -> Self {
+        Transaction {
+            kind: kind.to_owned(),
+            mint: None,
+            burn: None,
+            transfer: None,
+            approve: None,
+            timestamp,
+        }
+    }
+
+    pub fn mint(tx: MintTx, timestamp: u64) -> Self {
+        Transaction {
+            mint: Some(tx),
+            ..Self::new("mint", timestamp)
+        }
+    }
+
+    pub fn burn(tx: BurnTx, timestamp: u64) -> Self {
+        Transaction {
+            burn: Some(tx),
+            ..Self::new("burn", timestamp)
+        }
+    }
+
+    pub fn transfer(tx: TransferTx, timestamp: u64) -> Self {
+        Transaction {
+            transfer: Some(tx),
+            ..Self::new("transfer", timestamp)
+        }
+    }
+
+    pub fn approve(tx: ApproveTx, timestamp: u64) -> Self {
+        Transaction {
+            approve: Some(tx),
+            ..Self::new("approve", timestamp)
+        }
+    }
+
+    /// canonical accounts the transaction shows up for, spenders are indexed by default account
+    pub fn accounts(&self) -> Vec<Account> {
+        let mut accounts = vec![];
+
+        if let Some(ref tx) = self.mint {
+            accounts.push(tx.to.clone());
+        }
+        if let Some(ref tx) = self.burn {
+            accounts.push(tx.from.clone());
+        }
+        if let Some(ref tx) = self.transfer {
+            accounts.push(tx.from.clone());
+            accounts.push(tx.to.clone());
+            accounts.extend(tx.spender.map(Account::from_owner));
+        }
+        if let Some(ref tx) = self.approve {
+            accounts.push(tx.from.clone());
+            accounts.push(Account::from_owner(tx.spender));
+        }
+
+        let mut accounts: Vec<_> = accounts.iter().map(Account::to_canonical).collect();
+        accounts.sort();
+        accounts.dedup();
+        accounts
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, CandidType)]
+pub struct TransactionWithId {
+    pub id: Nat,
+    pub transaction: Transaction,
+}
+
+/// arguments for the "get_account_transactions" method
+#[derive(Debug, Clone, Deserialize, Serialize, CandidType)]
+pub struct GetAccountTransactionsArgs {
+    pub account: Account,
+    /// ID of the last transaction seen by the client, the most recent ones are returned if not set
+    pub start: Option<Nat>,
+    pub max_results: Nat,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, CandidType)]
+pub struct GetTransactions {
+    /// number of tokens held by the account
+    pub balance: Nat,
+    /// newest first
+    pub transactions: Vec<TransactionWithId>,
+    pub oldest_tx_id: Option<Nat>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, CandidType)]
+pub struct GetTransactionsErr {
+    pub message: String,
+}
+
+pub type GetTransactionsResult = Result<GetTransactions, GetTransactionsErr>;
+
+/// arguments for the "get_transactions" method
+#[derive(Debug, Clone, Deserialize, Serialize, CandidType)]
+pub struct GetTransactionsRequest {
+    pub start: Nat,
+    pub length: Nat,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, CandidType)]
+pub struct GetTransactionsResponse {
+    /// total number of transactions, the next one gets this ID
+    pub log_length: Nat,
+    /// oldest first
+    pub transactions: Vec<TransactionWithId>,
+}
+
+/// every transaction of the collection, never garbage collected, indexed by account
+#[derive(Debug, Clone, Default, Deserialize, Serialize, CandidType)]
+pub struct Transactions {
+    /// transaction ID is its position in the log
+    pub log: Vec<Transaction>,
+    /// IDs of transactions of every canonical account, ascending
+    pub by_account: HashMap<Account, Vec<TxID>>,
+}
+
+impl Collection {
+    pub fn record_transaction(&mut self, tx: Transaction) -> TxID {
+        let id = self.transactions.log.len() as TxID;
+
+        for account in tx.accounts() {
+            self.transactions
+                .by_account
+                .entry(account)
+                .or_default()
+                .push(id);
+        }
+        self.record_history(id, &tx);
+        self.transactions.log.push(tx);
+
+        id
+    }
+
+    /// transactions of the account newest first, older than `start` if set
+    pub fn get_account_transactions(
+        &self,
+        args: GetAccountTransactionsArgs,
+    ) -> GetTransactionsResult {
+        let account = args.account.to_canonical();
+        let max_results = u64::try_from(&args.max_results.0).map_or(MAX_TRANSACTIONS_PAGE, |max| {
+            (max as usize).min(MAX_TRANSACTIONS_PAGE)
+        });
+
+        let ids = self
+            .transactions
+            .by_account
+            .get(&account)
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+
+        let end = match args.start {
+            Some(start) => {
+                let start = u64::try_from(&start.0).unwrap_or(TxID::MAX);
+                ids.partition_point(|id| *id < start)
+            }
+            None => ids.len(),
+        };
+
+        let transactions = ids[end.saturating_sub(max_results)..end]
+            .iter()
+            .rev()
+            .map(|id| TransactionWithId {
+                id: (*id).into(),
+                transaction: self.transactions.log[*id as usize].clone(),
+            })
+            .collect();
+
+        let balance = self.tokens.values().filter(|t| t.owner == account).count();
+
+        Ok(GetTransactions {
+            balance: balance.into(),
+            transactions,
+            oldest_tx_id: ids.first().map(|id| (*id).into()),
+        })
+    }
+
+    /// range of the whole log, used by index canisters to catch up
+    pub fn get_transactions(&self, args: GetTransactionsRequest) -> GetTransactionsResponse {
+        let log = &self.transactions.log;
+        let start =
+            u64::try_from(&args.start.0).map_or(log.len(), |start| (start as usize).min(log.len()));
+        let length = u64::try_from(&args.length.0).map_or(MAX_TRANSACTIONS_PAGE, |length| {
+            (length as usize).min(MAX_TRANSACTIONS_PAGE)
+        });
+
+        let transactions = log[start..]
+            .iter()
+            .take(length)
+            .zip(start as TxID..)
+            .map(|(tx, id)| TransactionWithId {
+                id: id.into(),
+                transaction: tx.clone(),
+            })
+            .collect();
+
+        GetTransactionsResponse {
+            log_length: log.len().into(),
+            transactions,
+        }
+    }
+}
+
+/// mints, burns, transfers and approvals of the account, mirrors ICRC-1 index canister interface
+#[query]
+fn get_account_transactions(
+    c: &Collection,
+    args: GetAccountTransactionsArgs,
+) -> GetTransactionsResult {
+    c.get_account_transactions(args)
+}
+
+/// transactions of the whole collection oldest first, polled by the index canister
+#[query]
+fn get_transactions(c: &Collection, args: GetTransactionsRequest) -> GetTransactionsResponse {
+    c.get_transactions(args)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+    use crate::update::*;
+
+    fn page(c: &Collection, account: &Account, start: Option<u64>, max: u64) -> Vec<u64> {
+        let args = GetAccountTransactionsArgs {
+            account: account.clone(),
+            start: start.map(Into::into),
+            max_results: max.into(),
+        };
+        c.get_account_transactions(args)
+            .unwrap()
+            .transactions
+            .iter()
+            .map(|tx| u64::try_from(&tx.id.0).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_account_transactions() {
+        let authority = Principal::from_slice(&[0x1]);
+        let alice = Account::from_owner(Principal::from_slice(&[0x2]));
+        let bob = Account::from_owner(Principal::from_slice(&[0x3]));
+        let spender = Principal::from_slice(&[0x4]);
+
+        let mut c = Collection {
+            authority: Some(authority),
+            revocable: true,
+            ..Default::default()
+        };
+
+        for (id, transferable) in [(1, true), (2, false)] {
+            let args = MintTokenArgs {
+                id: id.into(),
+                name: format!("NFT-{}", id),
+                image: "QUFBQQ".to_owned(),
+                owner: alice.clone(),
+                transferable: Some(transferable),
+                locked_until: None,
+                royalties: None,
+                reveal_hash: None,
+            };
+            mint(&mut c, authority, 10, args).unwrap();
+        }
+
+        let args = ApproveArgs {
+            from_subaccount: None,
+            to: spender,
+            token_ids: Some(HashSet::from([1.into()])),
+            expires_at: None,
+            memo: None,
+            created_at: None,
+        };
+        approve(&mut c, alice.owner, 20, args).unwrap();
+
+        // token 2 is not transferable, so only token 1 is recorded
+        let args = TransferArgs {
+            from: Some(alice.clone()),
+            to: bob.clone(),
+            token_ids: HashSet::from([1.into(), 2.into()]),
+            memo: Some(vec![1]),
+            created_at_time: None,
+            is_atomic: Some(false),
+        };
+        transfer(&mut c, spender, 30, args).unwrap();
+
+        burn(&mut c, authority, 40, 2.into()).unwrap();
+
+        assert_eq!(page(&c, &alice, None, 10), vec![4, 3, 2, 1, 0]);
+        assert_eq!(page(&c, &alice, None, 2), vec![4, 3]);
+        assert_eq!(page(&c, &alice, Some(3), 2), vec![2, 1]);
+        assert_eq!(page(&c, &alice, Some(1), 2), vec![0]);
+        assert_eq!(page(&c, &bob, None, 10), vec![3]);
+        assert_eq!(
+            page(&c, &Account::from_owner(spender), None, 10),
+            vec![3, 2]
+        );
+
+        let result = c
+            .get_account_transactions(GetAccountTransactionsArgs {
+                account: bob.clone(),
+                start: None,
+                max_results: 10.into(),
+            })
+            .unwrap();
+        assert_eq!(result.balance, Nat::from(1));
+        assert_eq!(result.oldest_tx_id, Some(3.into()));
+
+        let tx = &result.transactions[0].transaction;
+        assert_eq!(tx.kind, "transfer");
+        assert_eq!(tx.timestamp, 30);
+        assert_eq!(
+            tx.transfer,
+            Some(TransferTx {
+                from: alice.clone(),
+                to: bob,
+                token_ids: vec![1.into()],
+                memo: Some(vec![1]),
+                spender: Some(spender),
+                transfer_id: 0.into(),
+            })
+        );
+
+        let resp = c.get_transactions(GetTransactionsRequest {
+            start: 3.into(),
+            length: 10.into(),
+        });
+        assert_eq!(resp.log_length, Nat::from(5));
+        assert_eq!(
+            resp.transactions
+                .iter()
+                .map(|tx| tx.id.clone())
+                .collect::<Vec<_>>(),
+            vec![Nat::from(3), Nat::from(4)]
+        );
+
+        let burn = &c.transactions.log[4];
+        assert_eq!(
+            burn.burn,
+            Some(BurnTx {
+                from: alice,
+                token_id: 2.into(),
+            })
+        );
+    }
+
+    #[test]
+    fn test_transfer_transactions() {
+        let authority = Principal::from_slice(&[0x1]);
+        let alice = Account::from_owner(Principal::from_slice(&[0x2]));
+        let bob = Account::from_owner(Principal::from_slice(&[0x3]));
+
+        let mut c = Collection {
+            authority: Some(authority),
+            ..Default::default()
+        };
+
+        for (id, transferable) in [(1, false), (2, true)] {
+            let args = MintTokenArgs {
+                id: id.into(),
+                name: format!("NFT-{}", id),
+                image: "QUFBQQ".to_owned(),
+                owner: alice.clone(),
+                transferable: Some(transferable),
+                locked_until: None,
+                royalties: None,
+                reveal_hash: None,
+            };
+            mint(&mut c, authority, 10, args).unwrap();
+        }
+
+        // non-atomic batch where nothing moved is not logged
+        let args = TransferArgs {
+            from: None,
+            to: bob.clone(),
+            token_ids: HashSet::from([1.into()]),
+            memo: None,
+            created_at_time: None,
+            is_atomic: Some(false),
+        };
+        assert_eq!(transfer(&mut c, alice.owner, 20, args), Ok(0.into()));
+        assert_eq!(c.transactions.log.len(), 2);
+
+        // timestamp is canister time, not the one set by the client
+        let args = TransferArgs {
+            from: None,
+            to: bob,
+            token_ids: HashSet::from([2.into()]),
+            memo: None,
+            created_at_time: Some(25),
+            is_atomic: None,
+        };
+        assert_eq!(transfer(&mut c, alice.owner, 30, args), Ok(1.into()));
+        assert_eq!(c.transactions.log.len(), 3);
+        assert_eq!(c.transactions.log[2].timestamp, 30);
+    }
+
+    #[test]
+    fn test_partial_transfer_transaction() {
+        let authority = Principal::from_slice(&[0x1]);
+        let alice = Account::from_owner(Principal::from_slice(&[0x2]));
+        let bob = Account::from_owner(Principal::from_slice(&[0x3]));
+
+        let mut c = Collection {
+            authority: Some(authority),
+            ..Default::default()
+        };
+
+        for (id, owner) in [(1, &alice), (2, &bob)] {
+            let args = MintTokenArgs {
+                id: id.into(),
+                name: format!("NFT-{}", id),
+                image: "QUFBQQ".to_owned(),
+                owner: owner.clone(),
+                transferable: None,
+                locked_until: None,
+                royalties: None,
+                reveal_hash: None,
+            };
+            mint(&mut c, authority, 10, args).unwrap();
+        }
+
+        // token 2 is already owned by bob, but alice can't move it, so it's not logged
+        let args = TransferArgs {
+            from: None,
+            to: bob.clone(),
+            token_ids: HashSet::from([1.into(), 2.into()]),
+            memo: None,
+            created_at_time: None,
+            is_atomic: Some(false),
+        };
+        transfer(&mut c, alice.owner, 20, args).unwrap();
+
+        assert_eq!(c.transactions.log.len(), 3);
+        let tx = c.transactions.log[2].transfer.as_ref().unwrap();
+        assert_eq!(tx.from, alice);
+        assert_eq!(tx.token_ids, vec![Nat::from(1)]);
+    }
+}
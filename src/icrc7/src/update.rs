@@ -2,11 +2,18 @@ use std::collections::HashSet;
 
 use ic_kit::prelude::*;
 
+use crate::denylist::{DenylistAction, DenylistTarget};
+use crate::limits::Limits;
+use crate::pause::PauseState;
+use crate::royalties::Royalties;
 use crate::state::*;
+use crate::transactions::{ApproveTx, BurnTx, MintTx, Transaction};
 
 use base64::engine::general_purpose::STANDARD_NO_PAD as b64;
 use base64::Engine;
 
+use sha2::{Digest, Sha256};
+
 /// arguments for the "mint" method
 #[derive(Debug, Deserialize, Serialize, CandidType)]
 pub struct MintTokenArgs {
@@ -18,23 +25,45 @@ pub struct MintTokenArgs {
     pub image: String,
     /// new token owner
     pub owner: Account,
+    /// false for soulbound tokens, that can not be transferred once minted, defaults to true
+    pub transferable: Option<bool>,
+    /// token can't be transferred until this time, in nanoseconds
+    pub locked_until: Option<u64>,
+    /// token royalties, collection-wide royalties apply if not set
+    pub royalties: Option<Royalties>,
+    /// sha256 of the final metadata, see `metadata_hash`, name and image are placeholders if set
+    pub reveal_hash: Option<Vec<u8>>,
 }
 
 #[update]
 pub fn mint_token(c: &mut Collection, args: MintTokenArgs) -> Result<TokenID, String> {
-    if c.authority.is_none() {
-        return Err("can't mint because authority is not set".to_owned());
-    }
+    mint(c, caller(), ic::time(), args)
+}
 
-    if c.authority.as_ref().unwrap() != &caller() {
-        return Err(format!(
-            "caller is not authority: {} != {}",
-            caller(),
-            c.authority.as_ref().unwrap(),
-        ));
+/// mints new token on behalf of `caller` at time `now`
+pub fn mint(
+    c: &mut Collection,
+    caller: Principal,
+    now: u64,
+    args: MintTokenArgs,
+) -> Result<TokenID, String> {
+    if c.pause.state.minting {
+        return Err("minting is paused".to_owned());
     }
 
-    if c.tokens.len() == c.supply_cap.unwrap_or(usize::MAX) {
+    c.check_authority(&caller)?;
+
+    create_token(c, now, args)
+}
+
+/// validates `args` and adds new token, callers are responsible for checking permissions
+pub(crate) fn create_token(
+    c: &mut Collection,
+    now: u64,
+    args: MintTokenArgs,
+) -> Result<TokenID, String> {
+    // public mints with payment in flight have their slots reserved
+    if c.tokens.len() + c.public_mint.pending >= c.supply_cap.unwrap_or(usize::MAX) {
         return Err("supply cap reached".to_owned());
     }
 
@@ -42,26 +71,177 @@ pub fn mint_token(c: &mut Collection, args: MintTokenArgs) -> Result<TokenID, St
         return Err("token with this ID already exists".to_owned());
     }
 
-    let image = match b64.decode(args.image) {
-        Ok(image) => image,
-        Err(e) => {
-            return Err(format!("failed to decode base64 image: {}", e));
-        }
-    };
+    if let Some(ref royalties) = args.royalties {
+        royalties.validate()?;
+    }
+
+    if args
+        .reveal_hash
+        .as_ref()
+        .is_some_and(|hash| hash.len() != 32)
+    {
+        return Err("reveal hash must be 32 bytes".to_owned());
+    }
+
+    c.limits.check_name(&args.name)?;
+    let image = decode_image(&c.limits, &args.image)?;
 
     let token = Token {
         id: args.id.clone(),
         name: args.name,
         image,
         owner: args.owner.to_canonical(),
+        transferable: args.transferable.unwrap_or(true),
+        locked_until: args.locked_until,
+        royalties: args.royalties,
+        reveal_hash: args.reveal_hash,
     };
 
     c.add_token(token);
+    c.record_transaction(Transaction::mint(
+        MintTx {
+            to: args.owner.to_canonical(),
+            token_id: args.id.clone(),
+        },
+        now,
+    ));
 
     Ok(args.id)
 }
 
-#[derive(Debug, Deserialize, Serialize, CandidType)]
+/// decodes base64 token image, checking its size against `limits`
+pub(crate) fn decode_image(limits: &Limits, image: &str) -> Result<Vec<u8>, String> {
+    // reject oversized images before spending instructions on decoding them,
+    // every 4 base64 characters encode 3 bytes
+    limits.check_image(image.len() / 4 * 3)?;
+
+    let image = match b64.decode(image) {
+        Ok(image) => image,
+        Err(e) => {
+            return Err(format!("failed to decode base64 image: {}", e));
+        }
+    };
+    limits.check_image(image.len())?;
+
+    Ok(image)
+}
+
+#[update]
+pub fn set_pause_state(c: &mut Collection, state: PauseState) -> Result<(), String> {
+    c.set_pause_state(&caller(), state)
+}
+
+#[update]
+pub fn add_pauser(c: &mut Collection, pauser: Principal) -> Result<(), String> {
+    c.check_authority(&caller())?;
+    c.pause.pausers.insert(pauser);
+    Ok(())
+}
+
+#[update]
+pub fn remove_pauser(c: &mut Collection, pauser: Principal) -> Result<(), String> {
+    c.check_authority(&caller())?;
+    c.pause.pausers.remove(&pauser);
+    Ok(())
+}
+
+#[update]
+pub fn add_to_denylist(c: &mut Collection, target: DenylistTarget) -> Result<(), String> {
+    c.update_denylist(&caller(), ic::time(), DenylistAction::Add, target)
+}
+
+#[update]
+pub fn remove_from_denylist(c: &mut Collection, target: DenylistTarget) -> Result<(), String> {
+    c.update_denylist(&caller(), ic::time(), DenylistAction::Remove, target)
+}
+
+/// burns non-transferable token, only available to authority of revocable collections
+#[update]
+pub fn burn_token(c: &mut Collection, id: TokenID) -> Result<TokenID, String> {
+    burn(c, caller(), ic::time(), id)
+}
+
+/// burns token on behalf of `caller` at time `now`
+pub fn burn(
+    c: &mut Collection,
+    caller: Principal,
+    now: u64,
+    id: TokenID,
+) -> Result<TokenID, String> {
+    c.check_authority(&caller)?;
+
+    if !c.revocable {
+        return Err("collection is not revocable".to_owned());
+    }
+
+    match c.tokens.get(&id) {
+        None => return Err(format!("token with id {} does not exist", id)),
+        Some(token) if c.is_transferable(token) => {
+            return Err("only non-transferable tokens can be burned".to_owned());
+        }
+        Some(_) => {}
+    }
+
+    if let Some(token) = c.tokens.remove(&id) {
+        c.record_transaction(Transaction::burn(
+            BurnTx {
+                from: token.owner,
+                token_id: id.clone(),
+            },
+            now,
+        ));
+    }
+
+    Ok(id)
+}
+
+/// arguments for the "set_token_lock" method
+#[derive(Debug, Clone, Deserialize, Serialize, CandidType)]
+pub struct TokenLockArgs {
+    /// token ID
+    pub id: TokenID,
+    /// token can't be transferred until this time, in nanoseconds, lock is lifted if not set
+    pub locked_until: Option<u64>,
+}
+
+#[update]
+pub fn set_token_lock(c: &mut Collection, args: TokenLockArgs) -> Result<(), String> {
+    c.check_authority(&caller())?;
+
+    match c.tokens.get_mut(&args.id) {
+        Some(token) => {
+            token.locked_until = args.locked_until;
+            Ok(())
+        }
+        None => Err(format!("token with id {} does not exist", args.id)),
+    }
+}
+
+/// arguments for the "set_tx_window" method
+#[derive(Debug, Clone, Deserialize, Serialize, CandidType)]
+pub struct TxWindowArgs {
+    /// deduplication window in nanoseconds, left unchanged if not set
+    pub tx_window: Option<u64>,
+    /// permitted time drift in nanoseconds, left unchanged if not set
+    pub permitted_drift: Option<u64>,
+}
+
+#[update]
+pub fn set_tx_window(c: &mut Collection, args: TxWindowArgs) -> Result<(), String> {
+    c.check_authority(&caller())?;
+
+    if args.tx_window.is_some() {
+        c.tx_window = args.tx_window;
+    }
+
+    if args.permitted_drift.is_some() {
+        c.permitted_drift = args.permitted_drift;
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, CandidType)]
 pub struct ApproveArgs {
     pub from_subaccount: Option<Subaccount>,
     pub to: Principal,
@@ -79,11 +259,23 @@ pub enum AppprovalError {
     GenericError { error_code: Nat, message: String },
 }
 
-pub const PERMITTED_TIME_DRIFT: u64 = 2 * 60 * 1_000_000_000; // 2 minutes in nanoseconds
-
 #[update]
 pub fn icrc7_approve(c: &mut Collection, args: ApproveArgs) -> Result<ApprovalID, AppprovalError> {
-    let from = caller();
+    approve(c, caller(), ic::time(), args)
+}
+
+/// approves tokens of `caller` at time `now`
+pub fn approve(
+    c: &mut Collection,
+    caller: Principal,
+    now: u64,
+    args: ApproveArgs,
+) -> Result<ApprovalID, AppprovalError> {
+    if c.pause.state.approvals {
+        return Err(AppprovalError::TemporarilyUnavailable);
+    }
+
+    let from = caller;
     if from == Principal::anonymous() {
         return Err(AppprovalError::GenericError {
             error_code: 3.into(),
@@ -91,11 +283,63 @@ pub fn icrc7_approve(c: &mut Collection, args: ApproveArgs) -> Result<ApprovalID
         });
     }
 
-    // check if caller owns all the tokens they want to approve
+    let from_acc = Account::new(from, args.from_subaccount).to_canonical();
+
+    if c.denylist.contains(&from_acc) || c.denylist.contains(&Account::from_owner(args.to)) {
+        return Err(AppprovalError::GenericError {
+            error_code: 7.into(),
+            message: "account is denylisted".to_string(),
+        });
+    }
+
+    let approvals_count = c.approvals_count.get(&from_acc).copied().unwrap_or(0);
+    let within_limits = c
+        .limits
+        .check_memo(&args.memo)
+        .and_then(|_| {
+            c.limits
+                .check_batch(args.token_ids.as_ref().map_or(0, HashSet::len))
+        })
+        .and_then(|_| c.limits.check_approvals(approvals_count));
+
+    if let Err(message) = within_limits {
+        return Err(AppprovalError::GenericError {
+            error_code: 5.into(),
+            message,
+        });
+    }
+
+    // tokens that can't be transferred can't be approved either
+    let non_transferable = match args.token_ids {
+        None if c.soulbound => Err("collection is soulbound".to_owned()),
+        Some(ref ids) => {
+            let ids = ids
+                .iter()
+                .filter(|id| c.tokens.get(id).is_some_and(|t| !c.is_transferable(t)))
+                .map(|id| id.to_string())
+                .collect::<Vec<_>>();
+
+            if ids.is_empty() {
+                Ok(())
+            } else {
+                Err(format!("tokens are not transferable: {}", ids.join(", ")))
+            }
+        }
+        None => Ok(()),
+    };
+
+    if let Err(message) = non_transferable {
+        return Err(AppprovalError::GenericError {
+            error_code: 6.into(),
+            message,
+        });
+    }
+
+    // check if approving account owns all the tokens caller wants to approve
     if let Some(ref ids) = args.token_ids {
         let unauthorized_ids = ids
             .iter()
-            .filter(|id| c.tokens[id].owner.owner != from)
+            .filter(|id| c.tokens.get(id).is_none_or(|t| t.owner != from_acc))
             .cloned()
             .collect::<Vec<_>>();
 
@@ -105,22 +349,40 @@ pub fn icrc7_approve(c: &mut Collection, args: ApproveArgs) -> Result<ApprovalID
     }
 
     if let Some(created_at) = args.created_at {
-        let now = ic::time();
-        if now > created_at + PERMITTED_TIME_DRIFT {
+        if now > created_at.saturating_add(c.permitted_drift()) {
             return Err(AppprovalError::TooOld);
         }
     }
 
+    let mut token_ids = args
+        .token_ids
+        .as_ref()
+        .map(|ids| ids.iter().cloned().collect::<Vec<_>>());
+    if let Some(ref mut ids) = token_ids {
+        ids.sort();
+    }
+
     let approval = Approval {
         from,
         from_subaccount: args.from_subaccount,
         to: args.to,
         token_ids: args.token_ids,
         expires_at: args.expires_at,
-        memo: args.memo,
+        memo: args.memo.clone(),
     };
 
     let id = c.add_approval(approval);
+    c.record_transaction(Transaction::approve(
+        ApproveTx {
+            from: from_acc,
+            spender: args.to,
+            token_ids,
+            expires_at: args.expires_at,
+            memo: args.memo,
+            approval_id: id.clone(),
+        },
+        now,
+    ));
 
     Ok(id)
 }
@@ -135,6 +397,47 @@ pub struct TransferArgs {
     pub is_atomic: Option<bool>,
 }
 
+impl TransferArgs {
+    /// hash of arguments as seen by the canister, two transfers are duplicates if their hashes match
+    pub fn hash(&self, caller: &Principal) -> TransferHash {
+        let mut h = Sha256::new();
+
+        // length prefix every variable sized field, so different args never produce same input
+        let mut put = |bytes: &[u8]| {
+            h.update((bytes.len() as u64).to_be_bytes());
+            h.update(bytes);
+        };
+
+        let from = self
+            .from
+            .clone()
+            .unwrap_or(Account::from_owner(*caller))
+            .to_canonical();
+        let to = self.to.to_canonical();
+
+        put(caller.as_slice());
+        put(from.owner.as_slice());
+        put(&from.subaccount.unwrap_or_default());
+        put(to.owner.as_slice());
+        put(&to.subaccount.unwrap_or_default());
+
+        // token ids are a set, so hash them in a stable order
+        let mut ids = self.token_ids.iter().collect::<Vec<_>>();
+        ids.sort();
+        put(&(ids.len() as u64).to_be_bytes());
+        for id in ids {
+            put(&id.0.to_bytes_be());
+        }
+
+        put(&[self.memo.is_some() as u8]);
+        put(self.memo.as_deref().unwrap_or_default());
+        put(&self.created_at_time.unwrap_or_default().to_be_bytes());
+        put(&[self.is_atomic.unwrap_or(true) as u8]);
+
+        h.finalize().into()
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Deserialize, Serialize, CandidType)]
 pub enum TransferError {
     Unauthorized { token_ids: Vec<TokenID> },
@@ -142,11 +445,26 @@ pub enum TransferError {
     CreatedInFuture { ledger_time: u64 },
     Duplicate { duplicate_of: Nat },
     TemporarilyUnavailable,
+    Locked { until: u64 },
     GenericError { error_code: Nat, message: String },
 }
 
 #[update]
 pub fn icrc7_transfer(c: &mut Collection, args: TransferArgs) -> Result<TransferID, TransferError> {
+    transfer(c, caller(), ic::time(), args)
+}
+
+/// transfers tokens on behalf of `caller` at time `now`
+pub fn transfer(
+    c: &mut Collection,
+    caller: Principal,
+    now: u64,
+    args: TransferArgs,
+) -> Result<TransferID, TransferError> {
+    if c.pause.state.transfers {
+        return Err(TransferError::TemporarilyUnavailable);
+    }
+
     if args.token_ids.is_empty() {
         return Err(TransferError::GenericError {
             error_code: 4.into(),
@@ -154,16 +472,26 @@ pub fn icrc7_transfer(c: &mut Collection, args: TransferArgs) -> Result<Transfer
         });
     }
 
+    let within_limits = c
+        .limits
+        .check_memo(&args.memo)
+        .and_then(|_| c.limits.check_batch(args.token_ids.len()));
+
+    if let Err(message) = within_limits {
+        return Err(TransferError::GenericError {
+            error_code: 5.into(),
+            message,
+        });
+    }
+
     if let Some(created_at) = args.created_at_time {
-        let now = ic::time();
-        if now > created_at + PERMITTED_TIME_DRIFT {
+        if now > created_at.saturating_add(c.permitted_drift()) {
             return Err(TransferError::TooOld);
         }
     }
 
     if let Some(created_at) = args.created_at_time {
-        let now = ic::time();
-        if now + PERMITTED_TIME_DRIFT < created_at {
+        if now.saturating_add(c.permitted_drift()) < created_at {
             return Err(TransferError::CreatedInFuture { ledger_time: now });
         }
     }
@@ -171,38 +499,59 @@ pub fn icrc7_transfer(c: &mut Collection, args: TransferArgs) -> Result<Transfer
     let from = args
         .from
         .clone()
-        .unwrap_or(Account::from_owner(caller()))
+        .unwrap_or(Account::from_owner(caller))
         .to_canonical();
 
-    let transfer = Transfer {
+    // caller is checked as well, since it may be spending tokens on behalf of `from`
+    if [&from, &args.to, &Account::from_owner(caller)]
+        .iter()
+        .any(|acc| c.denylist.contains(acc))
+    {
+        return Err(TransferError::GenericError {
+            error_code: 7.into(),
+            message: "account is denylisted".to_string(),
+        });
+    }
+
+    // only transfers with `created_at_time` set are deduplicated
+    let hash = args.created_at_time.map(|_| args.hash(&caller));
+    if let Some(id) = hash.and_then(|h| c.find_duplicate_transfer(&h, now)) {
+        return Err(TransferError::Duplicate { duplicate_of: id });
+    }
+
+    let mut transfer = Transfer {
         from: from.clone(),
         to: args.to.clone(),
         token_ids: args.token_ids.clone(),
         memo: args.memo.clone(),
-        created_at: args.created_at_time.unwrap_or(ic::time()),
+        created_at: args.created_at_time.unwrap_or(now),
+        hash,
+        ..Default::default()
     };
 
-    if let Some(id) = c.find_duplicate_transfer(&transfer) {
-        return Err(TransferError::Duplicate { duplicate_of: id });
-    }
-
     // since updates in IC are not atomic (i.e. replying with error does not revert state changes)
     // we need to make sure we don't mutate state before checking all preconditions
     let mut apply = |dry: bool| {
         let mut errs = Vec::new();
+        let mut approval_ids = HashSet::new();
+        let mut moved = Vec::new();
 
         for id in &args.token_ids {
             // dry run changes, before actually applying them
-            if let Err(e) = transfer_single(c, id.clone(), &from, &args, dry) {
-                errs.push(e);
+            match transfer_single(c, id.clone(), caller, now, &from, &args, dry) {
+                Ok(approval_id) => {
+                    approval_ids.extend(approval_id);
+                    moved.push(id.clone());
+                }
+                Err(e) => errs.push(e),
             }
         }
 
-        errs
+        (errs, approval_ids, moved)
     };
 
     let dry_run = args.is_atomic.unwrap_or(true);
-    let errs = apply(dry_run);
+    let (errs, mut approval_ids, mut moved) = apply(dry_run);
 
     if args.is_atomic.unwrap_or(true) && !errs.is_empty() {
         let err = errs.first().cloned().unwrap();
@@ -211,33 +560,70 @@ pub fn icrc7_transfer(c: &mut Collection, args: TransferArgs) -> Result<Transfer
 
     if dry_run {
         // actually apply state changes by running update again
-        let errs = apply(false);
+        let (errs, ids, tokens) = apply(false);
         assert!(errs.is_empty(), "dry run should have caught all errors");
+        approval_ids = ids;
+        moved = tokens;
     }
 
+    transfer.spender = (!approval_ids.is_empty()).then_some(caller);
+    transfer.approval_ids = approval_ids;
+
     // mutate
-    let id = c.add_transfer(transfer);
+    let id = c.add_transfer(transfer, moved, now);
 
     Ok(id)
 }
 
+/// transfers single token, returns id of approval that authorized the transfer (if any)
 fn transfer_single(
     c: &mut Collection,
     id: TokenID,
+    caller: Principal,
+    now: u64,
     from: &Account,
     args: &TransferArgs,
     dry_run: bool,
-) -> Result<(), TransferError> {
-    if c.tokens.get(&id).is_none() {
+) -> Result<Option<ApprovalID>, TransferError> {
+    let token = match c.tokens.get(&id) {
+        Some(token) => token,
+        None => {
+            return Err(TransferError::GenericError {
+                error_code: 1.into(),
+                message: format!("token with id {} does not exist", id),
+            });
+        }
+    };
+
+    if token.owner != *from {
+        // token does not belong to `from` account, even if caller owns it in another subaccount
+        return Err(TransferError::Unauthorized {
+            token_ids: vec![id],
+        });
+    }
+
+    if !c.is_transferable(token) {
         return Err(TransferError::GenericError {
-            error_code: 1.into(),
-            message: format!("token with id {} does not exist", id),
+            error_code: 6.into(),
+            message: format!("token with id {} is not transferable", id),
         });
     }
 
-    if from.owner != caller() {
+    if c.in_escrow(&id) {
+        return Err(TransferError::GenericError {
+            error_code: 8.into(),
+            message: format!("token with id {} is listed for sale", id),
+        });
+    }
+
+    if let Some(until) = token.locked_at(now) {
+        return Err(TransferError::Locked { until });
+    }
+
+    let mut approval = None;
+    if from.owner != caller {
         // this is either approval or someone wants to transfer someone else's token
-        let approval = c.find_approval_for_delegate(from, &caller(), &id);
+        approval = c.find_approval_for_delegate(from, &caller, &id, now);
         if approval.is_none() {
             return Err(TransferError::Unauthorized {
                 token_ids: vec![id],
@@ -245,7 +631,7 @@ fn transfer_single(
         }
     }
 
-    if *from == args.to {
+    if *from == args.to.to_canonical() {
         return Err(TransferError::GenericError {
             error_code: 2.into(),
             message: "can't transfer to self".to_string(),
@@ -257,5 +643,51 @@ fn transfer_single(
         token.owner = args.to.clone().to_canonical();
     }
 
-    Ok(())
+    Ok(approval)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_transfer_hash() {
+        let caller = Principal::from_slice(&[1]);
+        let args = TransferArgs {
+            from: None,
+            to: Account::from_owner(Principal::from_slice(&[2])),
+            token_ids: HashSet::from([1.into(), 2.into(), 3.into()]),
+            memo: Some(vec![1, 2, 3]),
+            created_at_time: Some(42),
+            is_atomic: None,
+        };
+
+        // same transfer described differently
+        let same = TransferArgs {
+            from: Some(Account::from_owner(caller)),
+            to: Account::new(Principal::from_slice(&[2]), None),
+            token_ids: HashSet::from([3.into(), 2.into(), 1.into()]),
+            is_atomic: Some(true),
+            ..args.clone()
+        };
+        assert_eq!(args.hash(&caller), same.hash(&caller));
+
+        assert_ne!(args.hash(&caller), args.hash(&Principal::from_slice(&[3])));
+
+        let other_memo = TransferArgs {
+            memo: Some(vec![1, 2]),
+            ..args.clone()
+        };
+        assert_ne!(args.hash(&caller), other_memo.hash(&caller));
+
+        let no_memo = TransferArgs {
+            memo: None,
+            ..args.clone()
+        };
+        let empty_memo = TransferArgs {
+            memo: Some(vec![]),
+            ..args.clone()
+        };
+        assert_ne!(no_memo.hash(&caller), empty_memo.hash(&caller));
+    }
 }
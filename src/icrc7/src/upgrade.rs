@@ -0,0 +1,232 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use ic_kit::prelude::*;
+
+use crate::limits::Limits;
+use crate::maintenance::Maintenance;
+use crate::state::*;
+use crate::transactions::{MintTx, Transaction};
+
+/// collection as it is saved to stable memory, new variant is added whenever layout of the state
+/// changes, so canisters can be upgraded from any released version
+#[derive(Debug, Deserialize, Serialize, CandidType)]
+pub enum StableCollection {
+    V1(Collection),
+}
+
+/// collection saved by the first release, which stored it without a version tag
+#[derive(Debug, Clone, Deserialize, Serialize, CandidType)]
+pub struct LegacyCollection {
+    pub name: String,
+    pub symbol: String,
+    pub royalties: u16,
+    pub royalty_recipient: Account,
+    pub description: Option<String>,
+    pub image: Option<Vec<u8>>,
+    pub supply_cap: Option<usize>,
+    pub authority: Option<Principal>,
+    pub tokens: HashMap<TokenID, LegacyToken>,
+    pub approval_id_seq: ApprovalID,
+    // approvals index of this release is not decoded, indexes are rebuilt instead
+    pub approvals: HashMap<ApprovalID, Approval>,
+    pub transfer_id_seq: TransferID,
+    pub transfers: BTreeMap<(u64, TransferID), LegacyTransfer>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, CandidType)]
+pub struct LegacyToken {
+    pub id: TokenID,
+    pub name: String,
+    pub image: Vec<u8>,
+    pub owner: Account,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, CandidType)]
+pub struct LegacyTransfer {
+    pub from: Account,
+    pub to: Account,
+    pub token_ids: HashSet<TokenID>,
+    pub memo: Option<Vec<u8>>,
+    pub created_at: u64,
+}
+
+impl From<LegacyToken> for Token {
+    fn from(token: LegacyToken) -> Self {
+        Token {
+            id: token.id,
+            name: token.name,
+            image: token.image,
+            owner: token.owner,
+            // every token of the first release could be transferred, tokens must not turn
+            // soulbound on upgrade
+            transferable: true,
+            locked_until: None,
+            royalties: None,
+            reveal_hash: None,
+        }
+    }
+}
+
+impl From<LegacyTransfer> for Transfer {
+    fn from(transfer: LegacyTransfer) -> Self {
+        Transfer {
+            from: transfer.from,
+            to: transfer.to,
+            token_ids: transfer.token_ids,
+            memo: transfer.memo,
+            created_at: transfer.created_at,
+            ..Default::default()
+        }
+    }
+}
+
+impl From<LegacyCollection> for Collection {
+    fn from(c: LegacyCollection) -> Self {
+        let mut collection = Collection {
+            name: c.name,
+            symbol: c.symbol,
+            royalties: c.royalties,
+            royalty_recipient: c.royalty_recipient,
+            description: c.description,
+            image: c.image,
+            supply_cap: c.supply_cap,
+            authority: c.authority,
+            // same as for collections created without these options
+            soulbound: false,
+            revocable: false,
+            tokens: c.tokens.into_iter().map(|(id, t)| (id, t.into())).collect(),
+            approval_id_seq: c.approval_id_seq,
+            approvals: c.approvals.into_iter().collect(),
+            transfer_id_seq: c.transfer_id_seq,
+            transfers: c
+                .transfers
+                .into_iter()
+                .map(|(k, t)| (k, t.into()))
+                .collect(),
+            // features added after the first release start with the same settings
+            // as collections created without them
+            limits: Limits::default(),
+            maintenance: Maintenance::default(),
+
+            ..Default::default()
+        };
+
+        // transaction log did not exist yet, so existing tokens are logged as minted
+        // at unknown time, otherwise history and index canisters would miss them
+        let mut tokens: Vec<_> = collection
+            .tokens
+            .values()
+            .map(|t| MintTx {
+                to: t.owner.clone(),
+                token_id: t.id.clone(),
+            })
+            .collect();
+        tokens.sort_by(|a, b| a.token_id.cmp(&b.token_id));
+        for tx in tokens {
+            collection.record_transaction(Transaction::mint(tx, 0));
+        }
+
+        collection
+    }
+}
+
+impl StableCollection {
+    pub fn into_collection(self) -> Collection {
+        match self {
+            StableCollection::V1(c) => c,
+        }
+    }
+}
+
+impl Collection {
+    /// recomputes approval indexes from `approvals`
+    pub fn rebuild_approval_indexes(&mut self) {
+        self.approvals_by_account.clear();
+        self.approvals_count.clear();
+
+        for (id, approval) in &self.approvals {
+            let key = approval.key();
+            *self.approvals_count.entry(key.0.clone()).or_default() += 1;
+            self.approvals_by_account
+                .entry(key)
+                .or_default()
+                .push(id.clone());
+        }
+    }
+}
+
+/// restores collection saved by any release, `versioned` and `legacy` decode saved state
+/// as the current and the first layout respectively
+pub fn restore_collection<V, L>(versioned: V, legacy: L) -> Result<Collection, String>
+where
+    V: FnOnce() -> Result<(StableCollection,), String>,
+    L: FnOnce() -> Result<(LegacyCollection,), String>,
+{
+    let mut c = match versioned() {
+        Ok((saved,)) => saved.into_collection(),
+        Err(e) => match legacy() {
+            Ok((saved,)) => saved.into(),
+            Err(_) => return Err(e),
+        },
+    };
+
+    c.rebuild_approval_indexes();
+
+    Ok(c)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_restore_legacy_collection() {
+        let owner = Account::from_owner(Principal::from_slice(&[0x1]));
+        let approval = |to: u8| Approval {
+            from: owner.owner,
+            from_subaccount: None,
+            to: Principal::from_slice(&[to]),
+            token_ids: None,
+            expires_at: None,
+            memo: None,
+        };
+
+        let legacy = LegacyCollection {
+            name: "test".to_owned(),
+            symbol: "TEST".to_owned(),
+            royalties: 0,
+            royalty_recipient: Account::default(),
+            description: None,
+            image: None,
+            supply_cap: None,
+            authority: None,
+            tokens: HashMap::from([(
+                1.into(),
+                LegacyToken {
+                    id: 1.into(),
+                    name: "NFT-1".to_owned(),
+                    image: vec![],
+                    owner: owner.clone(),
+                },
+            )]),
+            approval_id_seq: 3.into(),
+            approvals: HashMap::from([(0.into(), approval(2)), (2.into(), approval(2))]),
+            transfer_id_seq: 0.into(),
+            transfers: BTreeMap::new(),
+        };
+
+        let c = restore_collection(|| Err("not versioned".to_owned()), || Ok((legacy,))).unwrap();
+
+        assert!(!c.soulbound);
+        assert!(c.is_transferable(&c.tokens[&Nat::from(1)]));
+        assert_eq!(c.approvals_count[&owner], 2);
+        assert_eq!(
+            c.find_approval_for_delegate(&owner, &Principal::from_slice(&[0x2]), &1.into(), 0),
+            Some(0.into())
+        );
+        assert_eq!(c.approval_id_seq, Nat::from(3));
+        assert_eq!(c.limits, Limits::default());
+        assert_eq!(c.transactions.log.len(), 1);
+        assert_eq!(c.transactions.by_account[&owner], vec![0]);
+    }
+}
@@ -0,0 +1,271 @@
+use std::collections::HashSet;
+
+use ed25519_dalek::{Signature, Verifier, VerifyingKey};
+use ic_kit::prelude::*;
+
+use crate::reveal::metadata_hash;
+use crate::state::*;
+use crate::update::*;
+
+/// domain separator, so voucher signatures can't be confused with other messages of the signer
+const VOUCHER_DOMAIN: &[u8] = b"icrc7-mint-voucher";
+
+/// permission to mint a single token, signed off-chain by the voucher signer
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, CandidType)]
+pub struct MintVoucher {
+    pub token_id: TokenID,
+    /// token name
+    pub name: String,
+    /// base64 encoded token image
+    pub image: String,
+    pub recipient: Account,
+    /// voucher can't be redeemed at or after this time, in nanoseconds
+    pub expires_at: u64,
+    /// unique for every voucher, redeemed nonces are never accepted again
+    pub nonce: u64,
+}
+
+impl MintVoucher {
+    /// message signed by the signer, binds voucher to the `collection` canister
+    pub fn signed_message(&self, collection: &Principal) -> Vec<u8> {
+        let mut msg = vec![];
+
+        // length prefix every variable sized field, so different vouchers never produce same message
+        let mut put = |bytes: &[u8]| {
+            msg.extend_from_slice(&(bytes.len() as u64).to_be_bytes());
+            msg.extend_from_slice(bytes);
+        };
+
+        let recipient = self.recipient.to_canonical();
+
+        put(VOUCHER_DOMAIN);
+        put(collection.as_slice());
+        put(&self.token_id.0.to_bytes_be());
+        put(&metadata_hash(&self.name, &self.image));
+        put(recipient.owner.as_slice());
+        put(&recipient.subaccount.unwrap_or_default());
+        put(&self.expires_at.to_be_bytes());
+        put(&self.nonce.to_be_bytes());
+
+        msg
+    }
+}
+
+/// arguments for the "redeem_voucher" method
+#[derive(Debug, Clone, Deserialize, Serialize, CandidType)]
+pub struct RedeemVoucherArgs {
+    pub voucher: MintVoucher,
+    /// 64 byte Ed25519 signature of the voucher message
+    pub signature: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize, CandidType)]
+pub struct Vouchers {
+    /// 32 byte Ed25519 public key, vouchers are not accepted if not set
+    pub signer: Option<Vec<u8>>,
+    pub redeemed: HashSet<u64>,
+}
+
+fn parse_signer(key: &[u8]) -> Result<VerifyingKey, String> {
+    let key: &[u8; 32] = key
+        .try_into()
+        .map_err(|_| "signer key must be 32 bytes".to_owned())?;
+    VerifyingKey::from_bytes(key).map_err(|_| "invalid signer key".to_owned())
+}
+
+impl Collection {
+    /// mints token described by the voucher, `collection` is ID of this canister
+    pub fn redeem_voucher(
+        &mut self,
+        collection: &Principal,
+        now: u64,
+        args: RedeemVoucherArgs,
+    ) -> Result<TokenID, String> {
+        let signer = match self.vouchers.signer {
+            Some(ref signer) => parse_signer(signer)?,
+            None => return Err("voucher redemption is not enabled".to_owned()),
+        };
+
+        if self.pause.state.minting {
+            return Err("minting is paused".to_owned());
+        }
+
+        let voucher = args.voucher;
+        if now >= voucher.expires_at {
+            return Err("voucher has expired".to_owned());
+        }
+
+        if self.vouchers.redeemed.contains(&voucher.nonce) {
+            return Err("voucher has already been redeemed".to_owned());
+        }
+
+        let signature =
+            Signature::from_slice(&args.signature).map_err(|_| "malformed signature".to_owned())?;
+        if signer
+            .verify(&voucher.signed_message(collection), &signature)
+            .is_err()
+        {
+            return Err("invalid voucher signature".to_owned());
+        }
+
+        if self.denylist.contains(&voucher.recipient) {
+            return Err("account is denylisted".to_owned());
+        }
+
+        let nonce = voucher.nonce;
+        let id = create_token(
+            self,
+            now,
+            MintTokenArgs {
+                id: voucher.token_id,
+                name: voucher.name,
+                image: voucher.image,
+                owner: voucher.recipient,
+                transferable: None,
+                locked_until: None,
+                royalties: None,
+                reveal_hash: None,
+            },
+        )?;
+        self.vouchers.redeemed.insert(nonce);
+
+        Ok(id)
+    }
+}
+
+/// sets public key vouchers are verified with, redemption is disabled if not set
+#[update]
+pub fn set_voucher_signer(c: &mut Collection, signer: Option<Vec<u8>>) -> Result<(), String> {
+    c.check_authority(&caller())?;
+    if let Some(ref signer) = signer {
+        parse_signer(signer)?;
+    }
+    c.vouchers.signer = signer;
+    Ok(())
+}
+
+/// mints token authorized by the voucher signer, anyone can submit the voucher but the token
+/// always goes to the voucher recipient
+#[update]
+pub fn redeem_voucher(c: &mut Collection, args: RedeemVoucherArgs) -> Result<TokenID, String> {
+    c.redeem_voucher(&ic::id(), ic::time(), args)
+}
+
+#[query]
+fn voucher_signer(c: &Collection) -> Option<Vec<u8>> {
+    c.vouchers.signer.clone()
+}
+
+#[query]
+fn is_voucher_redeemed(c: &Collection, nonce: u64) -> bool {
+    c.vouchers.redeemed.contains(&nonce)
+}
+
+#[cfg(test)]
+mod tests {
+    use ed25519_dalek::{Signer, SigningKey};
+
+    use super::*;
+
+    fn sign(key: &SigningKey, collection: &Principal, voucher: MintVoucher) -> RedeemVoucherArgs {
+        RedeemVoucherArgs {
+            signature: key
+                .sign(&voucher.signed_message(collection))
+                .to_bytes()
+                .to_vec(),
+            voucher,
+        }
+    }
+
+    #[test]
+    fn test_redeem_voucher() {
+        let collection = Principal::from_slice(&[0xC]);
+        let recipient = Account::from_owner(Principal::from_slice(&[0x1]));
+        let key = SigningKey::from_bytes(&[7; 32]);
+
+        let mut c = Collection {
+            supply_cap: Some(2),
+            ..Default::default()
+        };
+        let voucher = MintVoucher {
+            token_id: 1.into(),
+            name: "Voucher #1".to_owned(),
+            image: "QUFBQQ".to_owned(),
+            recipient: recipient.clone(),
+            expires_at: 100,
+            nonce: 1,
+        };
+        let args = sign(&key, &collection, voucher.clone());
+
+        assert!(c.redeem_voucher(&collection, 10, args.clone()).is_err());
+        c.vouchers.signer = Some(key.verifying_key().to_bytes().to_vec());
+
+        assert_eq!(
+            c.redeem_voucher(&collection, 100, args.clone()),
+            Err("voucher has expired".to_owned())
+        );
+
+        // voucher is bound to the collection it was signed for
+        let other = Principal::from_slice(&[0xD]);
+        assert_eq!(
+            c.redeem_voucher(&other, 10, args.clone()),
+            Err("invalid voucher signature".to_owned())
+        );
+
+        // metadata can't be changed by the redeemer
+        let mut tampered = args.clone();
+        tampered.voucher.name = "Voucher #2".to_owned();
+        assert_eq!(
+            c.redeem_voucher(&collection, 10, tampered),
+            Err("invalid voucher signature".to_owned())
+        );
+
+        assert_eq!(
+            c.redeem_voucher(&collection, 10, args.clone()),
+            Ok(1.into())
+        );
+        assert_eq!(c.tokens[&Nat::from(1)].owner, recipient);
+
+        assert_eq!(
+            c.redeem_voucher(&collection, 10, args),
+            Err("voucher has already been redeemed".to_owned())
+        );
+
+        // minting rules still apply
+        let args = sign(
+            &key,
+            &collection,
+            MintVoucher {
+                nonce: 2,
+                ..voucher.clone()
+            },
+        );
+        assert_eq!(
+            c.redeem_voucher(&collection, 10, args),
+            Err("token with this ID already exists".to_owned())
+        );
+        assert!(!c.vouchers.redeemed.contains(&2));
+
+        c.supply_cap = Some(1);
+        let args = sign(
+            &key,
+            &collection,
+            MintVoucher {
+                token_id: 2.into(),
+                nonce: 3,
+                ..voucher
+            },
+        );
+        assert_eq!(
+            c.redeem_voucher(&collection, 10, args),
+            Err("supply cap reached".to_owned())
+        );
+    }
+
+    #[test]
+    fn test_set_voucher_signer() {
+        assert!(parse_signer(&[0; 31]).is_err());
+        let key = SigningKey::from_bytes(&[7; 32]);
+        assert!(parse_signer(key.verifying_key().as_bytes()).is_ok());
+    }
+}
@@ -0,0 +1,106 @@
+//! mock ICRC-2 ledger shared by tests of paid features
+
+#![allow(dead_code)]
+
+use std::collections::HashMap;
+
+use ic_kit::prelude::*;
+use ic_kit_runtime::handle::CanisterHandle;
+use icrc7::ledger::*;
+use icrc7::state::*;
+
+pub const LEDGER_FEE: u64 = 10;
+
+/// minimal ICRC-2 ledger, allowances are not tracked and any transfer_from is allowed
+#[derive(Default)]
+pub struct MockLedger {
+    balances: HashMap<Account, Nat>,
+}
+
+impl MockLedger {
+    fn move_funds(&mut self, from: Account, to: Account, amount: Nat) -> Result<(), Nat> {
+        let from_balance = self.balances.entry(from).or_insert_with(|| Nat::from(0));
+        let required = Nat(amount.0.clone() + LEDGER_FEE);
+        if *from_balance < required {
+            return Err(from_balance.clone());
+        }
+        from_balance.0 -= required.0;
+
+        self.balances.entry(to).or_insert_with(|| Nat::from(0)).0 += amount.0;
+
+        Ok(())
+    }
+}
+
+#[init]
+fn init(ledger: &mut MockLedger, balances: Vec<(Account, Nat)>) {
+    ledger.balances = balances.into_iter().collect();
+}
+
+#[query]
+fn icrc1_fee() -> Nat {
+    LEDGER_FEE.into()
+}
+
+#[query]
+fn icrc1_balance_of(ledger: &MockLedger, account: Account) -> Nat {
+    ledger
+        .balances
+        .get(&account.to_canonical())
+        .cloned()
+        .unwrap_or_else(|| Nat::from(0))
+}
+
+#[update]
+fn icrc1_transfer(
+    ledger: &mut MockLedger,
+    args: Icrc1TransferArgs,
+) -> Result<Nat, Icrc1TransferError> {
+    let from = Account::new(caller(), args.from_subaccount).to_canonical();
+    ledger
+        .move_funds(from, args.to.to_canonical(), args.amount)
+        .map(|_| Nat::from(0))
+        .map_err(|balance| Icrc1TransferError::InsufficientFunds { balance })
+}
+
+#[update]
+fn icrc2_transfer_from(
+    ledger: &mut MockLedger,
+    args: TransferFromArgs,
+) -> Result<Nat, TransferFromError> {
+    ledger
+        .move_funds(
+            args.from.to_canonical(),
+            args.to.to_canonical(),
+            args.amount,
+        )
+        .map(|_| Nat::from(0))
+        .map_err(|balance| TransferFromError::InsufficientFunds { balance })
+}
+
+#[derive(KitCanister)]
+#[candid_path("tests/mock_ledger.did")]
+pub struct MockLedgerCanister;
+
+pub async fn prepare_ledger(
+    replica: &Replica,
+    id: Principal,
+    balances: Vec<(Account, Nat)>,
+) -> CanisterHandle<'_> {
+    let l = replica.add_canister(MockLedgerCanister::build(id));
+
+    let env = ic_kit_runtime::types::Env::init().with_arg(balances);
+    l.run_env(env).await;
+
+    l
+}
+
+pub async fn balance_of(ledger: &CanisterHandle<'_>, account: &Account) -> Nat {
+    ledger
+        .new_call("icrc1_balance_of")
+        .with_arg(account.clone())
+        .perform()
+        .await
+        .decode_one()
+        .unwrap()
+}
@@ -6,10 +6,20 @@ use std::time::Duration;
 
 use ic_kit::prelude::*;
 use ic_kit_runtime::handle::CanisterHandle;
+use icrc7::airdrop::*;
+use icrc7::denylist::*;
+use icrc7::history::*;
+use icrc7::limits::*;
+use icrc7::pause::*;
+use icrc7::royalties::*;
 use icrc7::state::*;
+use icrc7::transactions::*;
+use icrc7::voucher::*;
 use icrc7::*;
 use rt::types::{EntryMode, Env};
 
+use ed25519_dalek::{Signer, SigningKey};
+
 #[kit_test]
 async fn test_basic(replica: Replica) {
     let c = prepare_initialized_canister(&replica).await;
@@ -171,6 +181,8 @@ async fn test_add_tokens(replica: Replica) {
             icrc7_id: 1.into(),
             icrc7_name: "NFT-1".to_owned(),
             icrc7_image: vec![65, 65, 65, 65],
+            icrc7_transferable: true,
+            icrc7_revealed: true,
         }
     );
 
@@ -192,6 +204,10 @@ async fn test_add_tokens(replica: Replica) {
             name: "NFT-2".to_owned(),
             image: "QUFBQQ".to_owned(),
             owner: Account::default(),
+            transferable: None,
+            locked_until: None,
+            royalties: None,
+            reveal_hash: None,
         })
         .perform()
         .await
@@ -316,6 +332,82 @@ async fn test_old_transfers(replica: Replica) {
     );
 }
 
+#[kit_test]
+async fn test_configured_time_drift(replica: Replica) {
+    let c = prepare_canister(
+        &replica,
+        InitArgs {
+            permitted_drift: Some(MINUTE * 5),
+            ..test_init_args()
+        },
+    )
+    .await;
+
+    let drift: Nat = c
+        .new_call("icrc7_permitted_drift")
+        .perform()
+        .await
+        .decode_one()
+        .unwrap();
+    assert_eq!(drift, MINUTE * 5);
+
+    let window: Nat = c
+        .new_call("icrc7_tx_window")
+        .perform()
+        .await
+        .decode_one()
+        .unwrap();
+    assert_eq!(window, TX_DEDUPLICATION_WINDOW);
+
+    let owner_acc = Account::default();
+    let to_acc = Account::from_owner(Principal::from_slice(&[0x1]));
+
+    add_token(&c, 1.into(), "NFT-1", &owner_acc).await;
+    add_token(&c, 2.into(), "NFT-2", &owner_acc).await;
+
+    let mut args = TransferArgs {
+        from: None,
+        to: to_acc.clone(),
+        token_ids: HashSet::from([1.into()]),
+        memo: None,
+        created_at_time: Some(NOW - MINUTE * 3),
+        is_atomic: None,
+    };
+
+    perform_transfer(&c, args.clone(), owner_acc.owner)
+        .await
+        .expect("transfer should be within permitted drift");
+
+    // only authority can change settings
+    let settings = TxWindowArgs {
+        tx_window: None,
+        permitted_drift: Some(MINUTE),
+    };
+
+    let reply: Result<(), String> = c
+        .new_call("set_tx_window")
+        .with_arg(settings.clone())
+        .with_caller(to_acc.owner)
+        .perform()
+        .await
+        .decode_one()
+        .unwrap();
+    assert!(reply.is_err());
+
+    let reply: Result<(), String> = c
+        .new_call("set_tx_window")
+        .with_arg(settings.clone())
+        .perform()
+        .await
+        .decode_one()
+        .unwrap();
+    assert_eq!(reply, Ok(()));
+
+    args.token_ids = HashSet::from([2.into()]);
+    let reply = perform_transfer(&c, args.clone(), owner_acc.owner).await;
+    assert_eq!(reply.unwrap_err(), TransferError::TooOld);
+}
+
 #[kit_test]
 async fn test_atomic_transfers(replica: Replica) {
     let c = prepare_initialized_canister(&replica).await;
@@ -433,6 +525,36 @@ async fn test_transfer_memo_deduplication(replica: Replica) {
     );
 }
 
+#[kit_test]
+async fn test_transfer_without_created_at_is_not_deduplicated(replica: Replica) {
+    let c = prepare_initialized_canister(&replica).await;
+
+    let owner_acc = Account::default();
+    let to_acc = Account::from_owner(Principal::from_slice(&[0x22]));
+
+    add_token(&c, 1.into(), "NFT-1", &owner_acc).await;
+
+    let args = TransferArgs {
+        from: None,
+        to: to_acc.clone(),
+        token_ids: HashSet::from([1.into()]),
+        memo: None,
+        created_at_time: None,
+        is_atomic: None,
+    };
+
+    perform_transfer(&c, args.clone(), owner_acc.owner)
+        .await
+        .expect("first transfer should succeed");
+
+    // second transfer is processed as a new one and fails, because token was already moved
+    let reply = perform_transfer(&c, args.clone(), owner_acc.owner).await;
+    assert!(matches!(
+        reply.unwrap_err(),
+        TransferError::Unauthorized { .. }
+    ));
+}
+
 #[kit_test]
 async fn test_approvals(replica: Replica) {
     let c = prepare_initialized_canister(&replica).await;
@@ -588,6 +710,175 @@ async fn test_approvals_for_different_subaccounts(replica: Replica) {
         .expect("transfer should succeed");
 }
 
+#[kit_test]
+async fn test_multiple_approvals(replica: Replica) {
+    let c = prepare_initialized_canister(&replica).await;
+
+    let owner_acc = Account::from_owner(Principal::from_slice(&[0x1]));
+    let other_delegate_acc = Account::from_owner(Principal::from_slice(&[0x4]));
+    let delegate_acc = Account::from_owner(Principal::from_slice(&[0x2, 0x2]));
+    let to_acc = Account::from_owner(Principal::from_slice(&[0x3, 0x3, 0x3]));
+
+    add_token(&c, 1.into(), "NFT-1", &owner_acc).await;
+
+    // first approval is for another delegate, it must not shadow the second one
+    let approve_args = ApproveArgs {
+        from_subaccount: None,
+        to: other_delegate_acc.owner,
+        token_ids: None,
+        memo: None,
+        created_at: None,
+        expires_at: None,
+    };
+
+    perform_approve(&c, approve_args, owner_acc.owner)
+        .await
+        .expect("approve should succeed");
+
+    let approve_args = ApproveArgs {
+        from_subaccount: None,
+        to: delegate_acc.owner,
+        token_ids: Some(HashSet::from([1.into()])),
+        memo: None,
+        created_at: None,
+        expires_at: None,
+    };
+
+    let approval_id = perform_approve(&c, approve_args, owner_acc.owner)
+        .await
+        .expect("approve should succeed");
+    assert_eq!(approval_id, 1);
+
+    let args = TransferArgs {
+        from: Some(owner_acc.clone()),
+        to: to_acc.clone(),
+        token_ids: HashSet::from([1.into()]),
+        memo: None,
+        created_at_time: None,
+        is_atomic: None,
+    };
+
+    perform_transfer(&c, args.clone(), delegate_acc.owner)
+        .await
+        .expect("transfer should succeed");
+}
+
+#[kit_test]
+async fn test_transfer_from_wrong_subaccount(replica: Replica) {
+    let c = prepare_initialized_canister(&replica).await;
+
+    let owner_acc = Account::from_owner(Principal::from_slice(&[0x1]));
+    let owner_sub_acc = Account::new(owner_acc.owner, Some([1; 32]));
+    let to_acc = Account::from_owner(Principal::from_slice(&[0x3, 0x3, 0x3]));
+
+    add_token(&c, 1.into(), "NFT-1", &owner_sub_acc).await;
+
+    // token belongs to subaccount, not to the default account of the caller
+    let mut args = TransferArgs {
+        from: None,
+        to: to_acc.clone(),
+        token_ids: HashSet::from([1.into()]),
+        memo: None,
+        created_at_time: None,
+        is_atomic: None,
+    };
+
+    let reply = perform_transfer(&c, args.clone(), owner_acc.owner).await;
+    assert_eq!(
+        reply.unwrap_err(),
+        TransferError::Unauthorized {
+            token_ids: vec![1.into()]
+        }
+    );
+
+    args.from = Some(owner_sub_acc.clone());
+    perform_transfer(&c, args.clone(), owner_acc.owner)
+        .await
+        .expect("transfer should succeed");
+}
+
+#[kit_test]
+async fn test_approve_from_wrong_subaccount(replica: Replica) {
+    let c = prepare_initialized_canister(&replica).await;
+
+    let owner_acc = Account::from_owner(Principal::from_slice(&[0x1]));
+    let owner_sub_acc = Account::new(owner_acc.owner, Some([1; 32]));
+    let delegate_acc = Account::from_owner(Principal::from_slice(&[0x2, 0x2]));
+
+    add_token(&c, 1.into(), "NFT-1", &owner_sub_acc).await;
+
+    // token is owned by subaccount, but approval is made from default account
+    let mut approve_args = ApproveArgs {
+        from_subaccount: None,
+        to: delegate_acc.owner,
+        token_ids: Some(HashSet::from([1.into()])),
+        memo: None,
+        created_at: None,
+        expires_at: None,
+    };
+
+    let reply = perform_approve(&c, approve_args.clone(), owner_acc.owner).await;
+    assert!(matches!(reply, Err(AppprovalError::Unauthorized(ids)) if ids == vec![Nat::from(1)]));
+
+    // non-existent tokens can not be approved either
+    approve_args.from_subaccount = owner_sub_acc.subaccount;
+    approve_args.token_ids = Some(HashSet::from([1.into(), 2.into()]));
+
+    let reply = perform_approve(&c, approve_args.clone(), owner_acc.owner).await;
+    assert!(matches!(reply, Err(AppprovalError::Unauthorized(ids)) if ids == vec![Nat::from(2)]));
+
+    approve_args.token_ids = Some(HashSet::from([1.into()]));
+    perform_approve(&c, approve_args, owner_acc.owner)
+        .await
+        .expect("approve should succeed");
+}
+
+#[kit_test]
+async fn test_delegate_transfer_from_wrong_subaccount(replica: Replica) {
+    let c = prepare_initialized_canister(&replica).await;
+
+    let owner_acc = Account::from_owner(Principal::from_slice(&[0x1]));
+    let owner_sub_acc = Account::new(owner_acc.owner, Some([1; 32]));
+    let delegate_acc = Account::from_owner(Principal::from_slice(&[0x2, 0x2]));
+    let to_acc = Account::from_owner(Principal::from_slice(&[0x3, 0x3, 0x3]));
+
+    add_token(&c, 1.into(), "NFT-1", &owner_sub_acc).await;
+
+    // approval only covers default account of the owner
+    let approve_args = ApproveArgs {
+        from_subaccount: None,
+        to: delegate_acc.owner,
+        token_ids: None,
+        memo: None,
+        created_at: None,
+        expires_at: None,
+    };
+
+    perform_approve(&c, approve_args, owner_acc.owner)
+        .await
+        .expect("approve should succeed");
+
+    let mut args = TransferArgs {
+        from: Some(owner_acc.clone()),
+        to: to_acc.clone(),
+        token_ids: HashSet::from([1.into()]),
+        memo: None,
+        created_at_time: None,
+        is_atomic: None,
+    };
+
+    // token is not in the approved account
+    perform_transfer(&c, args.clone(), delegate_acc.owner)
+        .await
+        .expect_err("transfer should fail");
+
+    // approval does not cover the subaccount the token is in
+    args.from = Some(owner_sub_acc.clone());
+    perform_transfer(&c, args.clone(), delegate_acc.owner)
+        .await
+        .expect_err("transfer should fail");
+}
+
 #[kit_test]
 async fn test_expired_approvals(replica: Replica) {
     let c = prepare_initialized_canister(&replica).await;
@@ -675,56 +966,1088 @@ async fn test_transfer_to_self(replica: Replica) {
     ));
 }
 
-/// helper to call transfer on the canister with predefined time
-async fn perform_transfer(
-    c: &CanisterHandle<'_>,
-    args: TransferArgs,
-    caller: Principal,
-) -> Result<TransferID, TransferError> {
-    let env = Env::default()
-        .with_entry_mode(EntryMode::Update)
-        .with_method_name("icrc7_transfer")
-        .with_arg(args)
-        .with_time(NOW)
-        .with_sender(caller);
-
-    c.run_env(env)
-        .await
-        .decode_one()
-        .expect("call should succeed")
-}
-
-async fn perform_approve(
-    c: &CanisterHandle<'_>,
-    args: ApproveArgs,
-    caller: Principal,
-) -> Result<ApprovalID, AppprovalError> {
-    c.new_call("icrc7_approve")
-        .with_arg(args)
-        .with_caller(caller)
+#[kit_test]
+async fn test_limits(replica: Replica) {
+    let c = prepare_canister(
+        &replica,
+        InitArgs {
+            limits: Some(Limits {
+                max_memo_bytes: 4,
+                max_batch_size: 2,
+                max_image_bytes: 4,
+                max_name_length: 5,
+                max_approvals_per_owner: 1,
+                max_airdrop_entries: 10,
+            }),
+            ..test_init_args()
+        },
+    )
+    .await;
+
+    let max_memo_size: Nat = c
+        .new_call("icrc7_max_memo_size")
         .perform()
         .await
         .decode_one()
-        .unwrap()
-}
+        .unwrap();
+    assert_eq!(max_memo_size, 4);
 
-#[kit_test]
-async fn test_non_existent_tokens(replica: Replica) {
-    let c = prepare_initialized_canister(&replica).await;
+    let owner_acc = Account::from_owner(Principal::from_slice(&[0x1]));
+    let delegate_acc = Account::from_owner(Principal::from_slice(&[0x2, 0x2]));
+    let to_acc = Account::from_owner(Principal::from_slice(&[0x3, 0x3, 0x3]));
 
-    assert!(c
-        .new_call("icrc7_metadata")
-        .with_arg(Nat::from(1))
-        .perform()
-        .await
-        .decode_one::<Option<TokenMetadata>>()
-        .unwrap()
-        .is_none());
-}
+    for (name, image) in [("NFT-too-long", "QUFBQQ"), ("NFT-1", "QUFBQUFB")] {
+        let resp: Result<TokenID, String> = c
+            .new_call("mint_token")
+            .with_arg(MintTokenArgs {
+                id: 1.into(),
+                name: name.to_owned(),
+                image: image.to_owned(),
+                owner: owner_acc.clone(),
+                transferable: None,
+                locked_until: None,
+                royalties: None,
+                reveal_hash: None,
+            })
+            .perform()
+            .await
+            .decode_one()
+            .unwrap();
+        assert!(resp.is_err(), "{} should not be minted", name);
+    }
 
-#[kit_test]
-async fn test_supported_standards(replica: Replica) {
-    let c = prepare_initialized_canister(&replica).await;
+    add_token(&c, 1.into(), "NFT-1", &owner_acc).await;
+
+    let mut args = TransferArgs {
+        from: None,
+        to: to_acc.clone(),
+        token_ids: HashSet::from([1.into()]),
+        memo: Some(vec![0; 5]),
+        created_at_time: None,
+        is_atomic: None,
+    };
+
+    let reply = perform_transfer(&c, args.clone(), owner_acc.owner).await;
+    assert!(
+        matches!(reply, Err(TransferError::GenericError { error_code, .. }) if error_code == 5)
+    );
+
+    args.memo = None;
+    args.token_ids = HashSet::from([1.into(), 2.into(), 3.into()]);
+    let reply = perform_transfer(&c, args.clone(), owner_acc.owner).await;
+    assert!(
+        matches!(reply, Err(TransferError::GenericError { error_code, .. }) if error_code == 5)
+    );
+
+    let approve_args = ApproveArgs {
+        from_subaccount: None,
+        to: delegate_acc.owner,
+        token_ids: None,
+        memo: None,
+        created_at: None,
+        expires_at: None,
+    };
+
+    perform_approve(&c, approve_args.clone(), owner_acc.owner)
+        .await
+        .expect("approve should succeed");
+
+    let reply = perform_approve(&c, approve_args, owner_acc.owner).await;
+    assert!(
+        matches!(reply, Err(AppprovalError::GenericError { error_code, .. }) if error_code == 5)
+    );
+}
+
+#[kit_test]
+async fn test_pause(replica: Replica) {
+    let c = prepare_initialized_canister(&replica).await;
+
+    let owner_acc = Account::from_owner(Principal::from_slice(&[0x1]));
+    let pauser = Principal::from_slice(&[0x2, 0x2]);
+    let to_acc = Account::from_owner(Principal::from_slice(&[0x3, 0x3, 0x3]));
+
+    add_token(&c, 1.into(), "NFT-1", &owner_acc).await;
+
+    let reply: Result<(), String> = c
+        .new_call("add_pauser")
+        .with_arg(pauser)
+        .perform()
+        .await
+        .decode_one()
+        .unwrap();
+    assert_eq!(reply, Ok(()));
+
+    let paused = PauseState {
+        transfers: true,
+        approvals: false,
+        minting: false,
+    };
+
+    let reply: Result<(), String> = c
+        .new_call("set_pause_state")
+        .with_arg(paused.clone())
+        .with_caller(pauser)
+        .perform()
+        .await
+        .decode_one()
+        .unwrap();
+    assert_eq!(reply, Ok(()));
+
+    let state: PauseState = c
+        .new_call("pause_state")
+        .perform()
+        .await
+        .decode_one()
+        .unwrap();
+    assert_eq!(state, paused);
+
+    let args = TransferArgs {
+        from: None,
+        to: to_acc.clone(),
+        token_ids: HashSet::from([1.into()]),
+        memo: None,
+        created_at_time: None,
+        is_atomic: None,
+    };
+
+    let reply = perform_transfer(&c, args.clone(), owner_acc.owner).await;
+    assert_eq!(reply.unwrap_err(), TransferError::TemporarilyUnavailable);
+
+    // approvals are paused independently
+    let approve_args = ApproveArgs {
+        from_subaccount: None,
+        to: pauser,
+        token_ids: None,
+        memo: None,
+        created_at: None,
+        expires_at: None,
+    };
+    perform_approve(&c, approve_args, owner_acc.owner)
+        .await
+        .expect("approve should succeed");
+
+    // pauser can not resume operations
+    let reply: Result<(), String> = c
+        .new_call("set_pause_state")
+        .with_arg(PauseState::default())
+        .with_caller(pauser)
+        .perform()
+        .await
+        .decode_one()
+        .unwrap();
+    assert!(reply.is_err());
+
+    let reply: Result<(), String> = c
+        .new_call("set_pause_state")
+        .with_arg(PauseState::default())
+        .perform()
+        .await
+        .decode_one()
+        .unwrap();
+    assert_eq!(reply, Ok(()));
+
+    perform_transfer(&c, args.clone(), owner_acc.owner)
+        .await
+        .expect("transfer should succeed");
+}
+
+#[kit_test]
+async fn test_soulbound(replica: Replica) {
+    let c = prepare_canister(
+        &replica,
+        InitArgs {
+            revocable: Some(true),
+            ..test_init_args()
+        },
+    )
+    .await;
+
+    let owner_acc = Account::from_owner(Principal::from_slice(&[0x1]));
+    let to_acc = Account::from_owner(Principal::from_slice(&[0x2, 0x2]));
+
+    add_token(&c, 1.into(), "NFT-1", &owner_acc).await;
+
+    let resp: Result<TokenID, String> = c
+        .new_call("mint_token")
+        .with_arg(MintTokenArgs {
+            id: 2.into(),
+            name: "Badge".to_owned(),
+            image: "QUFBQQ".to_owned(),
+            owner: owner_acc.clone(),
+            transferable: Some(false),
+            locked_until: None,
+            royalties: None,
+            reveal_hash: None,
+        })
+        .perform()
+        .await
+        .decode_one()
+        .unwrap();
+    assert_eq!(resp, Ok(2.into()));
+
+    let metadata: Option<TokenMetadata> = c
+        .new_call("icrc7_metadata")
+        .with_arg(Nat::from(2))
+        .perform()
+        .await
+        .decode_one()
+        .unwrap();
+    assert!(!metadata.unwrap().icrc7_transferable);
+
+    let args = TransferArgs {
+        from: None,
+        to: to_acc.clone(),
+        token_ids: HashSet::from([2.into()]),
+        memo: None,
+        created_at_time: None,
+        is_atomic: None,
+    };
+    let reply = perform_transfer(&c, args, owner_acc.owner).await;
+    assert!(matches!(
+        reply,
+        Err(TransferError::GenericError { error_code, .. }) if error_code == 6
+    ));
+
+    let approve_args = ApproveArgs {
+        from_subaccount: None,
+        to: to_acc.owner,
+        token_ids: Some(HashSet::from([1.into(), 2.into()])),
+        memo: None,
+        created_at: None,
+        expires_at: None,
+    };
+    let reply = perform_approve(&c, approve_args, owner_acc.owner).await;
+    assert!(matches!(
+        reply,
+        Err(AppprovalError::GenericError { error_code, .. }) if error_code == 6
+    ));
+
+    // only non-transferable tokens can be burned
+    let reply: Result<TokenID, String> = c
+        .new_call("burn_token")
+        .with_arg(Nat::from(1))
+        .perform()
+        .await
+        .decode_one()
+        .unwrap();
+    assert!(reply.is_err());
+
+    let reply: Result<TokenID, String> = c
+        .new_call("burn_token")
+        .with_arg(Nat::from(2))
+        .perform()
+        .await
+        .decode_one()
+        .unwrap();
+    assert_eq!(reply, Ok(2.into()));
+
+    let supply: Nat = c
+        .new_call("icrc7_total_supply")
+        .perform()
+        .await
+        .decode_one()
+        .unwrap();
+    assert_eq!(supply, Nat::from(1));
+}
+
+#[kit_test]
+async fn test_token_lock(replica: Replica) {
+    let c = prepare_initialized_canister(&replica).await;
+
+    let owner_acc = Account::from_owner(Principal::from_slice(&[0x1]));
+    let to_acc = Account::from_owner(Principal::from_slice(&[0x2, 0x2]));
+    let until = NOW + Duration::from_secs(30 * 24 * 60 * 60).as_nanos() as u64;
+
+    let resp: Result<TokenID, String> = c
+        .new_call("mint_token")
+        .with_arg(MintTokenArgs {
+            id: 1.into(),
+            name: "Reward".to_owned(),
+            image: "QUFBQQ".to_owned(),
+            owner: owner_acc.clone(),
+            transferable: None,
+            locked_until: Some(until),
+            royalties: None,
+            reveal_hash: None,
+        })
+        .perform()
+        .await
+        .decode_one()
+        .unwrap();
+    assert_eq!(resp, Ok(1.into()));
+
+    let env = Env::default()
+        .with_entry_mode(EntryMode::Query)
+        .with_method_name("icrc7_locked_tokens_of")
+        .with_arg(owner_acc.clone())
+        .with_time(NOW);
+    let locked: Vec<LockedToken> = c.run_env(env).await.decode_one().unwrap();
+    assert_eq!(
+        locked,
+        vec![LockedToken {
+            id: 1.into(),
+            locked_until: until,
+        }]
+    );
+
+    let args = TransferArgs {
+        from: None,
+        to: to_acc.clone(),
+        token_ids: HashSet::from([1.into()]),
+        memo: None,
+        created_at_time: None,
+        is_atomic: None,
+    };
+    let reply = perform_transfer(&c, args.clone(), owner_acc.owner).await;
+    assert_eq!(reply.unwrap_err(), TransferError::Locked { until });
+
+    // only authority can lift the lock
+    let lift = TokenLockArgs {
+        id: 1.into(),
+        locked_until: None,
+    };
+    let reply: Result<(), String> = c
+        .new_call("set_token_lock")
+        .with_arg(lift.clone())
+        .with_caller(owner_acc.owner)
+        .perform()
+        .await
+        .decode_one()
+        .unwrap();
+    assert!(reply.is_err());
+
+    let reply: Result<(), String> = c
+        .new_call("set_token_lock")
+        .with_arg(lift)
+        .perform()
+        .await
+        .decode_one()
+        .unwrap();
+    assert_eq!(reply, Ok(()));
+
+    perform_transfer(&c, args, owner_acc.owner)
+        .await
+        .expect("transfer should succeed");
+}
+
+#[kit_test]
+async fn test_denylist(replica: Replica) {
+    let c = prepare_initialized_canister(&replica).await;
+
+    let owner_acc = Account::from_owner(Principal::from_slice(&[0x1]));
+    let spender = Principal::from_slice(&[0x2, 0x2]);
+    let to_acc = Account::from_owner(Principal::from_slice(&[0x3, 0x3, 0x3]));
+
+    add_token(&c, 1.into(), "NFT-1", &owner_acc).await;
+
+    perform_approve(
+        &c,
+        ApproveArgs {
+            from_subaccount: None,
+            to: spender,
+            token_ids: None,
+            memo: None,
+            created_at: None,
+            expires_at: None,
+        },
+        owner_acc.owner,
+    )
+    .await
+    .expect("approve should succeed");
+
+    // only authority manages the denylist
+    let reply: Result<(), String> = c
+        .new_call("add_to_denylist")
+        .with_arg(DenylistTarget::Principal(spender))
+        .with_caller(spender)
+        .perform()
+        .await
+        .decode_one()
+        .unwrap();
+    assert!(reply.is_err());
+
+    let reply: Result<(), String> = c
+        .new_call("add_to_denylist")
+        .with_arg(DenylistTarget::Principal(spender))
+        .perform()
+        .await
+        .decode_one()
+        .unwrap();
+    assert_eq!(reply, Ok(()));
+
+    let reply: Result<(), String> = c
+        .new_call("add_to_denylist")
+        .with_arg(DenylistTarget::Account(to_acc.clone()))
+        .perform()
+        .await
+        .decode_one()
+        .unwrap();
+    assert_eq!(reply, Ok(()));
+
+    let denied: bool = c
+        .new_call("is_denied")
+        .with_arg(Account::new(spender, Some([1; 32])))
+        .perform()
+        .await
+        .decode_one()
+        .unwrap();
+    assert!(denied, "all accounts of denied principal are denied");
+
+    // denied spender can't use existing approval
+    let args = TransferArgs {
+        from: Some(owner_acc.clone()),
+        to: Account::from_owner(Principal::from_slice(&[0x4])),
+        token_ids: HashSet::from([1.into()]),
+        memo: None,
+        created_at_time: None,
+        is_atomic: None,
+    };
+    let reply = perform_transfer(&c, args, spender).await;
+    assert!(matches!(
+        reply,
+        Err(TransferError::GenericError { error_code, .. }) if error_code == 7
+    ));
+
+    // denied recipient
+    let args = TransferArgs {
+        from: None,
+        to: to_acc.clone(),
+        token_ids: HashSet::from([1.into()]),
+        memo: None,
+        created_at_time: None,
+        is_atomic: None,
+    };
+    let reply = perform_transfer(&c, args.clone(), owner_acc.owner).await;
+    assert!(matches!(
+        reply,
+        Err(TransferError::GenericError { error_code, .. }) if error_code == 7
+    ));
+
+    let reply: Result<(), String> = c
+        .new_call("remove_from_denylist")
+        .with_arg(DenylistTarget::Account(to_acc.clone()))
+        .perform()
+        .await
+        .decode_one()
+        .unwrap();
+    assert_eq!(reply, Ok(()));
+
+    perform_transfer(&c, args, owner_acc.owner)
+        .await
+        .expect("transfer should succeed");
+
+    let log: Vec<DenylistEntry> = c
+        .new_call("denylist_log")
+        .perform()
+        .await
+        .decode_one()
+        .unwrap();
+    let actions = log.iter().map(|e| e.action).collect::<Vec<_>>();
+    assert_eq!(
+        actions,
+        vec![
+            DenylistAction::Add,
+            DenylistAction::Add,
+            DenylistAction::Remove
+        ]
+    );
+}
+
+#[kit_test]
+async fn test_royalty_info(replica: Replica) {
+    let artist = Account::from_owner(Principal::from_slice(&[0x1]));
+    let studio = Account::from_owner(Principal::from_slice(&[0x2, 0x2]));
+    let owner_acc = Account::from_owner(Principal::from_slice(&[0x3, 0x3, 0x3]));
+
+    let c = prepare_canister(
+        &replica,
+        InitArgs {
+            royalties: 500,
+            royalty_recipients: Some(vec![
+                RoyaltyRecipient {
+                    account: artist.clone(),
+                    weight: 3,
+                },
+                RoyaltyRecipient {
+                    account: studio.clone(),
+                    weight: 1,
+                },
+            ]),
+            ..test_init_args()
+        },
+    )
+    .await;
+
+    add_token(&c, 1.into(), "NFT-1", &owner_acc).await;
+
+    let resp: Result<TokenID, String> = c
+        .new_call("mint_token")
+        .with_arg(MintTokenArgs {
+            id: 2.into(),
+            name: "NFT-2".to_owned(),
+            image: "QUFBQQ".to_owned(),
+            owner: owner_acc.clone(),
+            transferable: None,
+            locked_until: None,
+            royalties: Some(Royalties {
+                basis_points: 1000,
+                recipients: vec![RoyaltyRecipient {
+                    account: studio.clone(),
+                    weight: 1,
+                }],
+            }),
+            reveal_hash: None,
+        })
+        .perform()
+        .await
+        .decode_one()
+        .unwrap();
+    assert_eq!(resp, Ok(2.into()));
+
+    let info: Option<Vec<RoyaltyPayment>> = c
+        .new_call("royalty_info")
+        .with_args((Nat::from(1), Nat::from(10000)))
+        .perform()
+        .await
+        .decode_one()
+        .unwrap();
+    assert_eq!(
+        info,
+        Some(vec![
+            RoyaltyPayment {
+                recipient: artist.clone(),
+                amount: Nat::from(375),
+            },
+            RoyaltyPayment {
+                recipient: studio.clone(),
+                amount: Nat::from(125),
+            },
+        ])
+    );
+
+    // token royalties override collection-wide ones
+    let info: Option<Vec<RoyaltyPayment>> = c
+        .new_call("royalty_info")
+        .with_args((Nat::from(2), Nat::from(10000)))
+        .perform()
+        .await
+        .decode_one()
+        .unwrap();
+    assert_eq!(
+        info,
+        Some(vec![RoyaltyPayment {
+            recipient: studio,
+            amount: Nat::from(1000),
+        }])
+    );
+
+    let info: Option<Vec<RoyaltyPayment>> = c
+        .new_call("royalty_info")
+        .with_args((Nat::from(3), Nat::from(10000)))
+        .perform()
+        .await
+        .decode_one()
+        .unwrap();
+    assert!(info.is_none());
+}
+
+#[kit_test]
+async fn test_redeem_voucher(replica: Replica) {
+    let c = prepare_initialized_canister(&replica).await;
+
+    let recipient = Account::from_owner(Principal::from_slice(&[0x1]));
+    let redeemer = Principal::from_slice(&[0x2, 0x2]);
+    let key = SigningKey::from_bytes(&[7; 32]);
+
+    let reply: Result<(), String> = c
+        .new_call("set_voucher_signer")
+        .with_arg(Some(key.verifying_key().to_bytes().to_vec()))
+        .perform()
+        .await
+        .decode_one()
+        .unwrap();
+    assert_eq!(reply, Ok(()));
+
+    let voucher = MintVoucher {
+        token_id: 1.into(),
+        name: "NFT-1".to_owned(),
+        image: "QUFBQQ".to_owned(),
+        recipient: recipient.clone(),
+        expires_at: NOW + 1,
+        nonce: 42,
+    };
+    // test canister is created with anonymous principal as ID
+    let signature = key.sign(&voucher.signed_message(&Principal::anonymous()));
+    let args = RedeemVoucherArgs {
+        voucher,
+        signature: signature.to_bytes().to_vec(),
+    };
+
+    let resp = perform_redeem(&c, args.clone(), redeemer).await;
+    assert_eq!(resp, Ok(1.into()));
+
+    // token goes to the recipient no matter who submits the voucher
+    let owner: Option<Account> = c
+        .new_call("icrc7_owner_of")
+        .with_arg(Nat::from(1))
+        .perform()
+        .await
+        .decode_one()
+        .unwrap();
+    assert_eq!(owner, Some(recipient));
+
+    let resp = perform_redeem(&c, args, redeemer).await;
+    assert_eq!(resp, Err("voucher has already been redeemed".to_owned()));
+
+    let redeemed: bool = c
+        .new_call("is_voucher_redeemed")
+        .with_arg(42u64)
+        .perform()
+        .await
+        .decode_one()
+        .unwrap();
+    assert!(redeemed);
+}
+
+async fn perform_redeem(
+    c: &CanisterHandle<'_>,
+    args: RedeemVoucherArgs,
+    caller: Principal,
+) -> Result<TokenID, String> {
+    let env = Env::default()
+        .with_entry_mode(EntryMode::Update)
+        .with_method_name("redeem_voucher")
+        .with_arg(args)
+        .with_time(NOW)
+        .with_sender(caller);
+
+    c.run_env(env).await.decode_one().unwrap()
+}
+
+#[kit_test]
+async fn test_airdrop(replica: Replica) {
+    let c = prepare_initialized_canister(&replica).await;
+
+    let alice = Account::from_owner(Principal::from_slice(&[0x1]));
+    let bob = Account::from_owner(Principal::from_slice(&[0x2, 0x2]));
+
+    add_token(
+        &c,
+        1.into(),
+        "NFT-1",
+        &Account::from_owner(Principal::anonymous()),
+    )
+    .await;
+
+    let metadata = AirdropMetadata {
+        name: "Airdrop".to_owned(),
+        image: "QUFBQQ".to_owned(),
+    };
+    let entries = vec![
+        AirdropEntry {
+            to: alice.clone(),
+            token_id: Some(1.into()),
+            metadata: None,
+        },
+        AirdropEntry {
+            to: bob.clone(),
+            token_id: None,
+            metadata: Some(metadata),
+        },
+        AirdropEntry {
+            to: bob.clone(),
+            token_id: None,
+            metadata: None,
+        },
+    ];
+
+    // only authority can airdrop
+    let resp: Result<AirdropID, String> = c
+        .new_call("airdrop")
+        .with_arg(entries.clone())
+        .with_caller(alice.owner)
+        .perform()
+        .await
+        .decode_one()
+        .unwrap();
+    assert!(resp.is_err());
+
+    let resp: Result<AirdropID, String> = c
+        .new_call("airdrop")
+        .with_arg(entries)
+        .perform()
+        .await
+        .decode_one()
+        .unwrap();
+    let id = resp.unwrap();
+
+    let report: Option<AirdropReport> = c
+        .new_call("airdrop_status")
+        .with_arg(id)
+        .perform()
+        .await
+        .decode_one()
+        .unwrap();
+    let report = report.unwrap();
+    assert!(report.finished_at.is_some());
+    assert!(matches!(
+        report.statuses[0],
+        AirdropStatus::Transferred { .. }
+    ));
+    assert_eq!(
+        report.statuses[1],
+        AirdropStatus::Minted { token_id: 0.into() }
+    );
+    assert_eq!(
+        report.statuses[2],
+        AirdropStatus::Failed {
+            message: "metadata is required to mint".to_owned()
+        }
+    );
+
+    let owner: Option<Account> = c
+        .new_call("icrc7_owner_of")
+        .with_arg(Nat::from(1))
+        .perform()
+        .await
+        .decode_one()
+        .unwrap();
+    assert_eq!(owner, Some(alice));
+
+    let resp: Result<bool, String> = c
+        .new_call("resume_airdrop")
+        .with_arg(id)
+        .perform()
+        .await
+        .decode_one()
+        .unwrap();
+    assert_eq!(resp, Ok(true));
+}
+
+#[kit_test]
+async fn test_token_history(replica: Replica) {
+    let c = prepare_initialized_canister(&replica).await;
+
+    let owner_acc = Account::default();
+    let alice = Account::from_owner(Principal::from_slice(&[0x1]));
+
+    add_token(&c, 1.into(), "NFT-1", &owner_acc).await;
+
+    let args = |to: &Account| TransferArgs {
+        from: None,
+        to: to.clone(),
+        token_ids: HashSet::from([1.into()]),
+        memo: None,
+        created_at_time: None,
+        is_atomic: None,
+    };
+    perform_transfer(&c, args(&alice), owner_acc.owner)
+        .await
+        .unwrap();
+    perform_transfer(&c, args(&owner_acc), alice.owner)
+        .await
+        .unwrap();
+
+    // transaction 0 is the mint
+    for (at, expected) in [
+        (HistoryPoint::Block(1.into()), Some(alice.clone())),
+        (HistoryPoint::Block(2.into()), Some(owner_acc.clone())),
+        (HistoryPoint::Timestamp(NOW), Some(owner_acc.clone())),
+    ] {
+        let owner: Option<Account> = c
+            .new_call("owner_of_at")
+            .with_args((Nat::from(1), at))
+            .perform()
+            .await
+            .decode_one()
+            .unwrap();
+        assert_eq!(owner, expected);
+    }
+
+    let history: Vec<OwnershipRecord> = c
+        .new_call("token_history")
+        .with_args((Nat::from(1), Some(0u64), None::<u32>))
+        .perform()
+        .await
+        .decode_one()
+        .unwrap();
+    assert_eq!(
+        history.iter().map(|r| r.owner.clone()).collect::<Vec<_>>(),
+        vec![Some(alice), Some(owner_acc)]
+    );
+    assert_eq!(history[0].timestamp, NOW);
+    assert_eq!(history[0].tx_id, Nat::from(1));
+}
+
+#[kit_test]
+async fn test_account_transactions(replica: Replica) {
+    let c = prepare_initialized_canister(&replica).await;
+
+    let owner_acc = Account::default();
+    let alice = Account::from_owner(Principal::from_slice(&[0x1]));
+
+    add_token(&c, 1.into(), "NFT-1", &owner_acc).await;
+    add_token(&c, 2.into(), "NFT-2", &alice).await;
+
+    let args = TransferArgs {
+        from: None,
+        to: alice.clone(),
+        token_ids: HashSet::from([1.into()]),
+        memo: None,
+        created_at_time: None,
+        is_atomic: None,
+    };
+    let transfer_id = perform_transfer(&c, args, owner_acc.owner).await.unwrap();
+
+    let get = |start: Option<u64>| GetAccountTransactionsArgs {
+        account: alice.clone(),
+        start: start.map(Into::into),
+        max_results: 1.into(),
+    };
+
+    let resp: GetTransactionsResult = c
+        .new_call("get_account_transactions")
+        .with_arg(get(None))
+        .perform()
+        .await
+        .decode_one()
+        .unwrap();
+    let resp = resp.unwrap();
+    assert_eq!(resp.balance, Nat::from(2));
+    assert_eq!(resp.oldest_tx_id, Some(1.into()));
+    assert_eq!(resp.transactions.len(), 1);
+    assert_eq!(resp.transactions[0].id, Nat::from(2));
+    assert_eq!(
+        resp.transactions[0].transaction.transfer,
+        Some(TransferTx {
+            from: owner_acc.to_canonical(),
+            to: alice.clone(),
+            token_ids: vec![1.into()],
+            memo: None,
+            spender: None,
+            transfer_id,
+        })
+    );
+
+    let resp: GetTransactionsResult = c
+        .new_call("get_account_transactions")
+        .with_arg(get(Some(2)))
+        .perform()
+        .await
+        .decode_one()
+        .unwrap();
+    let tx = &resp.unwrap().transactions[0];
+    assert_eq!(tx.id, Nat::from(1));
+    assert_eq!(tx.transaction.kind, "mint");
+}
+
+/// helper to call transfer on the canister with predefined time
+async fn perform_transfer(
+    c: &CanisterHandle<'_>,
+    args: TransferArgs,
+    caller: Principal,
+) -> Result<TransferID, TransferError> {
+    let env = Env::default()
+        .with_entry_mode(EntryMode::Update)
+        .with_method_name("icrc7_transfer")
+        .with_arg(args)
+        .with_time(NOW)
+        .with_sender(caller);
+
+    c.run_env(env)
+        .await
+        .decode_one()
+        .expect("call should succeed")
+}
+
+async fn perform_approve(
+    c: &CanisterHandle<'_>,
+    args: ApproveArgs,
+    caller: Principal,
+) -> Result<ApprovalID, AppprovalError> {
+    c.new_call("icrc7_approve")
+        .with_arg(args)
+        .with_caller(caller)
+        .perform()
+        .await
+        .decode_one()
+        .unwrap()
+}
+
+#[kit_test]
+async fn test_upgrade(replica: Replica) {
+    let c = prepare_initialized_canister(&replica).await;
+
+    let owner_acc = Account::from_owner(Principal::from_slice(&[0x1]));
+    let delegate_acc = Account::from_owner(Principal::from_slice(&[0x2, 0x2]));
+    let to_acc = Account::from_owner(Principal::from_slice(&[0x3, 0x3, 0x3]));
+
+    add_token(&c, 1.into(), "NFT-1", &owner_acc).await;
+
+    let approve_args = ApproveArgs {
+        from_subaccount: None,
+        to: delegate_acc.owner,
+        token_ids: None,
+        memo: None,
+        created_at: None,
+        expires_at: None,
+    };
+    perform_approve(&c, approve_args, owner_acc.owner)
+        .await
+        .unwrap();
+
+    c.run_env(Env::default().with_entry_mode(EntryMode::PreUpgrade))
+        .await;
+    c.run_env(Env::default().with_entry_mode(EntryMode::PostUpgrade))
+        .await;
+
+    // approval indexes are rebuilt after upgrade
+    let args = TransferArgs {
+        from: Some(owner_acc.clone()),
+        to: to_acc.clone(),
+        token_ids: HashSet::from([1.into()]),
+        memo: None,
+        created_at_time: None,
+        is_atomic: None,
+    };
+    perform_transfer(&c, args, delegate_acc.owner)
+        .await
+        .expect("transfer should succeed");
+}
+
+/// state layout of the first release, as it was saved to stable memory
+mod baseline {
+    use std::collections::{BTreeMap, HashMap, HashSet};
+
+    use ic_kit::prelude::*;
+    use icrc7::state::{Account, Approval, ApprovalID, TokenID, TransferID};
+
+    #[derive(CandidType, Serialize)]
+    pub struct Collection {
+        pub name: String,
+        pub symbol: String,
+        pub royalties: u16,
+        pub royalty_recipient: Account,
+        pub description: Option<String>,
+        pub image: Option<Vec<u8>>,
+        pub supply_cap: Option<usize>,
+        pub authority: Option<Principal>,
+        pub tokens: HashMap<TokenID, Token>,
+        pub approval_id_seq: ApprovalID,
+        pub approvals: HashMap<ApprovalID, Approval>,
+        pub approvals_by_principal: HashMap<Principal, Vec<ApprovalID>>,
+        pub transfer_id_seq: TransferID,
+        pub transfers: BTreeMap<(u64, TransferID), Transfer>,
+    }
+
+    #[derive(CandidType, Serialize)]
+    pub struct Transfer {
+        pub from: Account,
+        pub to: Account,
+        pub token_ids: HashSet<TokenID>,
+        pub memo: Option<Vec<u8>>,
+        pub created_at: u64,
+    }
+
+    #[derive(CandidType, Serialize)]
+    pub struct Token {
+        pub id: TokenID,
+        pub name: String,
+        pub image: Vec<u8>,
+        pub owner: Account,
+    }
+}
+
+#[test]
+fn test_restore_baseline_state() {
+    let owner_acc = Account::from_owner(Principal::from_slice(&[0x1]));
+    let delegate = Principal::from_slice(&[0x2, 0x2]);
+
+    let approval = Approval {
+        from: owner_acc.owner,
+        from_subaccount: None,
+        to: delegate,
+        token_ids: None,
+        expires_at: None,
+        memo: None,
+    };
+    let saved = baseline::Collection {
+        name: "test collection".to_owned(),
+        symbol: "TEST".to_owned(),
+        royalties: 1000,
+        royalty_recipient: Account::default(),
+        description: None,
+        image: None,
+        supply_cap: Some(10),
+        authority: Some(Principal::anonymous()),
+        tokens: [(
+            Nat::from(1),
+            baseline::Token {
+                id: 1.into(),
+                name: "NFT-1".to_owned(),
+                image: vec![],
+                owner: owner_acc.clone(),
+            },
+        )]
+        .into(),
+        approval_id_seq: 1.into(),
+        approvals: [(Nat::from(0), approval)].into(),
+        approvals_by_principal: [(owner_acc.owner, vec![Nat::from(0)])].into(),
+        transfer_id_seq: 1.into(),
+        transfers: [(
+            (NOW, Nat::from(0)),
+            baseline::Transfer {
+                from: Account::default(),
+                to: owner_acc.clone(),
+                token_ids: [Nat::from(1)].into(),
+                memo: None,
+                created_at: NOW,
+            },
+        )]
+        .into(),
+    };
+    let bytes = candid::encode_args((saved,)).unwrap();
+
+    let c = icrc7::upgrade::restore_collection(
+        || candid::decode_args(&bytes).map_err(|e| e.to_string()),
+        || candid::decode_args(&bytes).map_err(|e| e.to_string()),
+    )
+    .expect("baseline state should be restored");
+
+    assert_eq!(c.tokens[&Nat::from(1)].owner, owner_acc);
+    assert!(c.is_transferable(&c.tokens[&Nat::from(1)]));
+    assert_eq!(c.transfers.len(), 1);
+    assert_eq!(c.transfer_id_seq, Nat::from(1));
+    assert_eq!(c.approvals_count[&owner_acc], 1);
+    assert_eq!(
+        c.find_approval_for_delegate(&owner_acc, &delegate, &1.into(), NOW),
+        Some(0.into())
+    );
+
+    // features added later start from defaults
+    assert_eq!(c.limits, Limits::default());
+    assert_eq!(c.maintenance.budget, 1000);
+    assert!(!c.pause.state.transfers);
+    assert_eq!(c.transactions.log[0].kind, "mint");
+}
+
+#[kit_test]
+async fn test_non_existent_tokens(replica: Replica) {
+    let c = prepare_initialized_canister(&replica).await;
+
+    assert!(c
+        .new_call("icrc7_metadata")
+        .with_arg(Nat::from(1))
+        .perform()
+        .await
+        .decode_one::<Option<TokenMetadata>>()
+        .unwrap()
+        .is_none());
+}
+
+#[kit_test]
+async fn test_supported_standards(replica: Replica) {
+    let c = prepare_initialized_canister(&replica).await;
 
     let standards = c
         .new_call("icrc7_supported_standards")
@@ -736,19 +2059,33 @@ async fn test_supported_standards(replica: Replica) {
     assert_eq!(standards.len(), 1);
 }
 
-async fn prepare_initialized_canister(replica: &Replica) -> CanisterHandle {
-    let r = replica.add_canister(Icrc7Canister::anonymous());
-
-    let args = InitArgs {
+fn test_init_args() -> InitArgs {
+    InitArgs {
         name: "test collection".to_owned(),
         symbol: "TEST".to_owned(),
         description: Some("blah".to_owned()),
         royalties: 1000,
         royalty_recipient: Account::default(),
+        royalty_recipients: None,
         image: None,
         supply_cap: None,
         authority: Principal::anonymous(),
-    };
+        limits: None,
+        gc_interval: None,
+        gc_budget: None,
+        soulbound: None,
+        revocable: None,
+        tx_window: None,
+        permitted_drift: None,
+    }
+}
+
+async fn prepare_initialized_canister(replica: &Replica) -> CanisterHandle {
+    prepare_canister(replica, test_init_args()).await
+}
+
+async fn prepare_canister(replica: &Replica, args: InitArgs) -> CanisterHandle {
+    let r = replica.add_canister(Icrc7Canister::anonymous());
 
     let env = ic_kit_runtime::types::Env::init().with_arg(args);
     assert_eq!(
@@ -768,6 +2105,10 @@ async fn add_token(c: &CanisterHandle<'_>, id: TokenID, name: &str, owner: &Acco
             name: name.to_owned(),
             image: "QUFBQQ".to_owned(),
             owner: owner.clone(),
+            transferable: None,
+            locked_until: None,
+            royalties: None,
+            reveal_hash: None,
         })
         .perform()
         .await
@@ -0,0 +1,381 @@
+#![cfg(feature = "marketplace")]
+
+extern crate icrc7;
+
+mod common;
+
+use ic_kit::prelude::*;
+use ic_kit_runtime::handle::CanisterHandle;
+use icrc7::auction::*;
+use icrc7::ledger::*;
+use icrc7::marketplace::*;
+use icrc7::state::*;
+use icrc7::*;
+use rt::types::{EntryMode, Env};
+
+use common::*;
+
+const NOW: u64 = 3600000000000; // 1 hour in nanoseconds
+
+#[kit_test]
+async fn test_buy_token(replica: Replica) {
+    let collection_id = Principal::from_slice(&[0xC, 0x1]);
+    let ledger_id = Principal::from_slice(&[0xC, 0x2]);
+
+    let seller = Account::from_owner(Principal::from_slice(&[0x1]));
+    let buyer = Account::from_owner(Principal::from_slice(&[0x2, 0x2]));
+    let artist = Account::from_owner(Principal::from_slice(&[0x3, 0x3, 0x3]));
+
+    let ledger = prepare_ledger(&replica, ledger_id, vec![(buyer.clone(), 1500.into())]).await;
+    let c = prepare_collection(&replica, collection_id, ledger_id, &artist).await;
+
+    mint(&c, 1, &seller).await;
+
+    let reply: Result<(), String> = c
+        .new_call("list_token")
+        .with_arg(ListArgs {
+            token_id: 1.into(),
+            price: 1000.into(),
+            from_subaccount: None,
+        })
+        .with_caller(seller.owner)
+        .perform()
+        .await
+        .decode_one()
+        .unwrap();
+    assert_eq!(reply, Ok(()));
+
+    // seller keeps the token while listed, but can't transfer it
+    let owner: Option<Account> = c
+        .new_call("icrc7_owner_of")
+        .with_arg(Nat::from(1))
+        .perform()
+        .await
+        .decode_one()
+        .unwrap();
+    assert_eq!(owner, Some(seller.clone()));
+
+    let transfer = TransferArgs {
+        from: None,
+        to: buyer.clone(),
+        token_ids: [Nat::from(1)].into(),
+        memo: None,
+        created_at_time: None,
+        is_atomic: None,
+    };
+    let env = Env::default()
+        .with_entry_mode(EntryMode::Update)
+        .with_method_name("icrc7_transfer")
+        .with_arg(transfer)
+        .with_time(NOW)
+        .with_sender(seller.owner);
+    let reply: Result<TransferID, TransferError> = c.run_env(env).await.decode_one().unwrap();
+    assert!(matches!(
+        reply,
+        Err(TransferError::GenericError { error_code, .. }) if error_code == 8
+    ));
+
+    let mut args = BuyArgs {
+        token_id: 1.into(),
+        price: 900.into(),
+        from_subaccount: None,
+        to: None,
+    };
+    let reply: Result<TransferID, BuyError> = c
+        .new_call("buy_token")
+        .with_arg(args.clone())
+        .with_caller(buyer.owner)
+        .perform()
+        .await
+        .decode_one()
+        .unwrap();
+    assert_eq!(reply, Err(BuyError::PriceMismatch { price: 1000.into() }));
+
+    args.price = 1000.into();
+    let reply: Result<TransferID, BuyError> = c
+        .new_call("buy_token")
+        .with_arg(args.clone())
+        .with_caller(buyer.owner)
+        .perform()
+        .await
+        .decode_one()
+        .unwrap();
+    assert!(reply.is_ok());
+
+    let owner: Option<Account> = c
+        .new_call("icrc7_owner_of")
+        .with_arg(Nat::from(1))
+        .perform()
+        .await
+        .decode_one()
+        .unwrap();
+    assert_eq!(owner, Some(buyer.clone()));
+
+    // 10% royalties go to the artist
+    assert_eq!(proceeds_of(&c, &seller).await, Nat::from(900));
+    assert_eq!(proceeds_of(&c, &artist).await, Nat::from(100));
+    assert_eq!(balance_of(&ledger, &buyer).await, Nat::from(490));
+    assert_eq!(
+        balance_of(&ledger, &Account::from_owner(collection_id)).await,
+        Nat::from(1000)
+    );
+
+    let reply: Result<Nat, String> = c
+        .new_call("withdraw_proceeds")
+        .with_arg(WithdrawArgs {
+            from_subaccount: None,
+            to: None,
+        })
+        .with_caller(seller.owner)
+        .perform()
+        .await
+        .decode_one()
+        .unwrap();
+    assert!(reply.is_ok());
+
+    assert_eq!(proceeds_of(&c, &seller).await, Nat::from(0));
+    assert_eq!(balance_of(&ledger, &seller).await, Nat::from(890));
+}
+
+#[kit_test]
+async fn test_failed_payment(replica: Replica) {
+    let collection_id = Principal::from_slice(&[0xC, 0x1]);
+    let ledger_id = Principal::from_slice(&[0xC, 0x2]);
+
+    let seller = Account::from_owner(Principal::from_slice(&[0x1]));
+    let buyer = Account::from_owner(Principal::from_slice(&[0x2, 0x2]));
+
+    prepare_ledger(&replica, ledger_id, vec![(buyer.clone(), 500.into())]).await;
+    let c = prepare_collection(&replica, collection_id, ledger_id, &seller).await;
+
+    mint(&c, 1, &seller).await;
+
+    let reply: Result<(), String> = c
+        .new_call("list_token")
+        .with_arg(ListArgs {
+            token_id: 1.into(),
+            price: 1000.into(),
+            from_subaccount: None,
+        })
+        .with_caller(seller.owner)
+        .perform()
+        .await
+        .decode_one()
+        .unwrap();
+    assert_eq!(reply, Ok(()));
+
+    let reply: Result<TransferID, BuyError> = c
+        .new_call("buy_token")
+        .with_arg(BuyArgs {
+            token_id: 1.into(),
+            price: 1000.into(),
+            from_subaccount: None,
+            to: None,
+        })
+        .with_caller(buyer.owner)
+        .perform()
+        .await
+        .decode_one()
+        .unwrap();
+    assert_eq!(
+        reply,
+        Err(BuyError::PaymentFailed(
+            TransferFromError::InsufficientFunds {
+                balance: 500.into()
+            }
+        ))
+    );
+
+    // listing is available again and can be delisted by the seller
+    let listing: Option<Listing> = c
+        .new_call("get_listing")
+        .with_arg(Nat::from(1))
+        .perform()
+        .await
+        .decode_one()
+        .unwrap();
+    assert!(!listing.unwrap().pending);
+
+    let reply: Result<(), String> = c
+        .new_call("delist_token")
+        .with_arg(Nat::from(1))
+        .with_caller(seller.owner)
+        .perform()
+        .await
+        .decode_one()
+        .unwrap();
+    assert_eq!(reply, Ok(()));
+
+    let owner: Option<Account> = c
+        .new_call("icrc7_owner_of")
+        .with_arg(Nat::from(1))
+        .perform()
+        .await
+        .decode_one()
+        .unwrap();
+    assert_eq!(owner, Some(seller));
+}
+
+#[kit_test]
+async fn test_english_auction(replica: Replica) {
+    let collection_id = Principal::from_slice(&[0xC, 0x1]);
+    let ledger_id = Principal::from_slice(&[0xC, 0x2]);
+
+    let seller = Account::from_owner(Principal::from_slice(&[0x1]));
+    let alice = Account::from_owner(Principal::from_slice(&[0x2, 0x2]));
+    let bob = Account::from_owner(Principal::from_slice(&[0x3, 0x3, 0x3]));
+    let artist = Account::from_owner(Principal::from_slice(&[0x4]));
+
+    let balances = vec![(alice.clone(), 2000.into()), (bob.clone(), 2000.into())];
+    let ledger = prepare_ledger(&replica, ledger_id, balances).await;
+    let c = prepare_collection(&replica, collection_id, ledger_id, &artist).await;
+
+    mint(&c, 1, &seller).await;
+
+    let args = CreateAuctionArgs {
+        token_id: 1.into(),
+        from_subaccount: None,
+        kind: AuctionKind::English {
+            min_increment: 100.into(),
+        },
+        start_price: 1000.into(),
+        reserve_price: None,
+        starts_at: None,
+        ends_at: NOW + 100,
+    };
+    let env = Env::default()
+        .with_entry_mode(EntryMode::Update)
+        .with_method_name("create_auction")
+        .with_arg(args)
+        .with_time(NOW)
+        .with_sender(seller.owner);
+    let reply: Result<(), String> = c.run_env(env).await.decode_one().unwrap();
+    assert_eq!(reply, Ok(()));
+
+    let reply = perform_bid(&c, &alice, 1000, NOW + 10).await;
+    assert_eq!(reply, Ok(None));
+
+    let reply = perform_bid(&c, &bob, 1050, NOW + 20).await;
+    assert_eq!(
+        reply,
+        Err(BuyError::BidTooLow {
+            min_bid: 1100.into()
+        })
+    );
+
+    let reply = perform_bid(&c, &bob, 1100, NOW + 20).await;
+    assert_eq!(reply, Ok(None));
+
+    // outbid bidder can withdraw the refund
+    assert_eq!(proceeds_of(&c, &alice).await, Nat::from(1000));
+
+    let env = Env::default()
+        .with_entry_mode(EntryMode::Heartbeat)
+        .with_time(NOW + 100);
+    c.run_env(env).await;
+
+    let owner: Option<Account> = c
+        .new_call("icrc7_owner_of")
+        .with_arg(Nat::from(1))
+        .perform()
+        .await
+        .decode_one()
+        .unwrap();
+    assert_eq!(owner, Some(bob.clone()));
+
+    assert_eq!(proceeds_of(&c, &seller).await, Nat::from(990));
+    assert_eq!(proceeds_of(&c, &artist).await, Nat::from(110));
+    assert_eq!(balance_of(&ledger, &bob).await, Nat::from(890));
+}
+
+async fn perform_bid(
+    c: &CanisterHandle<'_>,
+    bidder: &Account,
+    amount: u64,
+    time: u64,
+) -> Result<Option<TransferID>, BuyError> {
+    let args = BidArgs {
+        token_id: 1.into(),
+        amount: amount.into(),
+        from_subaccount: None,
+        to: None,
+    };
+    let env = Env::default()
+        .with_entry_mode(EntryMode::Update)
+        .with_method_name("place_bid")
+        .with_arg(args)
+        .with_time(time)
+        .with_sender(bidder.owner);
+
+    c.run_env(env).await.decode_one().unwrap()
+}
+
+async fn prepare_collection<'a>(
+    replica: &'a Replica,
+    id: Principal,
+    ledger: Principal,
+    royalty_recipient: &Account,
+) -> CanisterHandle<'a> {
+    let c = replica.add_canister(Icrc7Canister::build(id));
+
+    let args = InitArgs {
+        name: "test collection".to_owned(),
+        symbol: "TEST".to_owned(),
+        description: None,
+        royalties: 1000,
+        royalty_recipient: royalty_recipient.clone(),
+        royalty_recipients: None,
+        image: None,
+        supply_cap: None,
+        authority: Principal::anonymous(),
+        limits: None,
+        gc_interval: None,
+        gc_budget: None,
+        soulbound: None,
+        revocable: None,
+        tx_window: None,
+        permitted_drift: None,
+    };
+    let env = ic_kit_runtime::types::Env::init().with_arg(args);
+    c.run_env(env).await;
+
+    let reply: Result<(), String> = c
+        .new_call("set_payment_ledger")
+        .with_arg(ledger)
+        .perform()
+        .await
+        .decode_one()
+        .unwrap();
+    assert_eq!(reply, Ok(()));
+
+    c
+}
+
+async fn mint(c: &CanisterHandle<'_>, id: u64, owner: &Account) {
+    let resp: Result<TokenID, String> = c
+        .new_call("mint_token")
+        .with_arg(MintTokenArgs {
+            id: id.into(),
+            name: format!("NFT-{}", id),
+            image: "QUFBQQ".to_owned(),
+            owner: owner.clone(),
+            transferable: None,
+            locked_until: None,
+            royalties: None,
+            reveal_hash: None,
+        })
+        .perform()
+        .await
+        .decode_one()
+        .unwrap();
+    assert_eq!(resp, Ok(id.into()));
+}
+
+async fn proceeds_of(c: &CanisterHandle<'_>, account: &Account) -> Nat {
+    c.new_call("proceeds_of")
+        .with_arg(account.clone())
+        .perform()
+        .await
+        .decode_one()
+        .unwrap()
+}
@@ -0,0 +1,484 @@
+//! Model-based tests: random sequences of operations are applied both to `Collection`
+//! and to a simple reference model, after every step the two must agree and
+//! collection invariants must hold.
+
+extern crate icrc7;
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use ic_kit::prelude::*;
+use icrc7::state::*;
+use icrc7::*;
+use proptest::prelude::*;
+
+const AUTHORITY: u8 = 9;
+const SUPPLY_CAP: usize = 6;
+const MINUTE: u64 = 60 * 1_000_000_000;
+
+// far enough from zero, so windows never underflow
+const START: u64 = 10 * TX_DEDUPLICATION_WINDOW;
+
+fn principal(i: u8) -> Principal {
+    Principal::from_slice(&[i + 1])
+}
+
+/// 3 principals with 2 subaccounts each
+fn account(i: u8) -> Account {
+    let subaccount = if i % 2 == 1 { Some([1; 32]) } else { None };
+    Account::new(principal(i / 2), subaccount).to_canonical()
+}
+
+fn to_token_ids(ids: &HashSet<u8>) -> HashSet<TokenID> {
+    ids.iter().map(|&id| Nat::from(id)).collect()
+}
+
+#[derive(Debug, Clone)]
+enum Op {
+    Mint {
+        by_authority: bool,
+        id: u8,
+        owner: u8,
+    },
+    Approve {
+        caller: u8,
+        subaccount: bool,
+        spender: u8,
+        token_ids: Option<HashSet<u8>>,
+        expires_in: Option<u64>,
+    },
+    Transfer {
+        caller: u8,
+        from: Option<u8>,
+        to: u8,
+        token_ids: HashSet<u8>,
+        memo: Option<u8>,
+        created_at: Option<i64>,
+        is_atomic: Option<bool>,
+    },
+    /// submit previous transfer once again
+    Resubmit,
+    Advance(u64),
+    Gc,
+    /// incremental gc with given budget, run until it covers whole collection
+    IncrementalGc(usize),
+}
+
+fn arb_op() -> impl Strategy<Value = Op> {
+    let ids = || prop::collection::hash_set(0u8..8, 0..4);
+
+    prop_oneof![
+        2 => (prop::bool::weighted(0.9), 0u8..8, 0u8..6).prop_map(|(by_authority, id, owner)| {
+            Op::Mint {
+                by_authority,
+                id,
+                owner,
+            }
+        }),
+        2 => (
+            0u8..3,
+            any::<bool>(),
+            0u8..3,
+            prop::option::of(ids()),
+            prop::option::of(0..10 * MINUTE),
+        )
+            .prop_map(|(caller, subaccount, spender, token_ids, expires_in)| {
+                Op::Approve {
+                    caller,
+                    subaccount,
+                    spender,
+                    token_ids,
+                    expires_in,
+                }
+            }),
+        4 => (
+            0u8..3,
+            prop::option::of(0u8..6),
+            0u8..6,
+            ids(),
+            prop::option::of(0u8..2),
+            prop::option::of(-4i64..4),
+            prop::option::of(any::<bool>()),
+        )
+            .prop_map(|(caller, from, to, token_ids, memo, created_at, is_atomic)| {
+                Op::Transfer {
+                    caller,
+                    from,
+                    to,
+                    token_ids,
+                    memo,
+                    created_at,
+                    is_atomic,
+                }
+            }),
+        1 => Just(Op::Resubmit),
+        1 => prop_oneof![0..30 * MINUTE, Just(TX_DEDUPLICATION_WINDOW)].prop_map(Op::Advance),
+        1 => Just(Op::Gc),
+        1 => (1usize..5).prop_map(Op::IncrementalGc),
+    ]
+}
+
+struct ModelApproval {
+    from: Account,
+    spender: Principal,
+    token_ids: Option<HashSet<u8>>,
+    expires_at: Option<u64>,
+}
+
+struct ModelTransfer {
+    id: u64,
+    caller: Principal,
+    from: Account,
+    to: Account,
+    token_ids: HashSet<u8>,
+    memo: Option<Vec<u8>>,
+    created_at_time: Option<u64>,
+    is_atomic: bool,
+    created_at: u64,
+}
+
+#[derive(Debug, PartialEq)]
+enum Outcome {
+    Ok(u64),
+    Duplicate(u64),
+    Err,
+}
+
+impl From<Result<TransferID, TransferError>> for Outcome {
+    fn from(r: Result<TransferID, TransferError>) -> Self {
+        match r {
+            Ok(id) => Outcome::Ok(nat_to_u64(&id)),
+            Err(TransferError::Duplicate { duplicate_of }) => {
+                Outcome::Duplicate(nat_to_u64(&duplicate_of))
+            }
+            Err(_) => Outcome::Err,
+        }
+    }
+}
+
+fn nat_to_u64(n: &Nat) -> u64 {
+    u64::try_from(&n.0).unwrap()
+}
+
+/// straightforward reference implementation of the collection
+struct Model {
+    now: u64,
+    owners: BTreeMap<u8, Account>,
+    approvals: BTreeMap<u64, ModelApproval>,
+    approval_id_seq: u64,
+    transfers: Vec<ModelTransfer>,
+    transfer_id_seq: u64,
+}
+
+impl Model {
+    fn mint(&mut self, by_authority: bool, id: u8, owner: u8) -> bool {
+        if !by_authority || self.owners.len() == SUPPLY_CAP || self.owners.contains_key(&id) {
+            return false;
+        }
+
+        self.owners.insert(id, account(owner));
+        true
+    }
+
+    fn approve(
+        &mut self,
+        from: Account,
+        spender: Principal,
+        token_ids: &Option<HashSet<u8>>,
+        expires_at: Option<u64>,
+    ) -> Option<u64> {
+        if let Some(ids) = token_ids {
+            if !ids.iter().all(|id| self.owners.get(id) == Some(&from)) {
+                return None;
+            }
+        }
+
+        let id = self.approval_id_seq;
+        self.approval_id_seq += 1;
+
+        self.approvals.insert(
+            id,
+            ModelApproval {
+                from,
+                spender,
+                token_ids: token_ids.clone(),
+                expires_at,
+            },
+        );
+
+        Some(id)
+    }
+
+    fn is_approved(&self, from: &Account, spender: &Principal, token_id: u8) -> bool {
+        self.approvals.values().any(|a| {
+            a.from == *from
+                && a.spender == *spender
+                && a.token_ids
+                    .as_ref()
+                    .is_none_or(|ids| ids.contains(&token_id))
+                && a.expires_at.is_none_or(|e| e >= self.now)
+        })
+    }
+
+    fn transfer(&mut self, caller: Principal, args: &TransferArgs) -> Outcome {
+        if args.token_ids.is_empty() {
+            return Outcome::Err;
+        }
+
+        if let Some(created_at) = args.created_at_time {
+            if self.now > created_at + PERMITTED_TIME_DRIFT
+                || self.now + PERMITTED_TIME_DRIFT < created_at
+            {
+                return Outcome::Err;
+            }
+        }
+
+        let from = args
+            .from
+            .clone()
+            .unwrap_or(Account::from_owner(caller))
+            .to_canonical();
+        let to = args.to.to_canonical();
+        let ids: HashSet<u8> = args
+            .token_ids
+            .iter()
+            .map(|id| nat_to_u64(id) as u8)
+            .collect();
+        let created_at = args.created_at_time.unwrap_or(self.now);
+        let is_atomic = args.is_atomic.unwrap_or(true);
+
+        // only transfers with created_at_time are deduplicated
+        let duplicate = self.transfers.iter().find(|t| {
+            args.created_at_time.is_some()
+                && t.created_at_time == args.created_at_time
+                && t.created_at >= self.now - TX_DEDUPLICATION_WINDOW
+                && t.caller == caller
+                && t.from == from
+                && t.to == to
+                && t.token_ids == ids
+                && t.memo == args.memo
+                && t.is_atomic == is_atomic
+        });
+        if let Some(t) = duplicate {
+            return Outcome::Duplicate(t.id);
+        }
+
+        let allowed: Vec<u8> = ids
+            .iter()
+            .copied()
+            .filter(|id| {
+                self.owners.get(id) == Some(&from)
+                    && (from.owner == caller || self.is_approved(&from, &caller, *id))
+                    && from != to
+            })
+            .collect();
+
+        if is_atomic && allowed.len() != ids.len() {
+            return Outcome::Err;
+        }
+
+        for id in allowed {
+            self.owners.insert(id, to.clone());
+        }
+
+        let id = self.transfer_id_seq;
+        self.transfer_id_seq += 1;
+        self.transfers.push(ModelTransfer {
+            id,
+            caller,
+            from,
+            to,
+            token_ids: ids,
+            memo: args.memo.clone(),
+            created_at_time: args.created_at_time,
+            is_atomic,
+            created_at,
+        });
+
+        Outcome::Ok(id)
+    }
+
+    fn gc(&mut self) {
+        let now = self.now;
+        self.transfers
+            .retain(|t| t.created_at >= now - TX_DEDUPLICATION_WINDOW);
+        self.approvals
+            .retain(|_, a| a.expires_at.is_none_or(|e| e >= now));
+    }
+}
+
+fn check_invariants(c: &Collection, m: &Model) -> Result<(), TestCaseError> {
+    // ownership matches the model, every token has exactly one owner
+    prop_assert_eq!(c.tokens.len(), m.owners.len());
+    for (id, owner) in &m.owners {
+        let token = &c.tokens[&Nat::from(*id)];
+        prop_assert_eq!(&token.id, &Nat::from(*id));
+        prop_assert_eq!(&token.owner, owner);
+    }
+
+    prop_assert!(c.tokens.len() <= SUPPLY_CAP);
+
+    // approvals index is consistent with approvals
+    let mut indexed = HashSet::new();
+    for (key, ids) in &c.approvals_by_account {
+        prop_assert!(!ids.is_empty());
+        for id in ids {
+            prop_assert_eq!(&c.approvals[id].key(), key);
+            prop_assert!(indexed.insert(id.clone()), "approval indexed twice");
+        }
+    }
+    prop_assert_eq!(indexed.len(), c.approvals.len());
+
+    let mut counts: HashMap<Account, usize> = HashMap::new();
+    for a in c.approvals.values() {
+        *counts.entry(a.from_account()).or_default() += 1;
+    }
+    prop_assert_eq!(&counts, &c.approvals_count);
+    prop_assert_eq!(c.approvals.len(), m.approvals.len());
+
+    // deduplication log matches the model
+    prop_assert_eq!(c.transfers.len(), m.transfers.len());
+    for t in &m.transfers {
+        prop_assert!(c.transfers.contains_key(&(t.created_at, Nat::from(t.id))));
+    }
+
+    // deduplication index points only to transfers with matching hash
+    let hashed = c.transfers.values().filter(|t| t.hash.is_some()).count();
+    prop_assert_eq!(c.transfers_by_hash.len(), hashed);
+    for (hash, key) in &c.transfers_by_hash {
+        prop_assert_eq!(c.transfers[key].hash.as_ref(), Some(hash));
+    }
+
+    Ok(())
+}
+
+fn run(ops: Vec<Op>) -> Result<(), TestCaseError> {
+    let mut c = Collection {
+        authority: Some(principal(AUTHORITY)),
+        supply_cap: Some(SUPPLY_CAP),
+        ..Default::default()
+    };
+
+    let mut m = Model {
+        now: START,
+        owners: BTreeMap::new(),
+        approvals: BTreeMap::new(),
+        approval_id_seq: 0,
+        transfers: Vec::new(),
+        transfer_id_seq: 0,
+    };
+
+    let mut last_transfer: Option<(Principal, TransferArgs)> = None;
+
+    for op in ops {
+        match op {
+            Op::Mint {
+                by_authority,
+                id,
+                owner,
+            } => {
+                let caller = if by_authority {
+                    principal(AUTHORITY)
+                } else {
+                    principal(0)
+                };
+
+                let args = MintTokenArgs {
+                    id: id.into(),
+                    name: format!("NFT-{}", id),
+                    image: "QUFBQQ".to_owned(),
+                    owner: account(owner),
+                    transferable: None,
+                    locked_until: None,
+                    royalties: None,
+                    reveal_hash: None,
+                };
+
+                let res = mint(&mut c, caller, m.now, args);
+                prop_assert_eq!(res.is_ok(), m.mint(by_authority, id, owner));
+            }
+            Op::Approve {
+                caller,
+                subaccount,
+                spender,
+                token_ids,
+                expires_in,
+            } => {
+                let from = account(caller * 2 + subaccount as u8);
+                let expires_at = expires_in.map(|e| m.now + e);
+                let args = ApproveArgs {
+                    from_subaccount: from.subaccount,
+                    to: principal(spender),
+                    token_ids: token_ids.as_ref().map(to_token_ids),
+                    expires_at,
+                    memo: None,
+                    created_at: None,
+                };
+
+                let res = approve(&mut c, principal(caller), m.now, args);
+                let expected = m.approve(from, principal(spender), &token_ids, expires_at);
+                prop_assert_eq!(res.ok().map(|id| nat_to_u64(&id)), expected);
+            }
+            Op::Transfer {
+                caller,
+                from,
+                to,
+                ref token_ids,
+                memo,
+                created_at,
+                is_atomic,
+            } => {
+                let args = TransferArgs {
+                    from: from.map(account),
+                    to: account(to),
+                    token_ids: to_token_ids(token_ids),
+                    memo: memo.map(|m| vec![m]),
+                    created_at_time: created_at.map(|d| (m.now as i64 + d * MINUTE as i64) as u64),
+                    is_atomic,
+                };
+
+                let res = transfer(&mut c, principal(caller), m.now, args.clone());
+                prop_assert_eq!(Outcome::from(res), m.transfer(principal(caller), &args));
+
+                last_transfer = Some((principal(caller), args));
+            }
+            Op::Resubmit => {
+                if let Some((caller, ref args)) = last_transfer {
+                    let res = transfer(&mut c, caller, m.now, args.clone());
+                    prop_assert_eq!(Outcome::from(res), m.transfer(caller, args));
+                }
+            }
+            Op::Advance(d) => m.now += d,
+            Op::Gc => {
+                c.gc(m.now);
+                m.gc();
+            }
+            Op::IncrementalGc(budget) => {
+                // each step examines `budget` entries
+                loop {
+                    let mut left = budget;
+                    let has_budget = || {
+                        left -= 1;
+                        left > 0
+                    };
+                    if c.gc_step(m.now, has_budget).done {
+                        break;
+                    }
+                }
+                m.gc();
+            }
+        }
+
+        check_invariants(&c, &m)?;
+    }
+
+    Ok(())
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(512))]
+
+    #[test]
+    fn test_collection_matches_model(ops in prop::collection::vec(arb_op(), 1..100)) {
+        run(ops)?;
+    }
+}
@@ -0,0 +1,292 @@
+extern crate icrc7;
+
+mod common;
+
+use ic_kit::prelude::*;
+use ic_kit_runtime::handle::CanisterHandle;
+use icrc7::allowlist::*;
+use icrc7::public_mint::*;
+use icrc7::random::*;
+use icrc7::state::*;
+use icrc7::*;
+use rt::types::{EntryMode, Env};
+
+use common::*;
+
+const NOW: u64 = 3600000000000; // 1 hour in nanoseconds
+
+#[kit_test]
+async fn test_public_mint(replica: Replica) {
+    let ledger_id = Principal::from_slice(&[0xC, 0x2]);
+    let treasury = Account::from_owner(Principal::from_slice(&[0x9]));
+    let minter = Account::from_owner(Principal::from_slice(&[0x1]));
+    let poor_minter = Account::from_owner(Principal::from_slice(&[0x2, 0x2]));
+
+    let balances = vec![
+        (minter.clone(), 1000.into()),
+        (poor_minter.clone(), 50.into()),
+    ];
+    let ledger = prepare_ledger(&replica, ledger_id, balances).await;
+    let c = prepare_collection(&replica, None).await;
+
+    let reply = perform_public_mint(&c, &minter, NOW, None).await;
+    assert_eq!(reply, Err("public mint is not enabled".to_owned()));
+
+    let config = PublicMintConfig {
+        ledger: ledger_id,
+        price: 100.into(),
+        treasury: treasury.clone(),
+        starts_at: Some(NOW),
+        ends_at: Some(NOW + 100),
+        max_per_wallet: Some(2),
+        random_ids: false,
+    };
+
+    // only authority can configure public mint
+    let reply: Result<(), String> = c
+        .new_call("set_public_mint")
+        .with_arg(Some(config.clone()))
+        .with_caller(minter.owner)
+        .perform()
+        .await
+        .decode_one()
+        .unwrap();
+    assert!(reply.is_err());
+
+    let reply: Result<(), String> = c
+        .new_call("set_public_mint")
+        .with_arg(Some(config))
+        .perform()
+        .await
+        .decode_one()
+        .unwrap();
+    assert_eq!(reply, Ok(()));
+
+    let reply = perform_public_mint(&c, &minter, NOW - 1, None).await;
+    assert_eq!(reply, Err("public mint has not started yet".to_owned()));
+
+    let reply = perform_public_mint(&c, &minter, NOW, None).await;
+    assert_eq!(reply, Ok(0.into()));
+    let reply = perform_public_mint(&c, &minter, NOW + 10, None).await;
+    assert_eq!(reply, Ok(1.into()));
+
+    let reply = perform_public_mint(&c, &minter, NOW + 20, None).await;
+    assert_eq!(reply, Err("mint limit per wallet reached".to_owned()));
+
+    // failed payment does not use up the limit
+    let reply = perform_public_mint(&c, &poor_minter, NOW + 20, None).await;
+    assert!(reply.is_err());
+    let minted: u32 = c
+        .new_call("public_mints_of")
+        .with_arg(poor_minter.owner)
+        .perform()
+        .await
+        .decode_one()
+        .unwrap();
+    assert_eq!(minted, 0);
+
+    let reply = perform_public_mint(&c, &poor_minter, NOW + 100, None).await;
+    assert_eq!(reply, Err("public mint has ended".to_owned()));
+
+    let owner: Option<Account> = c
+        .new_call("icrc7_owner_of")
+        .with_arg(Nat::from(1))
+        .perform()
+        .await
+        .decode_one()
+        .unwrap();
+    assert_eq!(owner, Some(minter.clone()));
+
+    assert_eq!(balance_of(&ledger, &treasury).await, Nat::from(200));
+    assert_eq!(balance_of(&ledger, &minter).await, Nat::from(780));
+}
+
+#[kit_test]
+async fn test_allowlist_phase(replica: Replica) {
+    let alice = Account::from_owner(Principal::from_slice(&[0x1]));
+    let bob = Account::from_owner(Principal::from_slice(&[0x2, 0x2]));
+    let eve = Account::from_owner(Principal::from_slice(&[0x3, 0x3, 0x3]));
+
+    let c = prepare_collection(&replica, None).await;
+
+    // presale is free, so no ledger is needed
+    let config = PublicMintConfig {
+        ledger: Principal::from_slice(&[0xC, 0x2]),
+        price: 100.into(),
+        treasury: Account::from_owner(Principal::from_slice(&[0x9])),
+        starts_at: Some(NOW + 100),
+        ends_at: None,
+        max_per_wallet: None,
+        random_ids: false,
+    };
+    let reply: Result<(), String> = c
+        .new_call("set_public_mint")
+        .with_arg(Some(config))
+        .perform()
+        .await
+        .decode_one()
+        .unwrap();
+    assert_eq!(reply, Ok(()));
+
+    let leaves = vec![
+        allowlist_leaf(&alice.owner, 2),
+        allowlist_leaf(&bob.owner, 1),
+    ];
+    let phase = MintPhase {
+        name: "presale".to_owned(),
+        merkle_root: merkle_root(&leaves),
+        starts_at: NOW,
+        ends_at: NOW + 100,
+        price: Some(0.into()),
+    };
+    let reply: Result<(), String> = c
+        .new_call("set_mint_phases")
+        .with_arg(vec![phase])
+        .perform()
+        .await
+        .decode_one()
+        .unwrap();
+    assert_eq!(reply, Ok(()));
+
+    let alice_proof = AllowlistProof {
+        quota: 2,
+        proof: merkle_proof(&leaves, 0),
+    };
+    let bob_proof = AllowlistProof {
+        quota: 1,
+        proof: merkle_proof(&leaves, 1),
+    };
+
+    let reply = perform_public_mint(&c, &alice, NOW, None).await;
+    assert_eq!(reply, Err("allowlist proof is required".to_owned()));
+
+    // eve is not on the allowlist and can't reuse someone else's proof
+    let reply = perform_public_mint(&c, &eve, NOW, Some(bob_proof.clone())).await;
+    assert_eq!(reply, Err("invalid allowlist proof".to_owned()));
+
+    // bob can't claim more than his quota
+    let forged = AllowlistProof {
+        quota: 2,
+        ..bob_proof.clone()
+    };
+    let reply = perform_public_mint(&c, &bob, NOW, Some(forged)).await;
+    assert_eq!(reply, Err("invalid allowlist proof".to_owned()));
+
+    let reply = perform_public_mint(&c, &bob, NOW, Some(bob_proof.clone())).await;
+    assert_eq!(reply, Ok(0.into()));
+    let reply = perform_public_mint(&c, &bob, NOW + 10, Some(bob_proof)).await;
+    assert_eq!(reply, Err("allowlist quota reached".to_owned()));
+
+    let reply = perform_public_mint(&c, &alice, NOW + 10, Some(alice_proof.clone())).await;
+    assert_eq!(reply, Ok(1.into()));
+    let reply = perform_public_mint(&c, &alice, NOW + 20, Some(alice_proof)).await;
+    assert_eq!(reply, Ok(2.into()));
+
+    let claimed: u32 = c
+        .new_call("allowlist_claimed")
+        .with_args(("presale".to_owned(), alice.owner))
+        .perform()
+        .await
+        .decode_one()
+        .unwrap();
+    assert_eq!(claimed, 2);
+}
+
+#[kit_test]
+async fn test_random_public_mint(replica: Replica) {
+    let minter = Account::from_owner(Principal::from_slice(&[0x1]));
+
+    let c = prepare_collection(&replica, Some(5)).await;
+
+    let config = PublicMintConfig {
+        ledger: Principal::from_slice(&[0xC, 0x2]),
+        price: 0.into(),
+        treasury: Account::from_owner(Principal::from_slice(&[0x9])),
+        starts_at: None,
+        ends_at: None,
+        max_per_wallet: None,
+        random_ids: true,
+    };
+    let reply: Result<(), String> = c
+        .new_call("set_public_mint")
+        .with_arg(Some(config))
+        .perform()
+        .await
+        .decode_one()
+        .unwrap();
+    assert_eq!(reply, Ok(()));
+
+    let pool: Vec<_> = (0..5u64).map(Nat::from).collect();
+    let mut ids = vec![];
+    for i in 0..5 {
+        let id = perform_public_mint(&c, &minter, NOW + i, None)
+            .await
+            .unwrap();
+        assert!(pool.contains(&id));
+        assert!(!ids.contains(&id));
+        ids.push(id);
+    }
+
+    let reply = perform_public_mint(&c, &minter, NOW + 5, None).await;
+    assert_eq!(reply, Err("supply cap reached".to_owned()));
+
+    // every draw is logged along with the seed it was made with
+    let draws: Vec<RandomDraw> = c
+        .new_call("random_draws")
+        .perform()
+        .await
+        .decode_one()
+        .unwrap();
+    assert_eq!(draws.iter().map(|d| d.id.clone()).collect::<Vec<_>>(), ids);
+    assert!(draws
+        .iter()
+        .all(|d| d.seed.len() == 32 && d.minter == minter.owner));
+}
+
+async fn perform_public_mint(
+    c: &CanisterHandle<'_>,
+    minter: &Account,
+    time: u64,
+    proof: Option<AllowlistProof>,
+) -> Result<TokenID, String> {
+    let args = PublicMintArgs {
+        from_subaccount: None,
+        to: None,
+        proof,
+    };
+    let env = Env::default()
+        .with_entry_mode(EntryMode::Update)
+        .with_method_name("public_mint")
+        .with_arg(args)
+        .with_time(time)
+        .with_sender(minter.owner);
+
+    c.run_env(env).await.decode_one().unwrap()
+}
+
+async fn prepare_collection(replica: &Replica, supply_cap: Option<usize>) -> CanisterHandle<'_> {
+    let c = replica.add_canister(Icrc7Canister::anonymous());
+
+    let args = InitArgs {
+        name: "test collection".to_owned(),
+        symbol: "TEST".to_owned(),
+        description: None,
+        royalties: 0,
+        royalty_recipient: Account::default(),
+        royalty_recipients: None,
+        image: None,
+        supply_cap,
+        authority: Principal::anonymous(),
+        limits: None,
+        gc_interval: None,
+        gc_budget: None,
+        soulbound: None,
+        revocable: None,
+        tx_window: None,
+        permitted_drift: None,
+    };
+    let env = Env::init().with_arg(args);
+    c.run_env(env).await;
+
+    c
+}
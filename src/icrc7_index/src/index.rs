@@ -0,0 +1,324 @@
+use std::collections::{BTreeSet, HashMap};
+
+use ic_kit::prelude::*;
+
+use crate::types::*;
+
+/// max number of transactions returned by a single history call
+pub const MAX_PAGE: usize = 1000;
+
+/// heartbeat polls the collection at most this often by default, in nanoseconds
+pub const DEFAULT_SYNC_INTERVAL: u64 = 2_000_000_000;
+
+/// copy of the collection transaction log with indexes built from it
+#[derive(Debug, Clone, Default, Deserialize, Serialize, CandidType)]
+pub struct Index {
+    /// collection canister the log is fetched from
+    pub ledger_id: Option<Principal>,
+    /// min time between polls started by heartbeat, in nanoseconds
+    pub sync_interval: u64,
+    /// time the last poll finished at
+    pub last_sync_at: u64,
+    /// set while request to the collection is in flight
+    pub syncing: bool,
+    pub last_error: Option<String>,
+
+    /// transaction ID is its position in the log, same as in the collection
+    pub transactions: Vec<Transaction>,
+    /// IDs of transactions of every canonical account, ascending
+    pub by_account: HashMap<Account, Vec<TxID>>,
+    /// IDs of transactions mentioning every token, ascending
+    pub by_token: HashMap<TokenID, Vec<TxID>>,
+    /// current owners of tokens that were not burned
+    pub owners: HashMap<TokenID, Account>,
+    pub tokens_by_account: HashMap<Account, BTreeSet<TokenID>>,
+}
+
+impl Transaction {
+    /// canonical accounts the transaction shows up for, spenders are indexed by default account
+    pub fn accounts(&self) -> Vec<Account> {
+        let mut accounts = vec![];
+
+        if let Some(ref tx) = self.mint {
+            accounts.push(tx.to.clone());
+        }
+        if let Some(ref tx) = self.burn {
+            accounts.push(tx.from.clone());
+        }
+        if let Some(ref tx) = self.transfer {
+            accounts.push(tx.from.clone());
+            accounts.push(tx.to.clone());
+            accounts.extend(tx.spender.map(Account::from_owner));
+        }
+        if let Some(ref tx) = self.approve {
+            accounts.push(tx.from.clone());
+            accounts.push(Account::from_owner(tx.spender));
+        }
+
+        let mut accounts: Vec<_> = accounts.iter().map(Account::to_canonical).collect();
+        accounts.sort();
+        accounts.dedup();
+        accounts
+    }
+
+    /// tokens the transaction is about, approvals of all tokens don't mention any
+    pub fn token_ids(&self) -> Vec<TokenID> {
+        let mut ids = vec![];
+
+        if let Some(ref tx) = self.mint {
+            ids.push(tx.token_id.clone());
+        }
+        if let Some(ref tx) = self.burn {
+            ids.push(tx.token_id.clone());
+        }
+        if let Some(ref tx) = self.transfer {
+            ids.extend(tx.token_ids.iter().cloned());
+        }
+        if let Some(ref tx) = self.approve {
+            ids.extend(tx.token_ids.iter().flatten().cloned());
+        }
+
+        ids.sort();
+        ids.dedup();
+        ids
+    }
+}
+
+/// the newest `max_results` IDs older than `start`
+fn page<'a>(ids: &'a [TxID], start: Option<Nat>, max_results: &Nat) -> &'a [TxID] {
+    let max_results =
+        u64::try_from(&max_results.0).map_or(MAX_PAGE, |max| (max as usize).min(MAX_PAGE));
+
+    let end = match start {
+        Some(start) => {
+            let start = u64::try_from(&start.0).unwrap_or(TxID::MAX);
+            ids.partition_point(|id| *id < start)
+        }
+        None => ids.len(),
+    };
+
+    &ids[end.saturating_sub(max_results)..end]
+}
+
+impl Index {
+    /// adds the next transaction of the log and updates all indexes
+    pub fn append(&mut self, tx: Transaction) -> TxID {
+        let id = self.transactions.len() as TxID;
+
+        for account in tx.accounts() {
+            self.by_account.entry(account).or_default().push(id);
+        }
+        for token_id in tx.token_ids() {
+            self.by_token.entry(token_id).or_default().push(id);
+        }
+
+        if let Some(ref mint) = tx.mint {
+            self.set_owner(&mint.token_id, Some(mint.to.to_canonical()));
+        }
+        if let Some(ref transfer) = tx.transfer {
+            for token_id in &transfer.token_ids {
+                self.set_owner(token_id, Some(transfer.to.to_canonical()));
+            }
+        }
+        if let Some(ref burn) = tx.burn {
+            self.set_owner(&burn.token_id, None);
+        }
+
+        self.transactions.push(tx);
+
+        id
+    }
+
+    fn set_owner(&mut self, token_id: &TokenID, owner: Option<Account>) {
+        if let Some(prev) = self.owners.remove(token_id) {
+            if let Some(tokens) = self.tokens_by_account.get_mut(&prev) {
+                tokens.remove(token_id);
+                if tokens.is_empty() {
+                    self.tokens_by_account.remove(&prev);
+                }
+            }
+        }
+
+        if let Some(owner) = owner {
+            self.tokens_by_account
+                .entry(owner.clone())
+                .or_default()
+                .insert(token_id.clone());
+            self.owners.insert(token_id.clone(), owner);
+        }
+    }
+
+    /// appends transactions fetched from the collection, returns number of new ones
+    pub fn apply(&mut self, transactions: Vec<TransactionWithId>) -> Result<u64, String> {
+        let mut appended = 0;
+
+        for tx in transactions {
+            let next = Nat::from(self.transactions.len());
+            if tx.id < next {
+                // already indexed
+                continue;
+            }
+            if tx.id > next {
+                return Err(format!("expected transaction {}, got {}", next, tx.id));
+            }
+
+            self.append(tx.transaction);
+            appended += 1;
+        }
+
+        Ok(appended)
+    }
+
+    pub fn account_transactions(&self, args: GetAccountTransactionsArgs) -> GetTransactionsResult {
+        let account = args.account.to_canonical();
+        let ids = self
+            .by_account
+            .get(&account)
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+
+        let balance = self
+            .tokens_by_account
+            .get(&account)
+            .map_or(0, BTreeSet::len);
+
+        Ok(GetTransactions {
+            balance: balance.into(),
+            transactions: self.with_ids(page(ids, args.start, &args.max_results)),
+            oldest_tx_id: ids.first().map(|id| (*id).into()),
+        })
+    }
+
+    pub fn token_transactions(&self, args: GetTokenTransactionsArgs) -> Vec<TransactionWithId> {
+        let ids = self
+            .by_token
+            .get(&args.token_id)
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+
+        self.with_ids(page(ids, args.start, &args.max_results))
+    }
+
+    pub fn tokens_of(&self, account: &Account) -> Vec<TokenID> {
+        self.tokens_by_account
+            .get(&account.to_canonical())
+            .map(|tokens| tokens.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// transactions with given IDs, newest first
+    fn with_ids(&self, ids: &[TxID]) -> Vec<TransactionWithId> {
+        ids.iter()
+            .rev()
+            .map(|id| TransactionWithId {
+                id: (*id).into(),
+                transaction: self.transactions[*id as usize].clone(),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn account(id: u8) -> Account {
+        Account::from_owner(Principal::from_slice(&[id]))
+    }
+
+    fn tx(kind: &str) -> Transaction {
+        Transaction {
+            kind: kind.to_owned(),
+            mint: None,
+            burn: None,
+            transfer: None,
+            approve: None,
+            timestamp: 0,
+        }
+    }
+
+    fn mint(id: u64, to: &Account) -> Transaction {
+        Transaction {
+            mint: Some(MintTx {
+                to: to.clone(),
+                token_id: id.into(),
+            }),
+            ..tx("mint")
+        }
+    }
+
+    fn transfer(ids: &[u64], from: &Account, to: &Account) -> Transaction {
+        Transaction {
+            transfer: Some(TransferTx {
+                from: from.clone(),
+                to: to.clone(),
+                token_ids: ids.iter().map(|id| Nat::from(*id)).collect(),
+                memo: None,
+                spender: None,
+                transfer_id: 0.into(),
+            }),
+            ..tx("transfer")
+        }
+    }
+
+    fn ids(txs: &[TransactionWithId]) -> Vec<Nat> {
+        txs.iter().map(|tx| tx.id.clone()).collect()
+    }
+
+    #[test]
+    fn test_index() {
+        let alice = account(1);
+        let bob = account(2);
+
+        let mut index = Index::default();
+        let log = vec![
+            mint(1, &alice),
+            mint(2, &alice),
+            transfer(&[1, 2], &alice, &bob),
+            Transaction {
+                burn: Some(BurnTx {
+                    from: bob.clone(),
+                    token_id: 2.into(),
+                }),
+                ..tx("burn")
+            },
+            transfer(&[1], &bob, &alice),
+        ];
+        let log: Vec<_> = log
+            .into_iter()
+            .enumerate()
+            .map(|(id, transaction)| TransactionWithId {
+                id: id.into(),
+                transaction,
+            })
+            .collect();
+
+        assert_eq!(index.apply(log[..3].to_vec()), Ok(3));
+        // overlapping batches are fine, gaps are not
+        assert!(index.apply(log[4..].to_vec()).is_err());
+        assert_eq!(index.apply(log[1..].to_vec()), Ok(2));
+
+        assert_eq!(index.tokens_of(&alice), vec![Nat::from(1)]);
+        assert!(index.tokens_of(&bob).is_empty());
+        assert!(!index.tokens_by_account.contains_key(&bob));
+
+        let args = |start: Option<u64>| GetAccountTransactionsArgs {
+            account: alice.clone(),
+            start: start.map(Into::into),
+            max_results: 2.into(),
+        };
+        let resp = index.account_transactions(args(None)).unwrap();
+        assert_eq!(resp.balance, Nat::from(1));
+        assert_eq!(resp.oldest_tx_id, Some(0.into()));
+        assert_eq!(ids(&resp.transactions), vec![Nat::from(4), Nat::from(2)]);
+        let resp = index.account_transactions(args(Some(2))).unwrap();
+        assert_eq!(ids(&resp.transactions), vec![Nat::from(1), Nat::from(0)]);
+
+        let resp = index.token_transactions(GetTokenTransactionsArgs {
+            token_id: 2.into(),
+            start: None,
+            max_results: 10.into(),
+        });
+        assert_eq!(ids(&resp), vec![Nat::from(3), Nat::from(2), Nat::from(1)]);
+    }
+}
@@ -0,0 +1,108 @@
+pub mod types;
+use crate::types::*;
+
+pub mod index;
+use crate::index::*;
+
+pub mod sync;
+
+use ic_kit::prelude::*;
+
+#[derive(Debug, Deserialize, Serialize, CandidType)]
+pub struct InitArgs {
+    /// collection canister to index
+    pub ledger_id: Principal,
+    /// min time between polls of the collection in nanoseconds, 2 seconds if not set
+    pub sync_interval: Option<u64>,
+}
+
+#[init]
+fn init(index: &mut Index, args: InitArgs) {
+    *index = Index {
+        ledger_id: Some(args.ledger_id),
+        sync_interval: args.sync_interval.unwrap_or(DEFAULT_SYNC_INTERVAL),
+        ..Default::default()
+    };
+}
+
+#[pre_upgrade]
+fn pre_upgrade(index: &mut Index) {
+    // poll in flight is lost on upgrade
+    index.syncing = false;
+
+    #[allow(deprecated)]
+    ic_kit::stable::stable_store((index,)).expect("save state");
+}
+
+#[post_upgrade]
+fn post_upgrade(index: &mut Index) {
+    #[allow(deprecated)]
+    let (saved_index,): (Index,) = ic_kit::stable::stable_restore().expect("restore state");
+
+    *index = saved_index;
+}
+
+#[heartbeat]
+fn heartbeat() {
+    // state must not be borrowed while spawning, sync starts running right away and updates it
+    if ic::with(|index: &Index| index.should_sync(ic::time())) {
+        ic::spawn(async {
+            let _ = sync::sync().await;
+        });
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Deserialize, Serialize, CandidType)]
+pub struct Status {
+    pub num_transactions_synced: Nat,
+    pub last_sync_at: u64,
+    pub last_error: Option<String>,
+}
+
+#[query]
+fn ledger_id(index: &Index) -> Option<Principal> {
+    index.ledger_id
+}
+
+#[query]
+fn status(index: &Index) -> Status {
+    Status {
+        num_transactions_synced: index.transactions.len().into(),
+        last_sync_at: index.last_sync_at,
+        last_error: index.last_error.clone(),
+    }
+}
+
+/// transactions of the account newest first, mirrors ICRC-1 index canister interface
+#[query]
+fn get_account_transactions(
+    index: &Index,
+    args: GetAccountTransactionsArgs,
+) -> GetTransactionsResult {
+    index.account_transactions(args)
+}
+
+/// transactions mentioning the token newest first
+#[query]
+fn get_token_transactions(index: &Index, args: GetTokenTransactionsArgs) -> Vec<TransactionWithId> {
+    index.token_transactions(args)
+}
+
+#[query]
+fn icrc7_owner_of(index: &Index, token_id: TokenID) -> Option<Account> {
+    index.owners.get(&token_id).cloned()
+}
+
+#[query]
+fn icrc7_balance_of(index: &Index, account: Account) -> Nat {
+    index.tokens_of(&account).len().into()
+}
+
+#[query]
+fn icrc7_tokens_of(index: &Index, account: Account) -> Vec<TokenID> {
+    index.tokens_of(&account)
+}
+
+#[derive(KitCanister)]
+#[candid_path("icrc7_index.did")]
+pub struct Icrc7IndexCanister;
@@ -0,0 +1,121 @@
+use ic_kit::prelude::*;
+
+use crate::index::*;
+use crate::types::*;
+
+/// number of transactions requested from the collection at once, matches its page limit
+pub const SYNC_BATCH: u64 = 1000;
+
+impl Index {
+    /// heartbeat starts a poll if none is running and the interval has passed
+    pub fn should_sync(&self, now: u64) -> bool {
+        self.ledger_id.is_some()
+            && !self.syncing
+            && now >= self.last_sync_at.saturating_add(self.sync_interval)
+    }
+
+    /// marks poll as started, returns collection to fetch from and ID of the first missing transaction
+    pub fn begin_sync(&mut self) -> Result<(Principal, TxID), String> {
+        let ledger_id = match self.ledger_id {
+            Some(id) => id,
+            None => return Err("ledger is not set".to_owned()),
+        };
+
+        if self.syncing {
+            return Err("sync is already in progress".to_owned());
+        }
+
+        self.syncing = true;
+        Ok((ledger_id, self.transactions.len() as TxID))
+    }
+
+    /// applies fetched batch, returns number of new transactions and whether the log is caught up
+    pub fn finish_sync(
+        &mut self,
+        now: u64,
+        resp: Result<GetTransactionsResponse, String>,
+    ) -> Result<(u64, bool), String> {
+        self.syncing = false;
+        self.last_sync_at = now;
+
+        let result = resp.and_then(|resp| {
+            let appended = self.apply(resp.transactions)?;
+            let log_length = u64::try_from(&resp.log_length.0).unwrap_or(u64::MAX);
+            let caught_up = appended == 0 || self.transactions.len() as u64 >= log_length;
+            Ok((appended, caught_up))
+        });
+
+        self.last_error = result.as_ref().err().cloned();
+        result
+    }
+}
+
+/// fetches transactions from the collection until index is caught up, returns number of new ones
+pub async fn sync() -> Result<u64, String> {
+    let mut synced = 0;
+
+    loop {
+        let (ledger_id, start) = ic::with_mut(|index: &mut Index| index.begin_sync())?;
+
+        let resp = CallBuilder::new(ledger_id, "get_transactions")
+            .with_arg(GetTransactionsRequest {
+                start: start.into(),
+                length: SYNC_BATCH.into(),
+            })
+            .perform_one::<GetTransactionsResponse>()
+            .await
+            .map_err(|e| format!("collection call failed: {:?}", e));
+
+        let (appended, caught_up) =
+            ic::with_mut(|index: &mut Index| index.finish_sync(ic::time(), resp))?;
+        synced += appended;
+
+        if caught_up {
+            return Ok(synced);
+        }
+    }
+}
+
+/// catches up with the collection right away instead of waiting for heartbeat
+#[update]
+pub async fn sync_now() -> Result<Nat, String> {
+    sync().await.map(Into::into)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sync_state() {
+        let mut index = Index {
+            sync_interval: 10,
+            ..Default::default()
+        };
+        assert!(!index.should_sync(100));
+        assert!(index.begin_sync().is_err());
+
+        let ledger_id = Principal::from_slice(&[0x1]);
+        index.ledger_id = Some(ledger_id);
+        assert!(index.should_sync(100));
+        assert_eq!(index.begin_sync(), Ok((ledger_id, 0)));
+
+        // only one poll at a time
+        assert!(!index.should_sync(100));
+        assert!(index.begin_sync().is_err());
+
+        let resp = Err("collection call failed".to_owned());
+        assert!(index.finish_sync(100, resp).is_err());
+        assert_eq!(index.last_error, Some("collection call failed".to_owned()));
+        assert!(!index.should_sync(105));
+        assert!(index.should_sync(110));
+
+        index.begin_sync().unwrap();
+        let resp = GetTransactionsResponse {
+            log_length: 0.into(),
+            transactions: vec![],
+        };
+        assert_eq!(index.finish_sync(110, Ok(resp)), Ok((0, true)));
+        assert_eq!(index.last_error, None);
+    }
+}
@@ -0,0 +1,133 @@
+//! Candid types shared with the collection canister.
+//!
+//! They are copied rather than imported from `icrc7`, since linking it would pull all collection
+//! entrypoints into the index wasm.
+
+use ic_kit::prelude::*;
+
+pub type TokenID = Nat;
+pub type TxID = u64;
+
+pub type Subaccount = [u8; 32];
+
+#[derive(
+    Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Deserialize, Serialize, CandidType,
+)]
+pub struct Account {
+    pub owner: Principal,
+    pub subaccount: Option<Subaccount>,
+}
+
+impl Account {
+    pub fn from_owner(owner: Principal) -> Self {
+        Account {
+            owner,
+            subaccount: None,
+        }
+        .to_canonical()
+    }
+
+    /// fills missing subaccount with zeros, same as the collection does
+    pub fn to_canonical(&self) -> Self {
+        Account {
+            owner: self.owner,
+            subaccount: Some(self.subaccount.unwrap_or([0u8; 32])),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, CandidType)]
+pub struct MintTx {
+    pub to: Account,
+    pub token_id: TokenID,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, CandidType)]
+pub struct BurnTx {
+    pub from: Account,
+    pub token_id: TokenID,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, CandidType)]
+pub struct TransferTx {
+    pub from: Account,
+    pub to: Account,
+    pub token_ids: Vec<TokenID>,
+    pub memo: Option<Vec<u8>>,
+    pub spender: Option<Principal>,
+    pub transfer_id: Nat,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, CandidType)]
+pub struct ApproveTx {
+    pub from: Account,
+    pub spender: Principal,
+    pub token_ids: Option<Vec<TokenID>>,
+    pub expires_at: Option<u64>,
+    pub memo: Option<Vec<u8>>,
+    pub approval_id: Nat,
+}
+
+/// exactly one of the operations is set, `kind` names it
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, CandidType)]
+pub struct Transaction {
+    pub kind: String,
+    pub mint: Option<MintTx>,
+    pub burn: Option<BurnTx>,
+    pub transfer: Option<TransferTx>,
+    pub approve: Option<ApproveTx>,
+    pub timestamp: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, CandidType)]
+pub struct TransactionWithId {
+    pub id: Nat,
+    pub transaction: Transaction,
+}
+
+/// arguments for the collection "get_transactions" method
+#[derive(Debug, Clone, Deserialize, Serialize, CandidType)]
+pub struct GetTransactionsRequest {
+    pub start: Nat,
+    pub length: Nat,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, CandidType)]
+pub struct GetTransactionsResponse {
+    pub log_length: Nat,
+    pub transactions: Vec<TransactionWithId>,
+}
+
+/// arguments for the "get_account_transactions" method
+#[derive(Debug, Clone, Deserialize, Serialize, CandidType)]
+pub struct GetAccountTransactionsArgs {
+    pub account: Account,
+    /// ID of the last transaction seen by the client, the most recent ones are returned if not set
+    pub start: Option<Nat>,
+    pub max_results: Nat,
+}
+
+/// arguments for the "get_token_transactions" method
+#[derive(Debug, Clone, Deserialize, Serialize, CandidType)]
+pub struct GetTokenTransactionsArgs {
+    pub token_id: TokenID,
+    /// ID of the last transaction seen by the client, the most recent ones are returned if not set
+    pub start: Option<Nat>,
+    pub max_results: Nat,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, CandidType)]
+pub struct GetTransactions {
+    /// number of tokens held by the account
+    pub balance: Nat,
+    /// newest first
+    pub transactions: Vec<TransactionWithId>,
+    pub oldest_tx_id: Option<Nat>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, CandidType)]
+pub struct GetTransactionsErr {
+    pub message: String,
+}
+
+pub type GetTransactionsResult = Result<GetTransactions, GetTransactionsErr>;
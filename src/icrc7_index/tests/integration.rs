@@ -0,0 +1,216 @@
+extern crate icrc7;
+extern crate icrc7_index;
+
+use std::collections::HashSet;
+
+use ic_kit::prelude::*;
+use ic_kit_runtime::handle::CanisterHandle;
+use icrc7::state::*;
+use icrc7::*;
+use icrc7_index::types::{
+    GetAccountTransactionsArgs, GetTokenTransactionsArgs, GetTransactionsResult, TransactionWithId,
+};
+use icrc7_index::{Icrc7IndexCanister, Status};
+
+#[kit_test]
+async fn test_index_sync(replica: Replica) {
+    let collection_id = Principal::from_slice(&[0xC, 0x1]);
+    let c = prepare_collection(&replica, collection_id).await;
+    let index = prepare_index(&replica, collection_id).await;
+
+    let authority = Account::from_owner(Principal::anonymous());
+    let alice = Account::from_owner(Principal::from_slice(&[0x1]));
+
+    mint(&c, 1, &authority).await;
+    mint(&c, 2, &authority).await;
+    transfer(&c, &[1, 2], &authority, &alice, true).await;
+
+    assert_eq!(sync(&index).await, Ok(3.into()));
+    assert_eq!(tokens_of(&index, &alice).await, vec![1u64, 2]);
+    assert!(tokens_of(&index, &authority).await.is_empty());
+
+    // next sync only picks up new transactions
+    transfer(&c, &[2], &alice, &authority, true).await;
+    assert_eq!(sync(&index).await, Ok(1.into()));
+    assert_eq!(sync(&index).await, Ok(0.into()));
+    assert_eq!(tokens_of(&index, &alice).await, vec![1u64]);
+    assert_eq!(tokens_of(&index, &authority).await, vec![2u64]);
+
+    let resp: GetTransactionsResult = index
+        .new_call("get_account_transactions")
+        .with_arg(GetAccountTransactionsArgs {
+            account: to_index_account(&alice),
+            start: None,
+            max_results: 10.into(),
+        })
+        .perform()
+        .await
+        .decode_one()
+        .unwrap();
+    let resp = resp.unwrap();
+    assert_eq!(resp.balance, Nat::from(1));
+    assert_eq!(ids(&resp.transactions), vec![3u64, 2]);
+
+    let txs: Vec<TransactionWithId> = index
+        .new_call("get_token_transactions")
+        .with_arg(GetTokenTransactionsArgs {
+            token_id: 2.into(),
+            start: Some(3.into()),
+            max_results: 10.into(),
+        })
+        .perform()
+        .await
+        .decode_one()
+        .unwrap();
+    assert_eq!(ids(&txs), vec![2u64, 1]);
+    assert_eq!(txs[1].transaction.kind, "mint");
+
+    let status: Status = index
+        .new_call("status")
+        .perform()
+        .await
+        .decode_one()
+        .unwrap();
+    assert_eq!(status.num_transactions_synced, Nat::from(4));
+    assert_eq!(status.last_error, None);
+
+    // token 2 is already held by authority, so only token 1 moves and only it is indexed
+    transfer(&c, &[1, 2], &alice, &authority, false).await;
+    assert_eq!(sync(&index).await, Ok(1.into()));
+    assert!(tokens_of(&index, &alice).await.is_empty());
+    assert_eq!(tokens_of(&index, &authority).await, vec![1u64, 2]);
+
+    let txs: Vec<TransactionWithId> = index
+        .new_call("get_token_transactions")
+        .with_arg(GetTokenTransactionsArgs {
+            token_id: 2.into(),
+            start: None,
+            max_results: 10.into(),
+        })
+        .perform()
+        .await
+        .decode_one()
+        .unwrap();
+    assert_eq!(ids(&txs), vec![3u64, 2, 1]);
+}
+
+fn to_index_account(account: &Account) -> icrc7_index::types::Account {
+    icrc7_index::types::Account {
+        owner: account.owner,
+        subaccount: account.subaccount,
+    }
+}
+
+fn ids(txs: &[TransactionWithId]) -> Vec<u64> {
+    txs.iter()
+        .map(|tx| u64::try_from(&tx.id.0).unwrap())
+        .collect()
+}
+
+async fn sync(index: &CanisterHandle<'_>) -> Result<Nat, String> {
+    index
+        .new_call("sync_now")
+        .perform()
+        .await
+        .decode_one()
+        .unwrap()
+}
+
+async fn tokens_of(index: &CanisterHandle<'_>, account: &Account) -> Vec<u64> {
+    let tokens: Vec<TokenID> = index
+        .new_call("icrc7_tokens_of")
+        .with_arg(account.clone())
+        .perform()
+        .await
+        .decode_one()
+        .unwrap();
+    tokens
+        .iter()
+        .map(|id| u64::try_from(&id.0).unwrap())
+        .collect()
+}
+
+async fn mint(c: &CanisterHandle<'_>, id: u64, owner: &Account) {
+    let resp: Result<TokenID, String> = c
+        .new_call("mint_token")
+        .with_arg(MintTokenArgs {
+            id: id.into(),
+            name: format!("NFT-{}", id),
+            image: "QUFBQQ".to_owned(),
+            owner: owner.clone(),
+            transferable: None,
+            locked_until: None,
+            royalties: None,
+            reveal_hash: None,
+        })
+        .perform()
+        .await
+        .decode_one()
+        .unwrap();
+    assert_eq!(resp, Ok(id.into()));
+}
+
+async fn transfer(
+    c: &CanisterHandle<'_>,
+    ids: &[u64],
+    from: &Account,
+    to: &Account,
+    is_atomic: bool,
+) {
+    let resp: Result<TransferID, TransferError> = c
+        .new_call("icrc7_transfer")
+        .with_arg(TransferArgs {
+            from: None,
+            to: to.clone(),
+            token_ids: ids.iter().map(|id| Nat::from(*id)).collect::<HashSet<_>>(),
+            memo: None,
+            created_at_time: None,
+            is_atomic: Some(is_atomic),
+        })
+        .with_caller(from.owner)
+        .perform()
+        .await
+        .decode_one()
+        .unwrap();
+    assert!(resp.is_ok());
+}
+
+async fn prepare_collection(replica: &Replica, id: Principal) -> CanisterHandle<'_> {
+    let c = replica.add_canister(Icrc7Canister::build(id));
+
+    let args = InitArgs {
+        name: "test collection".to_owned(),
+        symbol: "TEST".to_owned(),
+        description: None,
+        royalties: 0,
+        royalty_recipient: Account::default(),
+        royalty_recipients: None,
+        image: None,
+        supply_cap: None,
+        authority: Principal::anonymous(),
+        limits: None,
+        gc_interval: None,
+        gc_budget: None,
+        soulbound: None,
+        revocable: None,
+        tx_window: None,
+        permitted_drift: None,
+    };
+    let env = ic_kit_runtime::types::Env::init().with_arg(args);
+    c.run_env(env).await;
+
+    c
+}
+
+async fn prepare_index(replica: &Replica, ledger_id: Principal) -> CanisterHandle<'_> {
+    let index = replica.add_canister(Icrc7IndexCanister::anonymous());
+
+    let args = icrc7_index::InitArgs {
+        ledger_id,
+        sync_interval: None,
+    };
+    let env = ic_kit_runtime::types::Env::init().with_arg(args);
+    index.run_env(env).await;
+
+    index
+}